@@ -0,0 +1,15 @@
+BEGIN;
+
+ALTER TABLE subscriptions
+    ADD COLUMN unsubscribe_token TEXT NULL;
+
+-- Existing subscribers get 25 random alphanumeric characters, like freshly generated tokens
+UPDATE subscriptions
+    SET unsubscribe_token = substr(md5(random()::text || id::text), 1, 25)
+    WHERE unsubscribe_token IS NULL;
+
+ALTER TABLE subscriptions
+    ALTER COLUMN unsubscribe_token SET NOT NULL,
+    ADD CONSTRAINT subscriptions_unsubscribe_token_key UNIQUE (unsubscribe_token);
+
+COMMIT;
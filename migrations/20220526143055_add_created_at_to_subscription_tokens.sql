@@ -0,0 +1,3 @@
+-- Tokens issued before this migration start their expiry window now
+ALTER TABLE subscription_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,10 @@
+-- Tokens are looked up by their hex-encoded SHA-256 digest instead of being stored in clear
+BEGIN;
+
+ALTER TABLE subscription_tokens
+    RENAME COLUMN subscription_token TO subscription_token_hash;
+
+UPDATE subscription_tokens
+    SET subscription_token_hash = encode(sha256(convert_to(subscription_token_hash, 'UTF8')), 'hex');
+
+COMMIT;
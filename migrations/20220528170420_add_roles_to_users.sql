@@ -0,0 +1,13 @@
+BEGIN;
+
+-- The existing admin keeps full access
+ALTER TABLE users
+    ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+        CHECK (role IN ('owner', 'editor', 'viewer')),
+    ADD COLUMN is_active BOOLEAN NOT NULL DEFAULT true;
+
+-- New users must be given a role explicitly
+ALTER TABLE users
+    ALTER COLUMN role DROP DEFAULT;
+
+COMMIT;
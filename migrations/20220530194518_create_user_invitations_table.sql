@@ -0,0 +1,10 @@
+CREATE TABLE user_invitations (
+    invitation_id uuid PRIMARY KEY,
+    email TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+    invited_by uuid NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    -- Set once the invitee has created their account, links can't be reused after that
+    accepted_at timestamptz NULL
+);
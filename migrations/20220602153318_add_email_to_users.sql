@@ -0,0 +1,3 @@
+-- Needed to email password reset links. Users created before invitations have none on file.
+ALTER TABLE users
+    ADD COLUMN email TEXT NULL UNIQUE;
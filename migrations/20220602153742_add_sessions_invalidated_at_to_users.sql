@@ -0,0 +1,3 @@
+-- Sessions that were started before this point in time are no longer accepted
+ALTER TABLE users
+    ADD COLUMN sessions_invalidated_at timestamptz NULL;
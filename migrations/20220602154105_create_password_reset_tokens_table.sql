@@ -0,0 +1,8 @@
+CREATE TABLE password_reset_tokens (
+    -- Hex-encoded SHA-256 digest, the token itself is only ever sent by email
+    token_hash TEXT PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz NULL
+);
@@ -0,0 +1,5 @@
+ALTER TABLE users
+    -- Base32-encoded, set once the user has confirmed their authenticator
+    ADD COLUMN totp_secret TEXT NULL,
+    -- The last time step a code was accepted for, so that a code can't be replayed
+    ADD COLUMN totp_last_step BIGINT NULL;
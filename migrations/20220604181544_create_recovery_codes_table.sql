@@ -0,0 +1,8 @@
+CREATE TABLE recovery_codes (
+    recovery_code_id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    -- Argon2 PHC string, recovery codes are as good as a password
+    code_hash TEXT NOT NULL,
+    used_at timestamptz NULL
+);
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
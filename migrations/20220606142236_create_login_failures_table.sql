@@ -0,0 +1,10 @@
+CREATE TABLE login_failures (
+    scope TEXT NOT NULL CHECK (scope IN ('username', 'ip')),
+    -- The username or IP address, depending on the scope
+    subject TEXT NOT NULL,
+    -- Failures since the last lockout, reset once `last_failed_at` is out of the window
+    failed_attempts INT NOT NULL,
+    last_failed_at timestamptz NOT NULL,
+    locked_until timestamptz NULL,
+    PRIMARY KEY (scope, subject)
+);
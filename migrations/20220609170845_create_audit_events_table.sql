@@ -0,0 +1,12 @@
+CREATE TABLE audit_events (
+    audit_event_id uuid PRIMARY KEY,
+    occurred_at timestamptz NOT NULL,
+    -- Missing when nobody was logged in, e.g. failed logins and subscribers' own changes
+    user_id uuid NULL REFERENCES users (user_id),
+    action TEXT NOT NULL,
+    client_ip TEXT NOT NULL,
+    details jsonb NOT NULL
+);
+
+CREATE INDEX audit_events_occurred_at_idx ON audit_events (occurred_at DESC);
+CREATE INDEX audit_events_user_id_idx ON audit_events (user_id, occurred_at DESC);
@@ -0,0 +1,19 @@
+-- Issues used to be sent as soon as they were stored. Existing ones count as sent, with an
+-- unknown author and recipient count.
+ALTER TABLE newsletter_issues
+    ADD status TEXT NOT NULL DEFAULT 'sent'
+        CHECK (status IN ('draft', 'scheduled', 'sending', 'sent')),
+    ADD author_id uuid NULL REFERENCES users (user_id),
+    ADD created_at timestamptz NULL,
+    ADD updated_at timestamptz NULL,
+    ADD n_recipients INT NULL,
+    ALTER published_at DROP NOT NULL;
+
+UPDATE newsletter_issues SET created_at = published_at, updated_at = published_at;
+
+ALTER TABLE newsletter_issues
+    ALTER status DROP DEFAULT,
+    ALTER created_at SET NOT NULL,
+    ALTER updated_at SET NOT NULL;
+
+CREATE INDEX newsletter_issues_status_idx ON newsletter_issues (status);
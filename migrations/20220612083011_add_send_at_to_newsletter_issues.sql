@@ -0,0 +1,6 @@
+ALTER TABLE newsletter_issues ADD send_at timestamptz NULL;
+
+-- Scheduled issues are the only ones with a send time, and the scheduler looks them up by it
+ALTER TABLE newsletter_issues
+    ADD CONSTRAINT newsletter_issues_send_at_check CHECK ((status = 'scheduled') = (send_at IS NOT NULL));
+CREATE INDEX newsletter_issues_send_at_idx ON newsletter_issues (send_at) WHERE status = 'scheduled';
@@ -0,0 +1,3 @@
+-- The Markdown that html_content and text_content were rendered from.
+-- NULL for issues written before Markdown authoring, and for ones with both versions written by hand.
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT NULL;
@@ -0,0 +1,8 @@
+-- Reset emails are sent by a background worker, so that asking for one does the same work
+-- whether or not the account exists
+CREATE TABLE password_reset_queue (
+    request_id uuid PRIMARY KEY,
+    username TEXT NOT NULL,
+    n_attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL DEFAULT now()
+);
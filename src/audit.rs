@@ -0,0 +1,163 @@
+use crate::{
+    authentication::{ApiCaller, UserId},
+    utils::client_ip,
+};
+
+use {
+    actix_web::{HttpMessage, HttpRequest},
+    anyhow::{Context, Result},
+    chrono::Utc,
+    sqlx::postgres::PgExecutor,
+    uuid::Uuid,
+};
+
+/// The kinds of event recorded in the audit log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    LoginSucceeded,
+    LoginFailed,
+    Logout,
+    PasswordChanged,
+    NewsletterPublished,
+    NewsletterScheduled,
+    NewsletterScheduleCancelled,
+    CollaboratorChanged,
+    SubscriberAdded,
+    SubscriberConfirmed,
+    SubscriberUnsubscribed,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0} is not a valid audit action")]
+pub struct AuditActionValidationError(String);
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 11] = [
+        AuditAction::LoginSucceeded,
+        AuditAction::LoginFailed,
+        AuditAction::Logout,
+        AuditAction::PasswordChanged,
+        AuditAction::NewsletterPublished,
+        AuditAction::NewsletterScheduled,
+        AuditAction::NewsletterScheduleCancelled,
+        AuditAction::CollaboratorChanged,
+        AuditAction::SubscriberAdded,
+        AuditAction::SubscriberConfirmed,
+        AuditAction::SubscriberUnsubscribed,
+    ];
+
+    pub fn parse(s: &str) -> Result<Self, AuditActionValidationError> {
+        AuditAction::ALL
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| AuditActionValidationError(s.to_owned()))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LoginSucceeded => "login_succeeded",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::Logout => "logout",
+            AuditAction::PasswordChanged => "password_changed",
+            AuditAction::NewsletterPublished => "newsletter_published",
+            AuditAction::NewsletterScheduled => "newsletter_scheduled",
+            AuditAction::NewsletterScheduleCancelled => "newsletter_schedule_cancelled",
+            AuditAction::CollaboratorChanged => "collaborator_changed",
+            AuditAction::SubscriberAdded => "subscriber_added",
+            AuditAction::SubscriberConfirmed => "subscriber_confirmed",
+            AuditAction::SubscriberUnsubscribed => "subscriber_unsubscribed",
+        }
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Something to record in the audit log.
+///
+/// Details are built with `serde_json::json!`, which only takes values that implement
+/// `Serialize`. `Secret` doesn't, so passwords, codes and tokens can't be logged by mistake.
+#[derive(Debug)]
+pub struct AuditEvent {
+    action: AuditAction,
+    user_id: Option<Uuid>,
+    details: serde_json::Value,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, details: serde_json::Value) -> Self {
+        Self {
+            action,
+            user_id: None,
+            details,
+        }
+    }
+
+    /// Attribute the event to a user who isn't authenticated by the request yet, e.g. while they
+    /// are logging in.
+    pub fn by(mut self, user_id: Uuid) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+}
+
+/// Write an event to the audit log, attributed to the user the request is authenticated as,
+/// either by their session or by an API token.
+///
+/// Pass the transaction making the change when there is one, so that the change and its record
+/// are committed together.
+#[tracing::instrument(
+    name = "Record audit event",
+    skip(request, event, executor),
+    fields(action = %event.action)
+)]
+pub async fn record_audit_event<'c>(
+    request: &HttpRequest,
+    event: AuditEvent,
+    executor: impl PgExecutor<'c>,
+) -> Result<()> {
+    let user_id = event.user_id.or_else(|| {
+        let extensions = request.extensions();
+        extensions
+            .get::<UserId>()
+            .map(|user_id| **user_id)
+            .or_else(|| extensions.get::<ApiCaller>().map(|caller| caller.user_id))
+    });
+
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_events (
+            audit_event_id, occurred_at, user_id, action, client_ip, details
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        Utc::now(),
+        user_id,
+        event.action.as_str(),
+        client_ip(request),
+        event.details,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to record an audit event")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditAction;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn actions_round_trip_through_their_names() {
+        for action in AuditAction::ALL {
+            assert_ok_eq!(AuditAction::parse(action.as_str()), action);
+        }
+        assert_err!(AuditAction::parse("LoginFailed"));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::authentication::Role;
+
+use {
+    actix_web::{
+        body::MessageBody,
+        dev::{ServiceRequest, ServiceResponse},
+        http::{
+            header::{AUTHORIZATION, WWW_AUTHENTICATE},
+            StatusCode,
+        },
+        web, HttpMessage, HttpResponse, ResponseError,
+    },
+    actix_web_lab::middleware::Next,
+    anyhow::Context,
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
+    sha2::{Digest, Sha256},
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+const TOKEN_PREFIX: &str = "zp_";
+const TOKEN_SECRET_LENGTH: usize = 40;
+
+#[derive(Debug, thiserror::Error)]
+#[error("An API token must be `zp_` followed by 40 ASCII alphanumeric characters")]
+pub struct ApiTokenError;
+
+/// A bearer token for the `/api` endpoints.
+///
+/// It is only shown to its owner when it is created, the database keeps its digest.
+#[derive(Debug)]
+pub struct ApiToken(String);
+
+impl ApiToken {
+    pub fn generate() -> Self {
+        let mut rng = thread_rng();
+        let secret: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(TOKEN_SECRET_LENGTH)
+            .collect();
+        Self(format!("{}{}", TOKEN_PREFIX, secret))
+    }
+
+    pub fn parse(s: String) -> Result<Self, ApiTokenError> {
+        match s.strip_prefix(TOKEN_PREFIX) {
+            Some(secret)
+                if secret.len() == TOKEN_SECRET_LENGTH
+                    && secret.chars().all(|c| c.is_ascii_alphanumeric()) =>
+            {
+                Ok(Self(s))
+            }
+            _ => Err(ApiTokenError),
+        }
+    }
+
+    /// Hex-encoded SHA-256 digest of the token, which is what gets stored in the database.
+    pub fn digest(&self) -> String {
+        hex::encode(Sha256::digest(self.0.as_bytes()))
+    }
+}
+
+impl AsRef<str> for ApiToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// What an API token can be used for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    ReadNewsletters,
+    PublishNewsletters,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0} is not a valid API scope")]
+pub struct ApiScopeValidationError(String);
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 2] = [ApiScope::ReadNewsletters, ApiScope::PublishNewsletters];
+
+    pub fn parse(s: &str) -> Result<Self, ApiScopeValidationError> {
+        match s {
+            "newsletters:read" => Ok(ApiScope::ReadNewsletters),
+            "newsletters:publish" => Ok(ApiScope::PublishNewsletters),
+            other => Err(ApiScopeValidationError(other.to_owned())),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::ReadNewsletters => "newsletters:read",
+            ApiScope::PublishNewsletters => "newsletters:publish",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ApiScope::ReadNewsletters => "List published newsletter issues",
+            ApiScope::PublishNewsletters => "Publish newsletter issues",
+        }
+    }
+
+    /// The role the token's owner needs for the scope to be granted, both when the token is
+    /// created and every time it is used.
+    pub fn required_role(&self) -> Role {
+        match self {
+            ApiScope::ReadNewsletters | ApiScope::PublishNewsletters => Role::Editor,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("A bearer token is required")]
+    MissingToken,
+    #[error("The bearer token is not valid")]
+    InvalidToken,
+    #[error("The token does not have the {0} scope")]
+    MissingScope(ApiScope),
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::MissingToken | ApiError::InvalidToken => StatusCode::UNAUTHORIZED,
+            ApiError::MissingScope(_) => StatusCode::FORBIDDEN,
+            ApiError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            // The details are in the logs, they are none of the client's business
+            ApiError::Unexpected(_) => "Something went wrong".to_string(),
+            e => e.to_string(),
+        };
+        let mut response = HttpResponse::build(self.status_code());
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            response.insert_header((WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(serde_json::json!({ "error": message }))
+    }
+}
+
+/// The token an API request was authenticated with, see `reject_invalid_api_tokens`.
+#[derive(Clone, Debug)]
+pub struct ApiCaller {
+    pub api_token_id: Uuid,
+    pub user_id: Uuid,
+    scopes: Vec<ApiScope>,
+}
+
+impl ApiCaller {
+    pub fn require(&self, scope: ApiScope) -> Result<(), ApiError> {
+        if self.scopes.contains(&scope) {
+            Ok(())
+        } else {
+            Err(ApiError::MissingScope(scope))
+        }
+    }
+}
+
+/// Only let requests with a valid `Authorization: Bearer` API token through, making the token
+/// available to handlers as `ApiCaller`.
+pub async fn reject_invalid_api_tokens(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or(ApiError::MissingToken)?;
+    let token = ApiToken::parse(token.trim().to_owned()).map_err(|_| ApiError::InvalidToken)?;
+
+    let db_pool = req
+        .app_data::<web::Data<PgPool>>()
+        .expect("The database pool is not registered as app data")
+        .clone();
+    let caller = authenticate_api_token(&token, &db_pool)
+        .await?
+        .ok_or(ApiError::InvalidToken)?;
+
+    req.extensions_mut().insert(caller);
+    next.call(req).await
+}
+
+/// Look up an unrevoked token belonging to an active user, recording that it has been used.
+///
+/// Scopes that the user's current role doesn't allow are dropped, so that tokens lose their
+/// privileges along with their owners.
+#[tracing::instrument(name = "Authenticate API token", skip(token, db_pool))]
+async fn authenticate_api_token(
+    token: &ApiToken,
+    db_pool: &PgPool,
+) -> Result<Option<ApiCaller>, ApiError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens t
+        SET last_used_at = now()
+        FROM users u
+        WHERE t.token_hash = $1
+            AND t.revoked_at IS NULL
+            AND u.user_id = t.user_id
+            AND u.is_active
+        RETURNING t.api_token_id, t.user_id, t.scopes, u.role
+        "#,
+        token.digest(),
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to look up the API token")?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let role = Role::parse(&row.role).context("Invalid role stored in the database")?;
+    let scopes = row
+        .scopes
+        .iter()
+        .filter_map(|s| ApiScope::parse(s).ok())
+        .filter(|scope| scope.required_role() <= role)
+        .collect();
+
+    Ok(Some(ApiCaller {
+        api_token_id: row.api_token_id,
+        user_id: row.user_id,
+        scopes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiScope, ApiToken};
+    use claim::{assert_err, assert_ok, assert_ok_eq};
+
+    #[test]
+    fn generated_tokens_are_valid() {
+        let token = ApiToken::generate();
+        assert_ok!(ApiToken::parse(token.as_ref().to_owned()));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let secret = "a".repeat(40);
+        assert_err!(ApiToken::parse(secret.clone()));
+        assert_err!(ApiToken::parse(format!("xx_{}", secret)));
+        assert_err!(ApiToken::parse(format!("zp_{}", &secret[1..])));
+        assert_err!(ApiToken::parse(format!("zp_{}-", &secret[1..])));
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_names() {
+        for scope in ApiScope::ALL {
+            assert_ok_eq!(ApiScope::parse(scope.as_str()), scope);
+        }
+        assert_err!(ApiScope::parse("newsletters:*"));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{
+    session_state::TypedSession,
+    utils::{e500, peek_body},
+};
+
+use {
+    actix_web::{
+        body::MessageBody,
+        dev::{ServiceRequest, ServiceResponse},
+        http::header::ContentType,
+        FromRequest, HttpResponse,
+    },
+    actix_web_flash_messages::FlashMessage,
+    actix_web_lab::middleware::Next,
+    subtle::ConstantTimeEq,
+};
+
+/// The form field that carries the CSRF token, see `templates/csrf_token_input.html`.
+pub const CSRF_TOKEN_FIELD: &str = "csrf_token";
+/// Clients that don't submit forms can send the token in this header instead.
+pub const CSRF_TOKEN_HEADER: &str = "X-CSRF-Token";
+
+#[derive(serde::Deserialize)]
+struct CsrfTokenField {
+    csrf_token: String,
+}
+
+/// The token for the hidden input that every form posting to a protected route has to include.
+pub fn form_csrf_token(session: &TypedSession) -> Result<String, actix_web::Error> {
+    session.csrf_token().map_err(e500)
+}
+
+/// Refuse requests with unsafe methods that don't carry the session's CSRF token, so that other
+/// sites can't make a logged in user's browser submit our forms.
+pub async fn reject_forged_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if req.method().is_safe() {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+
+    let expected = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await?
+    }
+    .get_csrf_token()
+    .map_err(e500)?;
+    let submitted = match req.headers().get(CSRF_TOKEN_HEADER) {
+        Some(header) => header.to_str().ok().map(str::to_owned),
+        None => {
+            let body = peek_body(&mut req).await?;
+            serde_urlencoded::from_bytes::<CsrfTokenField>(&body)
+                .ok()
+                .map(|f| f.csrf_token)
+        }
+    };
+
+    match (expected, submitted) {
+        (Some(expected), Some(submitted))
+            if bool::from(expected.as_bytes().ct_eq(submitted.as_bytes())) =>
+        {
+            next.call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        }
+        _ => {
+            tracing::warn!(
+                method = %req.method(),
+                path = %req.path(),
+                "Rejected a request without a valid CSRF token"
+            );
+            // Respond rather than error out, otherwise the flash message would be dropped
+            FlashMessage::error(
+                "Your form could not be verified, please reload the page and try again.",
+            )
+            .send();
+            let response = HttpResponse::Forbidden()
+                .content_type(ContentType::plaintext())
+                .body("This request could not be verified.");
+            Ok(req.into_response(response).map_into_right_body())
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{
+    authentication::{track_session, Role, SessionClient, SessionRegistry},
     session_state::TypedSession,
     utils::{e500, see_other},
 };
@@ -10,34 +11,224 @@ use {
         body::MessageBody,
         dev::{ServiceRequest, ServiceResponse},
         error::InternalError,
-        FromRequest, HttpMessage,
+        web, FromRequest, HttpMessage, HttpResponse,
     },
+    actix_web_flash_messages::FlashMessage,
     actix_web_lab::middleware::Next,
+    anyhow::Context,
+    chrono::{DateTime, Duration, Utc},
+    sqlx::PgPool,
     uuid::Uuid,
 };
 
 pub async fn reject_anonymous_users(
     mut req: ServiceRequest,
-    next: Next<impl MessageBody>,
+    next: Next<impl MessageBody + 'static>,
 ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
     let session = {
         let (http_request, payload) = req.parts_mut();
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => {
-            req.extensions_mut().insert(UserId(user_id));
-            next.call(req).await
-        }
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
         None => {
             let response = see_other("/login");
             let e = anyhow::anyhow!("The user has not logged in");
+            return Err(InternalError::from_response(e, response).into());
+        }
+    };
+
+    let db_pool = req
+        .app_data::<web::Data<PgPool>>()
+        .expect("The database pool is not registered as app data")
+        .clone();
+    let timeouts = req
+        .app_data::<web::Data<SessionTimeouts>>()
+        .expect("The session timeouts are not registered as app data")
+        .clone();
+    let now = Utc::now();
+    let login_time = session.get_login_time().map_err(e500)?;
+    let last_activity = session.get_last_activity().map_err(e500)?;
+    let rejection = if let Some(expiry) = timeouts.expiry(login_time, last_activity, now) {
+        expiry.message()
+    } else {
+        // Look the user up on every request so that changes and deactivations apply straight away
+        match get_active_user(&user_id, &db_pool).await.map_err(e500)? {
+            None => "Your account has been deactivated.",
+            Some(user) if !user.accepts_session_started_at(login_time) => {
+                "Your session has ended, please log in again."
+            }
+            Some(user) => {
+                let registry = req
+                    .app_data::<web::Data<SessionRegistry>>()
+                    .expect("The session registry is not registered as app data")
+                    .clone();
+                let client = SessionClient::new(req.parts_mut().0);
+                if track_session(&session, &user_id, &client, &registry)
+                    .await
+                    .map_err(e500)?
+                {
+                    // Restart the idle timer
+                    session.insert_last_activity(now).map_err(e500)?;
+                    req.extensions_mut().insert(UserId(user_id));
+                    req.extensions_mut().insert(user.role);
+                    return next
+                        .call(req)
+                        .await
+                        .map(ServiceResponse::map_into_left_body);
+                }
+                "This session has been signed out, please log in again."
+            }
+        }
+    };
+
+    tracing::warn!(%user_id, rejection, "Rejected a session that is no longer valid");
+    session.log_out();
+    // Respond rather than error out, otherwise the flash message would be dropped
+    FlashMessage::error(rejection).send();
+    Ok(req.into_response(see_other("/login")).map_into_right_body())
+}
+
+/// Only let editors and owners through. Must be wrapped by `reject_anonymous_users`.
+pub async fn require_editor(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Editor, req, next).await
+}
+
+/// Only let owners through. Must be wrapped by `reject_anonymous_users`.
+pub async fn require_owner(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Owner, req, next).await
+}
+
+async fn require_role(
+    required: Role,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let role = req.extensions().get::<Role>().copied();
+    match role {
+        Some(role) if role >= required => next.call(req).await,
+        _ => {
+            let response = HttpResponse::Forbidden().body("You are not allowed to do this.");
+            let e = anyhow::anyhow!("The user does not have the {} role", required);
             Err(InternalError::from_response(e, response).into())
         }
     }
 }
 
+/// Log the user out of every session they currently have, by refusing sessions that were
+/// started before now. They are also taken out of the registry, so they stop being listed.
+#[tracing::instrument(name = "Invalidate user sessions", skip(db_pool, registry))]
+pub async fn invalidate_sessions(
+    user_id: &Uuid,
+    db_pool: &PgPool,
+    registry: &SessionRegistry,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE users SET sessions_invalidated_at = $1 WHERE user_id = $2"#,
+        Utc::now(),
+        user_id
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to invalidate the user's sessions")?;
+    registry.revoke_all(user_id).await?;
+
+    Ok(())
+}
+
+/// How long a logged in session lasts, see `ApplicationSettings`.
+#[derive(Debug)]
+pub struct SessionTimeouts {
+    pub idle: Duration,
+    pub absolute: Duration,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionExpiry {
+    Idle,
+    Absolute,
+}
+
+impl SessionExpiry {
+    fn message(&self) -> &'static str {
+        match self {
+            SessionExpiry::Idle => {
+                "You have been logged out after a period of inactivity, please log in again."
+            }
+            SessionExpiry::Absolute => "Your session has expired, please log in again.",
+        }
+    }
+}
+
+impl SessionTimeouts {
+    /// Whether a session started at `login_time` and last used at `last_activity` is over by `now`.
+    pub fn expiry(
+        &self,
+        login_time: Option<DateTime<Utc>>,
+        last_activity: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Option<SessionExpiry> {
+        // Sessions that predate login times being recorded can't tell how old they are
+        let login_time = match login_time {
+            Some(login_time) => login_time,
+            None => return Some(SessionExpiry::Absolute),
+        };
+
+        if now - login_time >= self.absolute {
+            Some(SessionExpiry::Absolute)
+        } else if now - last_activity.unwrap_or(login_time) >= self.idle {
+            Some(SessionExpiry::Idle)
+        } else {
+            None
+        }
+    }
+}
+
+struct ActiveUser {
+    role: Role,
+    sessions_invalidated_at: Option<DateTime<Utc>>,
+}
+
+impl ActiveUser {
+    fn accepts_session_started_at(&self, login_time: Option<DateTime<Utc>>) -> bool {
+        match (self.sessions_invalidated_at, login_time) {
+            (None, _) => true,
+            (Some(cutoff), Some(login_time)) => login_time >= cutoff,
+            // Sessions that predate login times being recorded
+            (Some(_), None) => false,
+        }
+    }
+}
+
+#[tracing::instrument(name = "Get active user", skip(db_pool))]
+async fn get_active_user(
+    user_id: &Uuid,
+    db_pool: &PgPool,
+) -> Result<Option<ActiveUser>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT role, sessions_invalidated_at FROM users WHERE user_id = $1 AND is_active"#,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to retrieve the user")?;
+
+    row.map(|r| {
+        Ok(ActiveUser {
+            role: Role::parse(&r.role).context("Invalid role stored in the database")?,
+            sessions_invalidated_at: r.sessions_invalidated_at,
+        })
+    })
+    .transpose()
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct UserId(Uuid);
 
@@ -53,3 +244,66 @@ impl Deref for UserId {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{SessionExpiry, SessionTimeouts};
+
+    use chrono::{Duration, Utc};
+
+    fn timeouts() -> SessionTimeouts {
+        SessionTimeouts {
+            idle: Duration::minutes(30),
+            absolute: Duration::hours(12),
+        }
+    }
+
+    #[test]
+    fn recently_active_sessions_are_kept() {
+        let now = Utc::now();
+        let login_time = now - Duration::hours(2);
+        let last_activity = now - Duration::minutes(29);
+
+        assert_eq!(
+            timeouts().expiry(Some(login_time), Some(last_activity), now),
+            None
+        );
+    }
+
+    #[test]
+    fn sessions_expire_once_idle_for_too_long() {
+        let now = Utc::now();
+        let login_time = now - Duration::hours(2);
+
+        assert_eq!(
+            timeouts().expiry(Some(login_time), Some(now - Duration::minutes(30)), now),
+            Some(SessionExpiry::Idle)
+        );
+        // Without any activity since, the idle timer starts at login
+        assert_eq!(
+            timeouts().expiry(Some(login_time), None, now),
+            Some(SessionExpiry::Idle)
+        );
+    }
+
+    #[test]
+    fn sessions_expire_after_their_absolute_lifetime_despite_activity() {
+        let now = Utc::now();
+        let login_time = now - Duration::hours(12);
+
+        assert_eq!(
+            timeouts().expiry(Some(login_time), Some(now), now),
+            Some(SessionExpiry::Absolute)
+        );
+    }
+
+    #[test]
+    fn sessions_without_a_login_time_are_expired() {
+        let now = Utc::now();
+
+        assert_eq!(
+            timeouts().expiry(None, Some(now), now),
+            Some(SessionExpiry::Absolute)
+        );
+    }
+}
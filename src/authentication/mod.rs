@@ -1,4 +1,16 @@
+mod api_token;
+mod csrf;
+mod invitation;
 mod middleware;
 mod password;
+mod password_reset;
+mod role;
+mod sessions;
+mod throttle;
+mod totp;
+mod two_factor;
 
-pub use {middleware::*, password::*};
+pub use {
+    api_token::*, csrf::*, invitation::*, middleware::*, password::*, password_reset::*, role::*,
+    sessions::*, throttle::*, totp::*, two_factor::*,
+};
@@ -1,10 +1,13 @@
-use crate::telemetry::spawn_blocking_with_tracing;
+use crate::{configuration::PasswordHashingSettings, telemetry::spawn_blocking_with_tracing};
 
 use {
     anyhow::{Context, Result},
-    argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier},
+    argon2::{
+        password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+        PasswordVerifier, Version,
+    },
     secrecy::{ExposeSecret, Secret},
-    sqlx::PgPool,
+    sqlx::{PgExecutor, PgPool},
     uuid::Uuid,
 };
 
@@ -22,18 +25,73 @@ pub enum AuthError {
     Unexpected(#[from] anyhow::Error),
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+/// The argon2id parameters that new password hashes are computed with.
+#[derive(Clone, Debug)]
+pub struct PasswordHashingParams {
+    params: Params,
+    /// Verified against when the username is unknown, so that it takes as long as for a real
+    /// user. It is computed with `params` for the same reason.
+    dummy_hash: Secret<String>,
+}
+
+impl PasswordHashingParams {
+    pub fn new(settings: &PasswordHashingSettings) -> Result<Self> {
+        let params = Params::new(
+            settings.memory_cost_kib,
+            settings.time_cost,
+            settings.parallelism,
+            None,
+        )
+        .context("Invalid password hashing parameters")?;
+
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let dummy_password = SaltString::generate(&mut rand::thread_rng());
+        let dummy_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+            .hash_password(dummy_password.as_str().as_bytes(), &salt)
+            .context("Failed to compute the dummy password hash")?
+            .to_string();
+
+        Ok(Self {
+            params,
+            dummy_hash: Secret::new(dummy_hash),
+        })
+    }
+
+    fn hasher(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    /// Whether `hash` should be recomputed with these parameters: it was computed with another
+    /// algorithm or version, or is cheaper to compute.
+    fn supersede(&self, hash: &PasswordHash) -> bool {
+        if hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+        {
+            return true;
+        }
+
+        match Params::try_from(hash) {
+            Ok(params) => {
+                params.m_cost() < self.params.m_cost()
+                    || params.t_cost() < self.params.t_cost()
+                    || params.p_cost() < self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+/// Check a user's credentials. On success, a stored hash that is weaker than `hashing_params`
+/// is replaced with one computed with them, so that raising the costs doesn't require everyone
+/// to reset their password.
+#[tracing::instrument(name = "Validate credentials", skip(credentials, hashing_params, pool))]
 pub async fn validate_credentials(
     credentials: Credentials,
+    hashing_params: &PasswordHashingParams,
     pool: &PgPool,
 ) -> Result<uuid::Uuid, AuthError> {
     let mut user_id = None;
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-                gZiV/M1gPc22ElAH/Jh1Hw$\
-                CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-            .to_string(),
-    );
+    let mut expected_password_hash = hashing_params.dummy_hash.clone();
 
     if let Some((stored_user_id, stored_password_hash)) =
         get_stored_credentials(&credentials.username, pool).await?
@@ -42,13 +100,32 @@ pub async fn validate_credentials(
         expected_password_hash = stored_password_hash
     }
 
-    spawn_blocking_with_tracing(move || {
-        verify_password_hash(expected_password_hash, credentials.password)
+    let stored_password_hash = expected_password_hash.clone();
+    let hashing_params = hashing_params.clone();
+    let upgraded_password_hash = spawn_blocking_with_tracing(move || {
+        verify_password_hash(&expected_password_hash, &credentials.password)?;
+        Ok::<_, AuthError>(upgrade_password_hash(
+            &expected_password_hash,
+            &credentials.password,
+            &hashing_params,
+        ))
     })
     .await
     .context("Failed to spawn blocking task")??;
 
-    user_id.ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username")))
+    let user_id = user_id
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username")))?;
+
+    if let Some(password_hash) = upgraded_password_hash {
+        // The password was right, failing to store the new hash shouldn't stop the login
+        if let Err(e) =
+            replace_password_hash(&user_id, &stored_password_hash, &password_hash, pool).await
+        {
+            tracing::warn!(error.cause_chain = ?e, "Failed to upgrade the password hash");
+        }
+    }
+
+    Ok(user_id)
 }
 
 #[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
@@ -57,7 +134,7 @@ async fn get_stored_credentials(
     pool: &PgPool,
 ) -> Result<Option<(Uuid, Secret<String>)>> {
     let row: Option<_> = sqlx::query!(
-        r#"SELECT user_id, password_hash FROM users WHERE username = $1"#,
+        r#"SELECT user_id, password_hash FROM users WHERE username = $1 AND is_active"#,
         username
     )
     .fetch_optional(pool)
@@ -73,8 +150,8 @@ async fn get_stored_credentials(
     skip(expected_password_hash, password_candidate)
 )]
 fn verify_password_hash(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
+    expected_password_hash: &Secret<String>,
+    password_candidate: &Secret<String>,
 ) -> Result<(), AuthError> {
     let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
         .context("Failed to parse hash in PHC string format")?;
@@ -88,15 +165,64 @@ fn verify_password_hash(
         .map_err(AuthError::InvalidCredentials)
 }
 
-fn compute_password_hash(password: &Secret<String>) -> Result<Secret<String>, anyhow::Error> {
-    let salt = SaltString::generate(&mut rand::thread_rng());
-    let password_hash = Argon2::new(
-        argon2::Algorithm::Argon2id,
-        argon2::Version::V0x13,
-        argon2::Params::new(15000, 2, 1, None).unwrap(),
+/// Hash an already verified `password` again if `stored_password_hash` is weaker than
+/// `hashing_params`.
+fn upgrade_password_hash(
+    stored_password_hash: &Secret<String>,
+    password: &Secret<String>,
+    hashing_params: &PasswordHashingParams,
+) -> Option<Secret<String>> {
+    let stored_password_hash = PasswordHash::new(stored_password_hash.expose_secret()).ok()?;
+    if !hashing_params.supersede(&stored_password_hash) {
+        return None;
+    }
+
+    tracing::info!(
+        algorithm = %stored_password_hash.algorithm,
+        "Upgrading an outdated password hash"
+    );
+    match compute_password_hash(password, hashing_params) {
+        Ok(password_hash) => Some(password_hash),
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, "Failed to compute the upgraded password hash");
+            None
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Replace password hash",
+    skip(old_password_hash, new_password_hash, pool)
+)]
+async fn replace_password_hash(
+    user_id: &Uuid,
+    old_password_hash: &Secret<String>,
+    new_password_hash: &Secret<String>,
+    pool: &PgPool,
+) -> Result<()> {
+    // Leave the row alone if the password was changed since it was read
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $1 WHERE user_id = $2 AND password_hash = $3"#,
+        new_password_hash.expose_secret(),
+        user_id,
+        old_password_hash.expose_secret(),
     )
-    .hash_password(password.expose_secret().as_bytes(), &salt)?
-    .to_string();
+    .execute(pool)
+    .await
+    .context("Failed to replace the user's password hash")?;
+
+    Ok(())
+}
+
+pub fn compute_password_hash(
+    password: &Secret<String>,
+    hashing_params: &PasswordHashingParams,
+) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = hashing_params
+        .hasher()
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
 
     Ok(Secret::new(password_hash))
 }
@@ -104,20 +230,120 @@ fn compute_password_hash(password: &Secret<String>) -> Result<Secret<String>, an
 pub async fn change_password(
     user_id: &Uuid,
     password: Secret<String>,
-    db_pool: &PgPool,
+    hashing_params: &PasswordHashingParams,
+    executor: impl PgExecutor<'_>,
 ) -> Result<(), anyhow::Error> {
-    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(&password))
-        .await?
-        .context("Failed to hash password")?;
+    let hashing_params = hashing_params.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(&password, &hashing_params))
+            .await?
+            .context("Failed to hash password")?;
 
     sqlx::query!(
         r#"UPDATE users SET password_hash = $1 WHERE user_id = $2"#,
         password_hash.expose_secret(),
         user_id
     )
-    .execute(db_pool)
+    .execute(executor)
     .await
     .context("Failed to change user's password in the database")?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PasswordHashingParams;
+    use crate::configuration::PasswordHashingSettings;
+    use {
+        argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version},
+        claim::assert_err,
+        secrecy::ExposeSecret,
+    };
+
+    fn params(memory_cost_kib: u32, time_cost: u32, parallelism: u32) -> PasswordHashingParams {
+        PasswordHashingParams::new(&PasswordHashingSettings {
+            memory_cost_kib,
+            time_cost,
+            parallelism,
+        })
+        .unwrap()
+    }
+
+    fn hash(algorithm: Algorithm, version: Version, params: Params) -> String {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        Argon2::new(algorithm, version, params)
+            .hash_password(b"password", &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    fn supersedes(current: &PasswordHashingParams, stored: &str) -> bool {
+        current.supersede(&argon2::PasswordHash::new(stored).unwrap())
+    }
+
+    #[test]
+    fn hashes_with_the_current_parameters_are_kept() {
+        let current = params(64, 2, 1);
+        let stored = hash(Algorithm::Argon2id, Version::V0x13, current.params.clone());
+        assert!(!supersedes(&current, &stored));
+    }
+
+    #[test]
+    fn hashes_with_stronger_parameters_are_kept() {
+        let current = params(64, 2, 1);
+        let stored = hash(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(128, 3, 1, None).unwrap(),
+        );
+        assert!(!supersedes(&current, &stored));
+    }
+
+    #[test]
+    fn hashes_with_any_weaker_parameter_are_superseded() {
+        let current = params(64, 2, 2);
+        for (m_cost, t_cost, p_cost) in [(32, 2, 2), (64, 1, 2), (64, 2, 1)] {
+            let stored = hash(
+                Algorithm::Argon2id,
+                Version::V0x13,
+                Params::new(m_cost, t_cost, p_cost, None).unwrap(),
+            );
+            assert!(supersedes(&current, &stored));
+        }
+    }
+
+    #[test]
+    fn hashes_with_another_algorithm_or_version_are_superseded() {
+        let current = params(64, 2, 1);
+        for (algorithm, version) in [
+            (Algorithm::Argon2i, Version::V0x13),
+            (Algorithm::Argon2d, Version::V0x13),
+            (Algorithm::Argon2id, Version::V0x10),
+        ] {
+            let stored = hash(algorithm, version, current.params.clone());
+            assert!(supersedes(&current, &stored));
+        }
+    }
+
+    #[test]
+    fn the_dummy_hash_is_computed_with_the_current_parameters() {
+        let current = params(64, 3, 2);
+        let dummy_hash = argon2::PasswordHash::new(current.dummy_hash.expose_secret()).unwrap();
+        let params = Params::try_from(&dummy_hash).unwrap();
+        assert_eq!(
+            (params.m_cost(), params.t_cost(), params.p_cost()),
+            (64, 3, 2)
+        );
+        assert!(!current.supersede(&dummy_hash));
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        assert_err!(PasswordHashingParams::new(&PasswordHashingSettings {
+            memory_cost_kib: 1,
+            time_cost: 0,
+            parallelism: 0,
+        }));
+    }
+}
@@ -0,0 +1,71 @@
+use {
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
+    sha2::{Digest, Sha256},
+};
+
+const TOKEN_LENGTH: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+#[error("A password reset token must be 32 ASCII alphanumeric characters")]
+pub struct PasswordResetTokenError;
+
+/// The secret part of a password reset link.
+///
+/// Like subscription tokens, only the digest is stored so that a leaked table can't be used to
+/// take over accounts.
+#[derive(Debug)]
+pub struct PasswordResetToken(String);
+
+impl PasswordResetToken {
+    pub fn generate() -> Self {
+        let mut rng = thread_rng();
+        let token = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(TOKEN_LENGTH)
+            .collect();
+        Self(token)
+    }
+
+    pub fn parse(s: String) -> Result<Self, PasswordResetTokenError> {
+        if s.len() == TOKEN_LENGTH && s.chars().all(|c| c.is_ascii_alphanumeric()) {
+            Ok(Self(s))
+        } else {
+            Err(PasswordResetTokenError)
+        }
+    }
+
+    /// Hex-encoded SHA-256 digest of the token, which is what gets stored in the database.
+    pub fn digest(&self) -> String {
+        hex::encode(Sha256::digest(self.0.as_bytes()))
+    }
+}
+
+impl AsRef<str> for PasswordResetToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PasswordResetToken;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn generated_tokens_are_valid() {
+        let token = PasswordResetToken::generate();
+        assert_ok!(PasswordResetToken::parse(token.as_ref().to_owned()));
+    }
+
+    #[test]
+    fn tokens_of_the_wrong_length_are_rejected() {
+        assert_err!(PasswordResetToken::parse("a".repeat(31)));
+        assert_err!(PasswordResetToken::parse("a".repeat(33)));
+    }
+
+    #[test]
+    fn tokens_with_other_characters_are_rejected() {
+        assert_err!(PasswordResetToken::parse(format!("{}-", "a".repeat(31))));
+        assert_err!(PasswordResetToken::parse(format!("{}é", "a".repeat(30))));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{session_state::TypedSession, utils::client_ip};
+
+use std::{cmp::Reverse, collections::HashMap};
+
+use {
+    actix_web::{http::header::USER_AGENT, HttpRequest},
+    anyhow::{Context, Result},
+    chrono::{DateTime, TimeZone, Utc},
+    redis::{aio::ConnectionManager, AsyncCommands, Script},
+    secrecy::{ExposeSecret, Secret},
+    uuid::Uuid,
+};
+
+/// User agents are only shown to help users recognise their sessions, so long ones are cut.
+const MAX_USER_AGENT_CHARS: usize = 256;
+
+/// Records that a session in `KEYS[1]` has been seen again, unless it has been revoked.
+///
+/// ARGV holds the current time in milliseconds, the client IP, its user agent and the time to
+/// live in milliseconds, which is also applied to the user's index in `KEYS[2]`. Returns 1 if the
+/// session is still registered, 0 otherwise. Checking and updating in a script keeps a revoked
+/// session from being brought back by a request that was already in flight.
+const TOUCH_SESSION_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return 0
+end
+
+redis.call('HSET', KEYS[1], 'last_seen_at', ARGV[1], 'ip', ARGV[2], 'user_agent', ARGV[3])
+redis.call('PEXPIRE', KEYS[1], ARGV[4])
+redis.call('PEXPIRE', KEYS[2], ARGV[4])
+return 1
+"#;
+
+/// Where a session is being used from.
+#[derive(Debug)]
+pub struct SessionClient {
+    pub ip: String,
+    pub user_agent: String,
+}
+
+impl SessionClient {
+    pub fn new(request: &HttpRequest) -> Self {
+        let user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .chars()
+            .take(MAX_USER_AGENT_CHARS)
+            .collect();
+
+        Self {
+            ip: client_ip(request),
+            user_agent,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SessionRecord {
+    pub session_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub client: SessionClient,
+}
+
+impl SessionRecord {
+    fn parse(session_id: Uuid, mut fields: HashMap<String, String>) -> Option<Self> {
+        let timestamp = |field: &str| -> Option<DateTime<Utc>> {
+            let millis = fields.get(field)?.parse().ok()?;
+            Some(Utc.timestamp_millis(millis))
+        };
+        let created_at = timestamp("created_at")?;
+        let last_seen_at = timestamp("last_seen_at")?;
+
+        Some(Self {
+            session_id,
+            created_at,
+            last_seen_at,
+            client: SessionClient {
+                ip: fields.remove("ip")?,
+                user_agent: fields.remove("user_agent")?,
+            },
+        })
+    }
+}
+
+/// Keeps track of every user's sessions in Redis, next to the session state itself, so that
+/// users can see where they are logged in and sign sessions out remotely.
+///
+/// Sessions are identified by an ID of their own rather than by the session key, which must
+/// never leave the session cookie.
+pub struct SessionRegistry {
+    connection: ConnectionManager,
+    touch_script: Script,
+    /// Sessions are forgotten once they haven't been seen for this long.
+    record_ttl_millis: usize,
+}
+
+impl SessionRegistry {
+    /// `idle_timeout` should be the session idle timeout, after which sessions can't be used.
+    pub async fn new(redis_uri: &Secret<String>, idle_timeout: chrono::Duration) -> Result<Self> {
+        let client =
+            redis::Client::open(redis_uri.expose_secret().as_str()).context("Invalid Redis URI")?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis")?;
+
+        Ok(Self {
+            connection,
+            touch_script: Script::new(TOUCH_SESSION_SCRIPT),
+            record_ttl_millis: idle_timeout.num_milliseconds().max(1) as usize,
+        })
+    }
+
+    /// Record a new session for `user_id`, returning its ID.
+    #[tracing::instrument(name = "Register session", skip(self))]
+    pub async fn register(
+        &self,
+        user_id: &Uuid,
+        client: &SessionClient,
+        created_at: DateTime<Utc>,
+    ) -> Result<Uuid> {
+        let session_id = Uuid::new_v4();
+        let session_key = session_key(&session_id);
+        let index_key = index_key(user_id);
+        let now = Utc::now().timestamp_millis();
+
+        redis::pipe()
+            .hset_multiple(
+                &session_key,
+                &[
+                    ("created_at", created_at.timestamp_millis().to_string()),
+                    ("last_seen_at", now.to_string()),
+                    ("ip", client.ip.clone()),
+                    ("user_agent", client.user_agent.clone()),
+                ],
+            )
+            .ignore()
+            .pexpire(&session_key, self.record_ttl_millis)
+            .ignore()
+            .sadd(&index_key, session_id.to_string())
+            .ignore()
+            .pexpire(&index_key, self.record_ttl_millis)
+            .ignore()
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .context("Failed to register the session")?;
+
+        Ok(session_id)
+    }
+
+    /// Update when and where a session was last seen. Returns `false` if it has been revoked.
+    #[tracing::instrument(name = "Touch session", skip(self))]
+    pub async fn touch(
+        &self,
+        user_id: &Uuid,
+        session_id: &Uuid,
+        client: &SessionClient,
+    ) -> Result<bool> {
+        let is_registered: u8 = self
+            .touch_script
+            .key(session_key(session_id))
+            .key(index_key(user_id))
+            .arg(Utc::now().timestamp_millis())
+            .arg(&client.ip)
+            .arg(&client.user_agent)
+            .arg(self.record_ttl_millis)
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .context("Failed to run the session touch script")?;
+
+        Ok(is_registered == 1)
+    }
+
+    /// The user's sessions, most recently seen first.
+    #[tracing::instrument(name = "List sessions", skip(self))]
+    pub async fn list(&self, user_id: &Uuid) -> Result<Vec<SessionRecord>> {
+        let mut connection = self.connection.clone();
+        let index_key = index_key(user_id);
+        let session_ids: Vec<String> = connection
+            .smembers(&index_key)
+            .await
+            .context("Failed to retrieve the user's sessions")?;
+
+        let mut sessions = Vec::with_capacity(session_ids.len());
+        for session_id in session_ids {
+            let record = match Uuid::parse_str(&session_id) {
+                Ok(id) => {
+                    let fields = connection
+                        .hgetall(session_key(&id))
+                        .await
+                        .context("Failed to retrieve a session")?;
+                    SessionRecord::parse(id, fields)
+                }
+                Err(_) => None,
+            };
+            match record {
+                Some(record) => sessions.push(record),
+                // The session has expired since it was indexed
+                None => connection
+                    .srem::<_, _, ()>(&index_key, &session_id)
+                    .await
+                    .context("Failed to forget an expired session")?,
+            }
+        }
+
+        sessions.sort_by_key(|s| Reverse(s.last_seen_at));
+        Ok(sessions)
+    }
+
+    /// Sign one of the user's sessions out. Returns `false` if they had no such session.
+    #[tracing::instrument(name = "Revoke session", skip(self))]
+    pub async fn revoke(&self, user_id: &Uuid, session_id: &Uuid) -> Result<bool> {
+        let mut connection = self.connection.clone();
+        // Going through the user's index makes sure nobody can revoke someone else's session
+        let n_removed: u32 = connection
+            .srem(index_key(user_id), session_id.to_string())
+            .await
+            .context("Failed to remove the session from the user's index")?;
+        if n_removed == 0 {
+            return Ok(false);
+        }
+
+        connection
+            .del::<_, ()>(session_key(session_id))
+            .await
+            .context("Failed to revoke the session")?;
+        Ok(true)
+    }
+
+    /// Sign every one of the user's sessions out.
+    #[tracing::instrument(name = "Revoke all sessions", skip(self))]
+    pub async fn revoke_all(&self, user_id: &Uuid) -> Result<()> {
+        let mut connection = self.connection.clone();
+        let index_key = index_key(user_id);
+        let session_ids: Vec<String> = connection
+            .smembers(&index_key)
+            .await
+            .context("Failed to retrieve the user's sessions")?;
+
+        let mut keys: Vec<String> = session_ids
+            .iter()
+            .filter_map(|id| Uuid::parse_str(id).ok())
+            .map(|id| session_key(&id))
+            .collect();
+        keys.push(index_key);
+        connection
+            .del::<_, ()>(keys)
+            .await
+            .context("Failed to revoke the user's sessions")?;
+
+        Ok(())
+    }
+}
+
+/// Keep the registry up to date with a request from a logged in user. Returns `false` if their
+/// session has been revoked.
+///
+/// Sessions that were started before they were registered are registered on their next request.
+pub async fn track_session(
+    session: &TypedSession,
+    user_id: &Uuid,
+    client: &SessionClient,
+    registry: &SessionRegistry,
+) -> Result<bool> {
+    match session.get_session_id()? {
+        Some(session_id) => registry.touch(user_id, &session_id, client).await,
+        None => {
+            let created_at = session.get_login_time()?.unwrap_or_else(Utc::now);
+            let session_id = registry.register(user_id, client, created_at).await?;
+            session.insert_session_id(&session_id)?;
+            Ok(true)
+        }
+    }
+}
+
+fn session_key(session_id: &Uuid) -> String {
+    format!("session_registry:session:{}", session_id)
+}
+
+fn index_key(user_id: &Uuid) -> String {
+    format!("session_registry:user:{}", user_id)
+}
@@ -0,0 +1,173 @@
+use crate::configuration::LoginThrottleSettings;
+
+use {
+    anyhow::{Context, Result},
+    chrono::{DateTime, Utc},
+    sqlx::PgPool,
+};
+
+#[derive(Clone, Copy, Debug)]
+enum ThrottleScope {
+    Username,
+    Ip,
+}
+
+impl ThrottleScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ThrottleScope::Username => "username",
+            ThrottleScope::Ip => "ip",
+        }
+    }
+}
+
+pub enum ThrottleDecision {
+    /// Go ahead once `delay` has passed.
+    Allowed {
+        delay: std::time::Duration,
+    },
+    LockedOut {
+        until: DateTime<Utc>,
+    },
+}
+
+/// Decide whether a login attempt for `username` from `client_ip` may go ahead, based on the
+/// failures recorded by `record_login_failure`.
+#[tracing::instrument(name = "Check login throttle", skip(settings, db_pool))]
+pub async fn check_login_throttle(
+    username: &str,
+    client_ip: &str,
+    settings: &LoginThrottleSettings,
+    db_pool: &PgPool,
+) -> Result<ThrottleDecision> {
+    let now = Utc::now();
+    let rows = sqlx::query!(
+        r#"
+        SELECT failed_attempts, last_failed_at, locked_until
+        FROM login_failures
+        WHERE (scope = 'username' AND subject = $1) OR (scope = 'ip' AND subject = $2)
+        "#,
+        username,
+        client_ip
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve login failures")?;
+
+    if let Some(until) = rows.iter().filter_map(|r| r.locked_until).max() {
+        if until > now {
+            return Ok(ThrottleDecision::LockedOut { until });
+        }
+    }
+
+    let recent_failures = rows
+        .iter()
+        .filter(|r| r.last_failed_at > now - settings.failure_window())
+        .map(|r| r.failed_attempts)
+        .max()
+        .unwrap_or(0);
+    Ok(ThrottleDecision::Allowed {
+        delay: settings.delay_after(recent_failures),
+    })
+}
+
+/// How many minutes are left of a lockout, rounded up, to tell users when to try again.
+pub fn lockout_minutes(until: DateTime<Utc>) -> i64 {
+    (((until - Utc::now()).num_seconds() + 59) / 60).max(1)
+}
+
+/// Count a failed attempt against both the username and the IP address, locking either out
+/// once it reaches its limit.
+#[tracing::instrument(name = "Record login failure", skip(settings, db_pool))]
+pub async fn record_login_failure(
+    username: &str,
+    client_ip: &str,
+    settings: &LoginThrottleSettings,
+    db_pool: &PgPool,
+) -> Result<()> {
+    let subjects = [
+        (
+            ThrottleScope::Username,
+            username,
+            settings.max_failures_per_username,
+        ),
+        (ThrottleScope::Ip, client_ip, settings.max_failures_per_ip),
+    ];
+    for (scope, subject, max_failures) in subjects {
+        record_failure(scope, subject, max_failures, settings, db_pool).await?;
+    }
+
+    Ok(())
+}
+
+/// Forget the failures for a username once someone logs in with it, second factor included.
+#[tracing::instrument(name = "Clear login failures", skip(db_pool))]
+pub async fn clear_login_failures(username: &str, db_pool: &PgPool) -> Result<()> {
+    sqlx::query!(
+        r#"DELETE FROM login_failures WHERE scope = 'username' AND subject = $1"#,
+        username
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to clear login failures")?;
+
+    Ok(())
+}
+
+async fn record_failure(
+    scope: ThrottleScope,
+    subject: &str,
+    max_failures: i32,
+    settings: &LoginThrottleSettings,
+    db_pool: &PgPool,
+) -> Result<()> {
+    let now = Utc::now();
+    let failed_attempts = sqlx::query!(
+        r#"
+        INSERT INTO login_failures (scope, subject, failed_attempts, last_failed_at)
+        VALUES ($1, $2, 1, $3)
+        ON CONFLICT (scope, subject) DO UPDATE
+        SET failed_attempts = CASE
+                WHEN login_failures.last_failed_at < $4 THEN 1
+                ELSE login_failures.failed_attempts + 1
+            END,
+            last_failed_at = EXCLUDED.last_failed_at
+        RETURNING failed_attempts
+        "#,
+        scope.as_str(),
+        subject,
+        now,
+        now - settings.failure_window(),
+    )
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to record login failure")?
+    .failed_attempts;
+
+    if failed_attempts >= max_failures {
+        let locked_until = now + settings.lockout();
+        sqlx::query!(
+            r#"
+            UPDATE login_failures
+            SET failed_attempts = 0, locked_until = $3
+            WHERE scope = $1 AND subject = $2
+            "#,
+            scope.as_str(),
+            subject,
+            locked_until,
+        )
+        .execute(db_pool)
+        .await
+        .context("Failed to lock out login attempts")?;
+
+        tracing::warn!(
+            lockout_scope = scope.as_str(),
+            lockout_subject = subject,
+            failed_attempts,
+            %locked_until,
+            "Locked out logins after too many failed attempts"
+        );
+    }
+
+    Ok(())
+}
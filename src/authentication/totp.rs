@@ -0,0 +1,137 @@
+use {
+    hmac::{Hmac, Mac},
+    rand::RngCore,
+    secrecy::{ExposeSecret, Secret},
+    sha1::Sha1,
+};
+
+/// Shown by authenticator apps next to the account name.
+const ISSUER: &str = "zero2prod";
+const DIGITS: u32 = 6;
+const PERIOD_SECONDS: i64 = 30;
+/// Accept codes from one step either side of the current one, to allow for clock drift.
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+
+#[derive(Debug, thiserror::Error)]
+#[error("A TOTP secret must be base32-encoded")]
+pub struct TotpSecretError;
+
+/// The shared secret of a time-based one-time password authenticator (RFC 6238), using the
+/// parameters every authenticator app supports: HMAC-SHA1, 6 digits and a 30 second period.
+pub struct TotpSecret(Secret<Vec<u8>>);
+
+impl TotpSecret {
+    /// Generate a new 160-bit secret, the size RFC 4226 recommends.
+    pub fn generate() -> Self {
+        let mut bytes = vec![0; 20];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self(Secret::new(bytes))
+    }
+
+    pub fn from_base32(s: &str) -> Result<Self, TotpSecretError> {
+        base32::decode(base32::Alphabet::RFC4648 { padding: false }, s)
+            .filter(|bytes| !bytes.is_empty())
+            .map(|bytes| Self(Secret::new(bytes)))
+            .ok_or(TotpSecretError)
+    }
+
+    pub fn to_base32(&self) -> Secret<String> {
+        Secret::new(base32::encode(
+            base32::Alphabet::RFC4648 { padding: false },
+            self.0.expose_secret(),
+        ))
+    }
+
+    /// The URI authenticator apps import, usually through a QR code.
+    pub fn otpauth_uri(&self, account_name: &str) -> Secret<String> {
+        Secret::new(format!(
+            "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={PERIOD_SECONDS}",
+            issuer = urlencoding::encode(ISSUER),
+            account = urlencoding::encode(account_name),
+            secret = self.to_base32().expose_secret(),
+        ))
+    }
+
+    /// The time step that `unix_time` falls in.
+    pub fn step_at(unix_time: i64) -> i64 {
+        unix_time.div_euclid(PERIOD_SECONDS)
+    }
+
+    /// The code for a time step.
+    pub fn code(&self, step: i64) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.0.expose_secret())
+            .expect("HMAC accepts keys of any length");
+        mac.update(&step.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        // Dynamic truncation, RFC 4226 section 5.3
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary =
+            u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(DIGITS),
+            width = DIGITS as usize
+        )
+    }
+
+    /// Check `code` against the steps around `unix_time`, returning the step it matched.
+    pub fn verify(&self, code: &str, unix_time: i64) -> Option<i64> {
+        let current = Self::step_at(unix_time);
+        (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+            .find(|step| self.code(*step) == code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TotpSecret;
+    use {
+        claim::{assert_none, assert_some_eq},
+        secrecy::{ExposeSecret, Secret},
+    };
+
+    fn rfc_secret() -> TotpSecret {
+        TotpSecret(Secret::new(b"12345678901234567890".to_vec()))
+    }
+
+    #[test]
+    fn codes_match_the_rfc_6238_test_vectors() {
+        // The RFC lists 8 digit codes, these are their last 6 digits
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ];
+        for (unix_time, code) in vectors {
+            assert_eq!(rfc_secret().code(TotpSecret::step_at(unix_time)), code);
+        }
+    }
+
+    #[test]
+    fn codes_from_neighbouring_steps_are_accepted() {
+        let secret = rfc_secret();
+        assert_some_eq!(secret.verify("081804", 1111111109 + 30), 37037036);
+        assert_some_eq!(secret.verify("081804", 1111111109 - 30), 37037036);
+        assert_none!(secret.verify("081804", 1111111109 + 60));
+    }
+
+    #[test]
+    fn secrets_round_trip_through_base32() {
+        let secret = TotpSecret::generate();
+        let decoded = TotpSecret::from_base32(secret.to_base32().expose_secret()).unwrap();
+        assert_eq!(decoded.code(1), secret.code(1));
+    }
+
+    #[test]
+    fn otpauth_uris_carry_the_secret_and_account() {
+        let uri = rfc_secret().otpauth_uri("ursula le guin");
+        assert_eq!(
+            uri.expose_secret(),
+            "otpauth://totp/zero2prod:ursula%20le%20guin?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+            &issuer=zero2prod&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
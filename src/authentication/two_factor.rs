@@ -0,0 +1,266 @@
+use crate::{
+    authentication::{compute_password_hash, PasswordHashingParams, TotpSecret},
+    telemetry::spawn_blocking_with_tracing,
+};
+
+use {
+    anyhow::{Context, Result},
+    argon2::{Argon2, PasswordHash, PasswordVerifier},
+    chrono::Utc,
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
+    secrecy::{ExposeSecret, Secret},
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+#[tracing::instrument(name = "Get TOTP secret", skip(db_pool))]
+pub async fn get_totp_secret(user_id: &Uuid, db_pool: &PgPool) -> Result<Option<TotpSecret>> {
+    let row = sqlx::query!(
+        r#"SELECT totp_secret FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to retrieve TOTP secret")?;
+
+    row.totp_secret
+        .map(|s| TotpSecret::from_base32(&s))
+        .transpose()
+        .context("Invalid TOTP secret stored in the database")
+}
+
+/// Turn two-factor authentication on with a secret the user confirmed with a code for
+/// `confirmed_step`, returning a fresh set of recovery codes. They are only stored hashed, so
+/// this is the only time they can be shown.
+#[tracing::instrument(
+    name = "Enable two-factor authentication",
+    skip(secret, hashing_params, db_pool)
+)]
+pub async fn enable_two_factor(
+    user_id: &Uuid,
+    secret: &TotpSecret,
+    confirmed_step: i64,
+    hashing_params: &PasswordHashingParams,
+    db_pool: &PgPool,
+) -> Result<Vec<Secret<String>>> {
+    let codes: Vec<_> = std::iter::repeat_with(generate_recovery_code)
+        .take(RECOVERY_CODE_COUNT)
+        .collect();
+    let hashes: Vec<Secret<String>> = {
+        let codes: Vec<_> = codes
+            .iter()
+            .map(|c| Secret::new(normalize_recovery_code(c.expose_secret())))
+            .collect();
+        let hashing_params = hashing_params.clone();
+        spawn_blocking_with_tracing(move || {
+            codes
+                .iter()
+                .map(|code| compute_password_hash(code, &hashing_params))
+                .collect::<Result<_>>()
+        })
+        .await
+        .context("Failed to spawn blocking task")?
+        .context("Failed to hash recovery codes")?
+    };
+
+    let encoded_secret = secret.to_base32();
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")?;
+    sqlx::query!(
+        r#"UPDATE users SET totp_secret = $1, totp_last_step = $2 WHERE user_id = $3"#,
+        encoded_secret.expose_secret(),
+        confirmed_step,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store TOTP secret")?;
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete previous recovery codes")?;
+    for hash in hashes {
+        sqlx::query!(
+            r#"
+            INSERT INTO recovery_codes (recovery_code_id, user_id, code_hash)
+            VALUES ($1, $2, $3)
+            "#,
+            Uuid::new_v4(),
+            user_id,
+            hash.expose_secret()
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to store recovery code")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to enable two-factor authentication")?;
+
+    Ok(codes)
+}
+
+/// Check a 6-digit authenticator code or a recovery code, using it up if it is valid.
+#[tracing::instrument(name = "Verify second factor", skip(code, db_pool))]
+pub async fn verify_second_factor(
+    user_id: &Uuid,
+    code: Secret<String>,
+    db_pool: &PgPool,
+) -> Result<bool> {
+    let code = code.expose_secret().trim();
+    if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+        verify_totp_code(user_id, code, db_pool).await
+    } else {
+        verify_recovery_code(user_id, Secret::new(normalize_recovery_code(code)), db_pool).await
+    }
+}
+
+#[tracing::instrument(name = "Count unused recovery codes", skip(db_pool))]
+pub async fn count_unused_recovery_codes(user_id: &Uuid, db_pool: &PgPool) -> Result<i64> {
+    let count = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM recovery_codes
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to count recovery codes")?
+    .count;
+
+    Ok(count)
+}
+
+async fn verify_totp_code(user_id: &Uuid, code: &str, db_pool: &PgPool) -> Result<bool> {
+    let secret = match get_totp_secret(user_id, db_pool).await? {
+        Some(secret) => secret,
+        None => return Ok(false),
+    };
+    let step = match secret.verify(code, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+
+    // Only move forward, so that a code can't be used twice
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_last_step = $1
+        WHERE user_id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)
+        "#,
+        step,
+        user_id
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to record TOTP step")?
+    .rows_affected();
+
+    Ok(n_updated == 1)
+}
+
+async fn verify_recovery_code(
+    user_id: &Uuid,
+    code: Secret<String>,
+    db_pool: &PgPool,
+) -> Result<bool> {
+    let candidates: Vec<_> = sqlx::query!(
+        r#"
+        SELECT recovery_code_id, code_hash
+        FROM recovery_codes
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve recovery codes")?
+    .into_iter()
+    .map(|r| (r.recovery_code_id, Secret::new(r.code_hash)))
+    .collect();
+
+    let matched = spawn_blocking_with_tracing(move || {
+        candidates
+            .into_iter()
+            .find(|(_, hash)| recovery_code_matches(&code, hash))
+            .map(|(id, _)| id)
+    })
+    .await
+    .context("Failed to spawn blocking task")?;
+    let recovery_code_id = match matched {
+        Some(id) => id,
+        None => return Ok(false),
+    };
+
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = $1
+        WHERE recovery_code_id = $2 AND used_at IS NULL
+        "#,
+        Utc::now(),
+        recovery_code_id
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to mark recovery code as used")?
+    .rows_affected();
+
+    Ok(n_updated == 1)
+}
+
+fn recovery_code_matches(code: &Secret<String>, hash: &Secret<String>) -> bool {
+    match PasswordHash::new(hash.expose_secret()) {
+        Ok(hash) => Argon2::default()
+            .verify_password(code.expose_secret().as_bytes(), &hash)
+            .is_ok(),
+        Err(e) => {
+            tracing::error!(error = %e, "Invalid recovery code hash stored in the database");
+            false
+        }
+    }
+}
+
+/// Codes are shown as `xxxxx-xxxxx`, but typing them without the dash or in upper case is fine.
+fn generate_recovery_code() -> Secret<String> {
+    let mut rng = thread_rng();
+    let code: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .take(RECOVERY_CODE_LENGTH)
+        .collect();
+    let (first, second) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+    Secret::new(format!("{}-{}", first, second))
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_recovery_code, normalize_recovery_code};
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn recovery_codes_are_normalized_before_hashing() {
+        let code = generate_recovery_code();
+        let code = code.expose_secret();
+        assert_eq!(code.len(), 11);
+        assert_eq!(
+            normalize_recovery_code(&code.to_uppercase()),
+            code.replace('-', "")
+        );
+        assert_eq!(normalize_recovery_code(" abcde-fghij "), "abcdefghij");
+    }
+}
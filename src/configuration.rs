@@ -1,6 +1,13 @@
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::{BreachedPasswords, PasswordPolicy, SubscriberEmail},
+    email_client::{EmailSender, FileSinkClient, PostmarkClient, SmtpClient, SmtpTls},
+};
+
+use std::sync::Arc;
 
 use {
+    chrono_tz::Tz,
+    ipnet::IpNet,
     secrecy::{ExposeSecret, Secret},
     serde::Deserialize,
     serde_aux::field_attributes::deserialize_number_from_string,
@@ -70,6 +77,11 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub login_throttle: LoginThrottleSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub password_policy: PasswordPolicySettings,
+    pub subscription_rate_limit: SubscriptionRateLimitSettings,
+    pub password_reset_rate_limit: PasswordResetRateLimitSettings,
     pub redis_uri: Secret<String>,
 }
 
@@ -80,6 +92,101 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Confirmation links stop working after this many hours.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub confirmation_token_expiry_hours: i64,
+    /// Collaborator invitation links stop working after this many hours.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub invitation_expiry_hours: i64,
+    /// Password reset links stop working after this many minutes.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub password_reset_expiry_minutes: i64,
+    /// Subscriptions still pending confirmation after this many days are deleted.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub unconfirmed_subscription_retention_days: i64,
+    /// Logged in users are logged out after this many minutes without a request.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub session_idle_timeout_minutes: i64,
+    /// Logged in users have to log in again this many hours after they last did, however active.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub session_absolute_timeout_hours: i64,
+    /// Reverse proxies whose `X-Forwarded-For` header is trusted, see `utils::client_ip`.
+    pub trusted_proxies: Vec<IpNet>,
+    /// The time zone send times of scheduled issues are entered and shown in.
+    pub timezone: Tz,
+}
+
+/// Limits on failed login attempts, see `authentication::check_login_throttle`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LoginThrottleSettings {
+    /// Failed attempts for a single username before it is locked out.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_username: i32,
+    /// Failed attempts from a single IP address, across all usernames, before it is locked out.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_ip: i32,
+    /// Failures older than this are forgotten.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_window_seconds: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_seconds: i64,
+    /// How long to hold back the attempt after a failure, doubled for every further failure.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_millis: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_millis: u64,
+}
+
+/// Argon2id costs for new password hashes. Raising them upgrades existing hashes as their users
+/// log in.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PasswordHashingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_cost_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub time_cost: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+/// What new passwords have to look like, see `domain::PasswordPolicy`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PasswordPolicySettings {
+    /// The lowest acceptable strength score, from 0 to 4.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub minimum_strength: u8,
+    /// A list of breached password hashes, in the Pwned Passwords format.
+    pub breached_passwords_path: Option<String>,
+}
+
+/// Limits on `POST /subscriptions`, which sends an email on every call.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SubscriptionRateLimitSettings {
+    /// Prepended to the Redis keys, so that deployments sharing a Redis don't share limits.
+    pub key_prefix: String,
+    pub per_ip: RateLimitSettings,
+    pub per_email: RateLimitSettings,
+}
+
+/// Limits on `POST /login/forgot`, which can send an email on every call.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PasswordResetRateLimitSettings {
+    /// Prepended to the Redis keys, so that deployments sharing a Redis don't share limits.
+    pub key_prefix: String,
+    pub per_ip: RateLimitSettings,
+    /// Applies to the username that was asked for, whether or not it exists.
+    pub per_account: RateLimitSettings,
+}
+
+/// A token bucket.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RateLimitSettings {
+    /// How many requests can be made in a burst.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: u32,
+    /// One more request is allowed every time this many seconds pass, up to `capacity`.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub refill_interval_seconds: u64,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -95,11 +202,37 @@ pub struct DatabaseSettings {
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct EmailClientSettings {
+    pub backend: EmailBackend,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub timeout_millis: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub file_sink: Option<FileSinkSettings>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailBackend {
+    Postmark,
+    Smtp,
+    File,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FileSinkSettings {
+    pub directory: String,
 }
 
 impl DatabaseSettings {
@@ -125,6 +258,66 @@ impl DatabaseSettings {
     }
 }
 
+impl ApplicationSettings {
+    pub fn confirmation_token_expiry(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.confirmation_token_expiry_hours)
+    }
+
+    pub fn invitation_expiry(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.invitation_expiry_hours)
+    }
+
+    pub fn password_reset_expiry(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.password_reset_expiry_minutes)
+    }
+
+    pub fn unconfirmed_subscription_retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.unconfirmed_subscription_retention_days)
+    }
+
+    pub fn session_idle_timeout(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.session_idle_timeout_minutes)
+    }
+
+    pub fn session_absolute_timeout(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.session_absolute_timeout_hours)
+    }
+}
+
+impl LoginThrottleSettings {
+    pub fn failure_window(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.failure_window_seconds)
+    }
+
+    pub fn lockout(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.lockout_seconds)
+    }
+
+    /// The delay before processing an attempt, given the number of recent failures.
+    pub fn delay_after(&self, failures: i32) -> std::time::Duration {
+        if failures <= 0 {
+            return std::time::Duration::ZERO;
+        }
+        let factor = 2u64.saturating_pow(failures as u32 - 1);
+        let delay = self.base_delay_millis.saturating_mul(factor);
+        std::time::Duration::from_millis(delay.min(self.max_delay_millis))
+    }
+}
+
+impl PasswordPolicySettings {
+    pub fn policy(&self) -> Result<PasswordPolicy, anyhow::Error> {
+        let breached_passwords = match &self.breached_passwords_path {
+            Some(path) => BreachedPasswords::from_file(path)?,
+            None => BreachedPasswords::default(),
+        };
+
+        Ok(PasswordPolicy::new(
+            self.minimum_strength,
+            breached_passwords,
+        ))
+    }
+}
+
 impl EmailClientSettings {
     pub fn sender(&self) -> Option<SubscriberEmail> {
         SubscriberEmail::parse(self.sender_email.clone())
@@ -133,4 +326,78 @@ impl EmailClientSettings {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_millis)
     }
+
+    pub fn client(self) -> Arc<dyn EmailSender> {
+        let sender_email = self.sender().expect("Invalid sender email address");
+        let timeout = self.timeout();
+        match self.backend {
+            EmailBackend::Postmark => Arc::new(
+                PostmarkClient::new(
+                    self.base_url,
+                    sender_email,
+                    self.authorization_token,
+                    timeout,
+                )
+                .expect("Failed to build Postmark email client"),
+            ),
+            EmailBackend::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("The SMTP backend requires `email_client.smtp` settings");
+                let credentials = smtp.username.zip(smtp.password);
+                Arc::new(
+                    SmtpClient::new(
+                        &smtp.host,
+                        smtp.port,
+                        smtp.tls,
+                        credentials,
+                        sender_email,
+                        timeout,
+                    )
+                    .expect("Failed to build SMTP email client"),
+                )
+            }
+            EmailBackend::File => {
+                let file_sink = self
+                    .file_sink
+                    .expect("The file backend requires `email_client.file_sink` settings");
+                Arc::new(FileSinkClient::new(file_sink.directory, sender_email))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoginThrottleSettings;
+    use std::time::Duration;
+
+    fn settings() -> LoginThrottleSettings {
+        LoginThrottleSettings {
+            max_failures_per_username: 5,
+            max_failures_per_ip: 20,
+            failure_window_seconds: 900,
+            lockout_seconds: 900,
+            base_delay_millis: 250,
+            max_delay_millis: 4000,
+        }
+    }
+
+    #[test]
+    fn login_delays_double_with_every_failure_up_to_a_maximum() {
+        let settings = settings();
+        let delays: Vec<_> = (0..8).map(|n| settings.delay_after(n)).collect();
+        assert_eq!(
+            delays,
+            [0, 250, 500, 1000, 2000, 4000, 4000, 4000].map(Duration::from_millis)
+        );
+    }
+
+    #[test]
+    fn login_delays_do_not_overflow() {
+        assert_eq!(
+            settings().delay_after(i32::MAX),
+            Duration::from_millis(4000)
+        );
+    }
 }
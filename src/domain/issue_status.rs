@@ -0,0 +1,66 @@
+/// Where a newsletter issue is in its lifecycle.
+///
+/// Issues start as drafts, which are the only ones that can be edited. Sending one queues up a
+/// delivery per confirmed subscriber, and the issue is sent once the queue has been worked through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueStatus {
+    Draft,
+    /// Waiting to be sent at a later time.
+    Scheduled,
+    /// Deliveries are queued up.
+    Sending,
+    /// Every delivery has been attempted.
+    Sent,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0} is not a valid issue status")]
+pub struct IssueStatusValidationError(String);
+
+impl IssueStatus {
+    pub const ALL: [IssueStatus; 4] = [
+        IssueStatus::Draft,
+        IssueStatus::Scheduled,
+        IssueStatus::Sending,
+        IssueStatus::Sent,
+    ];
+
+    pub fn parse(s: &str) -> Result<Self, IssueStatusValidationError> {
+        match s {
+            "draft" => Ok(IssueStatus::Draft),
+            "scheduled" => Ok(IssueStatus::Scheduled),
+            "sending" => Ok(IssueStatus::Sending),
+            "sent" => Ok(IssueStatus::Sent),
+            other => Err(IssueStatusValidationError(other.to_owned())),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Draft => "draft",
+            IssueStatus::Scheduled => "scheduled",
+            IssueStatus::Sending => "sending",
+            IssueStatus::Sent => "sent",
+        }
+    }
+}
+
+impl std::fmt::Display for IssueStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueStatus;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn statuses_round_trip_through_their_names() {
+        for status in IssueStatus::ALL {
+            assert_ok_eq!(IssueStatus::parse(status.as_str()), status);
+        }
+        assert_err!(IssueStatus::parse("published"));
+    }
+}
@@ -1,10 +1,21 @@
+mod issue_status;
 mod new_subscriber;
+mod newsletter_content;
+mod password_policy;
+mod password_strength;
+mod send_time;
 mod subscriber_email;
 mod subscriber_name;
 mod subscription_token;
 
 pub use {
+    issue_status::{IssueStatus, IssueStatusValidationError},
     new_subscriber::NewSubscriber,
+    newsletter_content::{
+        render_html, render_text, NewsletterContent, NewsletterContentValidationError,
+    },
+    password_policy::{BreachedPasswords, PasswordPolicy, PasswordPolicyViolation},
+    send_time::{SendTime, SendTimeValidationError},
     subscriber_email::SubscriberEmail,
     subscriber_name::{SubscriberName, SubscriberNameValidationError},
     subscription_token::{SubTokenValidationError, SubscriptionToken},
@@ -0,0 +1,288 @@
+use std::fmt::Write;
+
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag};
+
+/// The body of a newsletter issue, in the HTML and plain-text versions that go out to subscribers.
+///
+/// Authors write it in Markdown, which both versions are rendered from. Either version can be
+/// replaced by one written by hand, and with both of them replaced no Markdown is needed.
+#[derive(Clone, Debug)]
+pub struct NewsletterContent {
+    markdown: Option<String>,
+    html: String,
+    text: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NewsletterContentValidationError {
+    #[error("Please write the issue in Markdown, or provide both its HTML and text versions.")]
+    Missing,
+}
+
+impl NewsletterContent {
+    /// Blank fields count as missing, since that is how forms submit empty text areas.
+    pub fn parse(
+        markdown: Option<String>,
+        html_override: Option<String>,
+        text_override: Option<String>,
+    ) -> Result<Self, NewsletterContentValidationError> {
+        let non_blank = |s: Option<String>| s.filter(|s| !s.trim().is_empty());
+        let markdown = non_blank(markdown);
+        let (html, text) = match (
+            &markdown,
+            non_blank(html_override),
+            non_blank(text_override),
+        ) {
+            (_, Some(html), Some(text)) => (html, text),
+            (Some(markdown), html, text) => (
+                html.unwrap_or_else(|| render_html(markdown)),
+                text.unwrap_or_else(|| render_text(markdown)),
+            ),
+            (None, _, _) => return Err(NewsletterContentValidationError::Missing),
+        };
+        Ok(Self {
+            markdown,
+            html,
+            text,
+        })
+    }
+
+    pub fn markdown(&self) -> Option<&str> {
+        self.markdown.as_deref()
+    }
+
+    pub fn html(&self) -> &str {
+        &self.html
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// Render Markdown to HTML, stripping anything from embedded HTML that could run scripts or
+/// otherwise misbehave in a mail client.
+pub fn render_html(markdown: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new(markdown));
+    ammonia::clean(&html)
+}
+
+/// Render Markdown to plain text, listing where links point to as footnotes at the end.
+pub fn render_text(markdown: &str) -> String {
+    let mut renderer = TextRenderer::default();
+    for event in Parser::new(markdown) {
+        renderer.push(event);
+    }
+    renderer.finish()
+}
+
+#[derive(Default)]
+struct TextRenderer {
+    out: String,
+    /// Where each element that is still open starts in `out`.
+    starts: Vec<usize>,
+    /// The number of the next item of each open list, `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+    /// Link targets, in the order of their footnote numbers.
+    links: Vec<String>,
+}
+
+impl TextRenderer {
+    fn push(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) | Event::Code(text) => self.out.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => self.out.push('\n'),
+            Event::Rule => {
+                self.out.push_str("----------");
+                self.end_block();
+            }
+            Event::TaskListMarker(checked) => {
+                self.out.push_str(if checked { "[x] " } else { "[ ] " })
+            }
+            // Embedded HTML is markup rather than content
+            Event::Html(_) | Event::FootnoteReference(_) => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::List(first_number) => {
+                // A list nested in a tight list item follows the item's text directly
+                self.end_line();
+                self.lists.push(first_number);
+            }
+            Tag::Item => {
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                self.out.push_str(&indent);
+                self.out.push_str(&marker);
+            }
+            _ => {}
+        }
+        self.starts.push(self.out.len());
+    }
+
+    fn end(&mut self, tag: Tag) {
+        let start = self.starts.pop().unwrap_or_default();
+        match tag {
+            Tag::Paragraph => self.end_block(),
+            Tag::Heading(level, ..) => {
+                let underline = match level {
+                    HeadingLevel::H1 => "=",
+                    HeadingLevel::H2 => "-",
+                    _ => "",
+                };
+                let width = self.out[start..].chars().count();
+                self.out.push('\n');
+                self.out.push_str(&underline.repeat(width));
+                self.end_block();
+            }
+            Tag::BlockQuote => self.prefix_lines(start, "> "),
+            Tag::CodeBlock(_) => self.prefix_lines(start, "    "),
+            Tag::List(_) => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.end_block();
+                }
+            }
+            Tag::Item => self.end_line(),
+            Tag::Link(_, url, _) | Tag::Image(_, url, _) => {
+                // Autolinks already show where they point to
+                let text = &self.out[start..];
+                if text != url.as_ref() && format!("mailto:{}", text) != url.as_ref() {
+                    let number = match self.links.iter().position(|link| *link == *url) {
+                        Some(index) => index + 1,
+                        None => {
+                            self.links.push(url.to_string());
+                            self.links.len()
+                        }
+                    };
+                    write!(self.out, " [{}]", number).unwrap();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Prefix each line of the block that starts at `start`.
+    fn prefix_lines(&mut self, start: usize, prefix: &str) {
+        let block = self.out.split_off(start);
+        for line in block.trim_end().lines() {
+            writeln!(self.out, "{}{}", prefix, line).unwrap();
+        }
+        self.end_block();
+    }
+
+    fn end_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    /// Leave a blank line before whatever comes next.
+    fn end_block(&mut self) {
+        self.end_line();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut text = self.out.trim_end().to_string();
+        if !self.links.is_empty() {
+            text.push('\n');
+            for (index, link) in self.links.iter().enumerate() {
+                write!(text, "\n[{}] {}", index + 1, link).unwrap();
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_html, render_text, NewsletterContent};
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn links_are_footnoted_in_the_text_version() {
+        let markdown =
+            "Read [the post](https://example.com/post), [twice](https://example.com/post) \
+            and then <https://example.com>.";
+        assert_eq!(
+            render_text(markdown),
+            "Read the post [1], twice [1] and then https://example.com.\n\n\
+            [1] https://example.com/post"
+        );
+    }
+
+    #[test]
+    fn the_text_version_keeps_the_structure() {
+        let markdown = "# Title\n\nSome *emphasis* and `code`.\n\n\
+            - one\n- two\n  1. nested\n\n\
+            > quoted\n> text\n\n\
+            ```\nlet x = 1;\n```\n";
+        assert_eq!(
+            render_text(markdown),
+            "Title\n=====\n\n\
+            Some emphasis and code.\n\n\
+            - one\n- two\n  1. nested\n\n\
+            > quoted\n> text\n\n    \
+            let x = 1;"
+        );
+    }
+
+    #[test]
+    fn the_html_version_is_sanitised() {
+        let html = render_html(
+            "Hi <script>alert(1)</script><b onclick=\"alert(1)\">there</b>, \
+            [click me](javascript:alert(1))",
+        );
+        assert!(html.contains("<b>there</b>"));
+        assert!(!html.contains("script"));
+        assert!(!html.contains("onclick"));
+        assert!(!html.contains("javascript:"));
+    }
+
+    #[test]
+    fn overrides_replace_the_rendered_versions() {
+        let markdown = Some("Hello **world**".to_string());
+
+        let content = assert_ok!(NewsletterContent::parse(markdown.clone(), None, None));
+        assert_eq!(content.html(), "<p>Hello <strong>world</strong></p>\n");
+        assert_eq!(content.text(), "Hello world");
+
+        let content = assert_ok!(NewsletterContent::parse(
+            markdown,
+            Some("<p>Custom</p>".to_string()),
+            Some(" ".to_string()),
+        ));
+        assert_eq!(content.html(), "<p>Custom</p>");
+        assert_eq!(content.text(), "Hello world");
+    }
+
+    #[test]
+    fn markdown_is_required_unless_both_versions_are_given() {
+        assert_err!(NewsletterContent::parse(None, None, None));
+        assert_err!(NewsletterContent::parse(
+            Some("  ".to_string()),
+            Some("<p>HTML</p>".to_string()),
+            None
+        ));
+        let content = assert_ok!(NewsletterContent::parse(
+            None,
+            Some("<p>HTML</p>".to_string()),
+            Some("Text".to_string()),
+        ));
+        assert_eq!(content.markdown(), None);
+    }
+}
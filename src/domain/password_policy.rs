@@ -0,0 +1,204 @@
+use crate::domain::password_strength;
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use {
+    anyhow::Context,
+    secrecy::{ExposeSecret, Secret},
+    sha1::{Digest, Sha1},
+};
+
+const MIN_LENGTH: usize = 12;
+const MAX_LENGTH: usize = 128;
+/// Usernames shorter than this would rule out too many passwords by coincidence.
+const MIN_USERNAME_LENGTH: usize = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PasswordPolicyViolation {
+    #[error("The new password must be at least 12 characters")]
+    TooShort,
+    #[error("The new password must be no more than 128 chars")]
+    TooLong,
+    #[error("The new password must not contain your username.")]
+    ContainsUsername,
+    #[error("This password has appeared in a data breach, please choose another one.")]
+    Breached,
+    #[error("This password is too easy to guess. {0}")]
+    TooWeak(&'static str),
+}
+
+/// The rules every new password has to follow, wherever it is set.
+pub struct PasswordPolicy {
+    /// From 0 to 4, see `password_strength::Strength::score`.
+    minimum_strength: u8,
+    breached_passwords: BreachedPasswords,
+}
+
+impl PasswordPolicy {
+    pub fn new(minimum_strength: u8, breached_passwords: BreachedPasswords) -> Self {
+        Self {
+            minimum_strength,
+            breached_passwords,
+        }
+    }
+
+    /// Check a password that `username` wants to use.
+    pub fn check(
+        &self,
+        password: &Secret<String>,
+        username: &str,
+    ) -> Result<(), PasswordPolicyViolation> {
+        let password = password.expose_secret();
+
+        let length = password.chars().count();
+        if length < MIN_LENGTH {
+            return Err(PasswordPolicyViolation::TooShort);
+        }
+        if length > MAX_LENGTH {
+            return Err(PasswordPolicyViolation::TooLong);
+        }
+
+        let username = username.trim().to_lowercase();
+        if username.chars().count() >= MIN_USERNAME_LENGTH
+            && password.to_lowercase().contains(&username)
+        {
+            return Err(PasswordPolicyViolation::ContainsUsername);
+        }
+
+        if self.breached_passwords.contains(password) {
+            return Err(PasswordPolicyViolation::Breached);
+        }
+
+        let strength = password_strength::estimate(password);
+        if strength.score < self.minimum_strength {
+            return Err(PasswordPolicyViolation::TooWeak(strength.suggestion));
+        }
+
+        Ok(())
+    }
+}
+
+/// SHA-1 hashes of passwords known to have leaked, in the format of the Pwned Passwords
+/// downloads: one upper-case hex hash per line, optionally followed by `:{count}`.
+///
+/// Hashes are indexed by their first five characters, the same split as the k-anonymity range
+/// API, so a list built from range queries can be loaded as is.
+#[derive(Default)]
+pub struct BreachedPasswords(HashMap<String, HashSet<String>>);
+
+impl BreachedPasswords {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read breached passwords from {:?}", path))?;
+
+        Self::parse(&contents)
+            .with_context(|| format!("Invalid breached passwords list in {:?}", path))
+    }
+
+    pub fn parse(s: &str) -> Result<Self, anyhow::Error> {
+        let mut hashes: HashMap<String, HashSet<String>> = HashMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let hash = line.split(':').next().unwrap_or(line).to_uppercase();
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                anyhow::bail!("Line {} is not a SHA-1 hash", i + 1);
+            }
+            let (prefix, suffix) = hash.split_at(5);
+            hashes
+                .entry(prefix.to_owned())
+                .or_default()
+                .insert(suffix.to_owned());
+        }
+
+        Ok(Self(hashes))
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+        self.0
+            .get(prefix)
+            .map_or(false, |suffixes| suffixes.contains(suffix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BreachedPasswords, PasswordPolicy, PasswordPolicyViolation};
+    use {claim::assert_ok, secrecy::Secret};
+
+    // SHA-1 of "correct horse battery staple"
+    const BREACHED: &str = "ABF7AAD6438836DBE526AA231ABDE2D0EEF74D42:312\n";
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy::new(3, BreachedPasswords::parse(BREACHED).unwrap())
+    }
+
+    fn check(password: &str, username: &str) -> Result<(), PasswordPolicyViolation> {
+        policy().check(&Secret::new(password.to_owned()), username)
+    }
+
+    #[test]
+    fn strong_passwords_are_accepted() {
+        assert_ok!(check("k8#Vq2!zLw9@", "ursula"));
+        assert_ok!(check("tangerine violin marsupial", "ursula"));
+    }
+
+    #[test]
+    fn passwords_must_be_between_12_and_128_characters() {
+        assert!(matches!(
+            check("k8#Vq2!zLw9", "ursula"),
+            Err(PasswordPolicyViolation::TooShort)
+        ));
+        assert!(matches!(
+            check(&"k8#Vq2!zLw9@".repeat(11), "ursula"),
+            Err(PasswordPolicyViolation::TooLong)
+        ));
+        // Length is counted in characters, not bytes
+        assert_ok!(check("ё8#Vq2!zLw9@", "ursula"));
+    }
+
+    #[test]
+    fn passwords_containing_the_username_are_rejected() {
+        assert!(matches!(
+            check("k8#Vq2!URSULAzLw9@", "ursula"),
+            Err(PasswordPolicyViolation::ContainsUsername)
+        ));
+    }
+
+    #[test]
+    fn short_usernames_are_not_looked_for() {
+        assert_ok!(check("k8#Vq2!zLw9@", "k8"));
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected() {
+        assert!(matches!(
+            check("correct horse battery staple", "ursula"),
+            Err(PasswordPolicyViolation::Breached)
+        ));
+    }
+
+    #[test]
+    fn weak_passwords_are_rejected_with_a_suggestion() {
+        match check("qwertyuiop123", "ursula") {
+            Err(PasswordPolicyViolation::TooWeak(suggestion)) => {
+                assert!(suggestion.contains("keyboard"))
+            }
+            other => panic!("Expected a weak password, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed_breached_password_lists_are_rejected() {
+        assert!(BreachedPasswords::parse("not a hash\n").is_err());
+        assert!(BreachedPasswords::parse("# comment\n\n").is_ok());
+    }
+}
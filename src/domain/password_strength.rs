@@ -0,0 +1,413 @@
+//! A small estimator in the spirit of zxcvbn: the password is split into the cheapest sequence of
+//! guessable patterns (common words, repeats, sequences, keyboard walks, years) and brute-forced
+//! characters, and scored by how many guesses an attacker who knows those patterns would need.
+
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+
+const MIN_PATTERN_LENGTH: usize = 3;
+
+/// Most common first, ranks are used to estimate how quickly a word would be guessed.
+static COMMON_WORDS: Lazy<HashMap<&'static str, usize>> = Lazy::new(|| {
+    include_str!("common_passwords.txt")
+        .lines()
+        .map(str::trim)
+        .filter(|word| !word.is_empty() && !word.starts_with('#'))
+        .enumerate()
+        .map(|(rank, word)| (word, rank + 1))
+        .collect()
+});
+
+const KEYBOARD_ROWS: [&str; 4] = [
+    "1234567890-=",
+    "qwertyuiop[]\\",
+    "asdfghjkl;'",
+    "zxcvbnm,./",
+];
+const KEYBOARD_KEYS: f64 = 47.0;
+
+/// zxcvbn's thresholds, in log2 of the number of guesses: 10^3, 10^6, 10^8 and 10^10.
+const SCORE_THRESHOLDS: [f64; 4] = [9.97, 19.93, 26.58, 33.22];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Pattern {
+    Year,
+    Repeat,
+    Sequence,
+    Keyboard,
+    CommonWord,
+}
+
+impl Pattern {
+    fn suggestion(&self) -> &'static str {
+        match self {
+            Pattern::CommonWord => {
+                "Avoid common passwords and words, even with numbers or symbols swapped in."
+            }
+            Pattern::Keyboard => "Avoid keyboard patterns like qwerty or asdf.",
+            Pattern::Sequence => "Avoid sequences like abc or 1234.",
+            Pattern::Repeat => "Avoid repeated characters and words.",
+            Pattern::Year => "Avoid years and dates.",
+        }
+    }
+}
+
+struct Match {
+    start: usize,
+    end: usize,
+    /// log2 of the number of guesses needed for this part of the password.
+    bits: f64,
+    pattern: Pattern,
+}
+
+#[derive(Debug)]
+pub struct Strength {
+    /// From 0 (trivially guessable) to 4 (very unlikely to be guessed).
+    pub score: u8,
+    /// log2 of the estimated number of guesses.
+    pub bits: f64,
+    /// How to do better, based on the weakest patterns found.
+    pub suggestion: &'static str,
+}
+
+pub fn estimate(password: &str) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|c| to_lower(*c)).collect();
+
+    let mut matches = Vec::new();
+    common_word_matches(&chars, &lower, &mut matches);
+    repeat_matches(&chars, &lower, &mut matches);
+    sequence_matches(&lower, &mut matches);
+    keyboard_matches(&lower, &mut matches);
+    year_matches(&chars, &mut matches);
+
+    // Cheapest way of covering the first `i` characters, and the match that ends the cover
+    let brute_force_bits = cardinality(&chars).log2();
+    let mut best = vec![(0.0, None); chars.len() + 1];
+    for end in 1..=chars.len() {
+        best[end] = (best[end - 1].0 + brute_force_bits, None);
+        for (i, m) in matches.iter().enumerate().filter(|(_, m)| m.end == end) {
+            let bits = best[m.start].0 + m.bits;
+            if bits < best[end].0 {
+                best[end] = (bits, Some(i));
+            }
+        }
+    }
+
+    let mut patterns = Vec::new();
+    let mut end = chars.len();
+    while end > 0 {
+        match best[end].1 {
+            Some(i) => {
+                patterns.push(matches[i].pattern);
+                end = matches[i].start;
+            }
+            None => end -= 1,
+        }
+    }
+
+    let bits = best[chars.len()].0;
+    let score = SCORE_THRESHOLDS.iter().filter(|t| bits >= **t).count() as u8;
+    let suggestion = patterns
+        .into_iter()
+        .max()
+        .map(|p| p.suggestion())
+        .unwrap_or("Use a longer password, a few uncommon words work well.");
+
+    Strength {
+        score,
+        bits,
+        suggestion,
+    }
+}
+
+fn to_lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn cardinality(chars: &[char]) -> f64 {
+    let mut cardinality = 0.0;
+    if chars.iter().any(char::is_ascii_lowercase) {
+        cardinality += 26.0;
+    }
+    if chars.iter().any(char::is_ascii_uppercase) {
+        cardinality += 26.0;
+    }
+    if chars.iter().any(char::is_ascii_digit) {
+        cardinality += 10.0;
+    }
+    if chars
+        .iter()
+        .any(|c| c.is_ascii() && !c.is_ascii_alphanumeric())
+    {
+        cardinality += 33.0;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        cardinality += 100.0;
+    }
+    f64::max(cardinality, 10.0)
+}
+
+fn unleet(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '8' => 'b',
+        '(' => 'c',
+        '3' => 'e',
+        '6' | '9' => 'g',
+        '1' | '!' | '|' => 'i',
+        '0' => 'o',
+        '5' | '$' => 's',
+        '7' | '+' => 't',
+        '2' => 'z',
+        c => c,
+    }
+}
+
+fn common_word_matches(chars: &[char], lower: &[char], matches: &mut Vec<Match>) {
+    let unleeted: Vec<char> = lower.iter().map(|c| unleet(*c)).collect();
+
+    for start in 0..chars.len() {
+        for end in start + MIN_PATTERN_LENGTH..=chars.len() {
+            let plain: String = lower[start..end].iter().collect();
+            let substituted: String = unleeted[start..end].iter().collect();
+            let reversed: String = plain.chars().rev().collect();
+
+            let candidates = [
+                (COMMON_WORDS.get(plain.as_str()), 0.0),
+                (COMMON_WORDS.get(reversed.as_str()), 1.0),
+                (
+                    COMMON_WORDS.get(substituted.as_str()),
+                    // One extra bit for every substituted character
+                    lower[start..end]
+                        .iter()
+                        .zip(&unleeted[start..end])
+                        .filter(|(a, b)| a != b)
+                        .count() as f64,
+                ),
+            ];
+            let best = candidates
+                .into_iter()
+                .filter_map(|(rank, extra_bits)| rank.map(|r| (*r as f64).log2() + extra_bits))
+                .reduce(f64::min);
+
+            if let Some(bits) = best {
+                matches.push(Match {
+                    start,
+                    end,
+                    bits: bits + uppercase_bits(&chars[start..end]),
+                    pattern: Pattern::CommonWord,
+                });
+            }
+        }
+    }
+}
+
+/// Capitalizing the first letter or the whole word is the first thing an attacker would try.
+fn uppercase_bits(word: &[char]) -> f64 {
+    let n_upper = word.iter().filter(|c| c.is_uppercase()).count();
+    let n_letters = word.iter().filter(|c| c.is_alphabetic()).count();
+    if n_upper == 0 {
+        0.0
+    } else if n_upper == n_letters || (n_upper == 1 && word[0].is_uppercase()) {
+        1.0
+    } else {
+        n_upper as f64 + 1.0
+    }
+}
+
+fn repeat_matches(chars: &[char], lower: &[char], matches: &mut Vec<Match>) {
+    // Runs of the same character, `aaa`
+    let mut start = 0;
+    while start < lower.len() {
+        let run = lower[start..]
+            .iter()
+            .take_while(|c| **c == lower[start])
+            .count();
+        if run >= MIN_PATTERN_LENGTH {
+            matches.push(Match {
+                start,
+                end: start + run,
+                bits: cardinality(&chars[start..start + 1]).log2() + (run as f64).log2(),
+                pattern: Pattern::Repeat,
+            });
+        }
+        start += run;
+    }
+
+    // Repeated chunks, `abcabc`. Only the longest repetition from each position is kept, the
+    // chunk is estimated recursively and anything more would blow up on long repeats.
+    let mut start = 0;
+    while start < lower.len() {
+        let longest = (2..=(lower.len() - start) / 2)
+            .map(|chunk| {
+                let base = &lower[start..start + chunk];
+                let repeats = lower[start..]
+                    .chunks_exact(chunk)
+                    .take_while(|c| *c == base)
+                    .count();
+                (chunk, repeats)
+            })
+            .filter(|(_, repeats)| *repeats >= 2)
+            .max_by_key(|(chunk, repeats)| (chunk * repeats, std::cmp::Reverse(*chunk)));
+
+        match longest {
+            Some((chunk, repeats)) => {
+                // The chunk itself may well be guessable
+                let base: String = chars[start..start + chunk].iter().collect();
+                matches.push(Match {
+                    start,
+                    end: start + chunk * repeats,
+                    bits: estimate(&base).bits + (repeats as f64).log2(),
+                    pattern: Pattern::Repeat,
+                });
+                start += chunk * repeats;
+            }
+            None => start += 1,
+        }
+    }
+}
+
+fn sequence_matches(lower: &[char], matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start + 1 < lower.len() {
+        let delta = lower[start + 1] as i64 - lower[start] as i64;
+        let same_class = |a: char, b: char| {
+            (a.is_ascii_lowercase() && b.is_ascii_lowercase())
+                || (a.is_ascii_digit() && b.is_ascii_digit())
+        };
+        let mut end = start + 1;
+        while end < lower.len()
+            && lower[end] as i64 - lower[end - 1] as i64 == delta
+            && same_class(lower[end], lower[end - 1])
+        {
+            end += 1;
+        }
+
+        let length = end - start;
+        if delta.abs() == 1 && length >= MIN_PATTERN_LENGTH {
+            let first = lower[start];
+            let start_bits = if ['a', 'z', '0', '1', '9'].contains(&first) {
+                2.0
+            } else if first.is_ascii_digit() {
+                10f64.log2()
+            } else {
+                26f64.log2()
+            };
+            let direction_bits = if delta < 0 { 1.0 } else { 0.0 };
+            matches.push(Match {
+                start,
+                end,
+                bits: start_bits + (length as f64).log2() + direction_bits,
+                pattern: Pattern::Sequence,
+            });
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+fn keyboard_matches(lower: &[char], matches: &mut Vec<Match>) {
+    let rows: Vec<Vec<char>> = KEYBOARD_ROWS
+        .iter()
+        .flat_map(|row| [row.chars().collect(), row.chars().rev().collect()])
+        .collect();
+
+    for start in 0..lower.len() {
+        for row in &rows {
+            let position = match row.iter().position(|c| *c == lower[start]) {
+                Some(position) => position,
+                None => continue,
+            };
+            let length = lower[start..]
+                .iter()
+                .zip(&row[position..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length >= MIN_PATTERN_LENGTH {
+                matches.push(Match {
+                    start,
+                    end: start + length,
+                    bits: KEYBOARD_KEYS.log2() + (length as f64).log2() + 1.0,
+                    pattern: Pattern::Keyboard,
+                });
+            }
+        }
+    }
+}
+
+fn year_matches(chars: &[char], matches: &mut Vec<Match>) {
+    for start in 0..chars.len().saturating_sub(3) {
+        let candidate: String = chars[start..start + 4].iter().collect();
+        if let Ok(year) = candidate.parse::<u32>() {
+            if (1900..2100).contains(&year) {
+                matches.push(Match {
+                    start,
+                    end: start + 4,
+                    bits: 200f64.log2(),
+                    pattern: Pattern::Year,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::estimate;
+
+    #[test]
+    fn common_passwords_score_zero() {
+        for password in ["password", "Password1", "qwerty", "123456789", "letmein"] {
+            assert_eq!(estimate(password).score, 0, "{}", password);
+        }
+    }
+
+    #[test]
+    fn character_substitutions_do_not_help_much() {
+        let strength = estimate("P@ssw0rd2019");
+        assert!(strength.score < 3, "{:?}", strength);
+        assert!(strength.suggestion.contains("common passwords"));
+    }
+
+    #[test]
+    fn long_patterns_are_still_weak() {
+        for (password, hint) in [
+            ("aaaaaaaaaaaaaaaa", "repeated"),
+            ("abcdefghijklmnop", "sequences"),
+            ("qwertyuiopasdfgh", "keyboard"),
+            ("abc123abc123abc123", "repeated"),
+        ] {
+            let strength = estimate(password);
+            assert!(strength.score < 3, "{}: {:?}", password, strength);
+            assert!(
+                strength.suggestion.contains(hint),
+                "{}: {:?}",
+                password,
+                strength
+            );
+        }
+    }
+
+    #[test]
+    fn long_repeats_are_estimated_quickly() {
+        let started = std::time::Instant::now();
+        for password in ["a".repeat(128), "ab".repeat(64), "abc123".repeat(21)] {
+            assert!(estimate(&password).score < 3, "{}", password);
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn random_looking_passwords_score_four() {
+        for password in [
+            "k8#Vq2!zLw9@",
+            "correct horse battery staple",
+            "6f1e3a2c-9b7d-4e5f-8a1b-2c3d4e5f6a7b",
+        ] {
+            assert_eq!(estimate(password).score, 4, "{}", password);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use {
+    chrono::{DateTime, LocalResult, NaiveDateTime, TimeZone, Utc},
+    chrono_tz::Tz,
+};
+
+/// When a scheduled newsletter issue should go out.
+///
+/// It comes from a `datetime-local` form field, which has no time zone, and is read in the
+/// configured `application.timezone`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendTime(DateTime<Utc>);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SendTimeValidationError {
+    #[error("Please enter a valid date and time.")]
+    Invalid,
+    #[error("The send time must be in the future.")]
+    InThePast,
+    #[error("That time is skipped when the clocks change, please pick another.")]
+    Skipped,
+}
+
+impl SendTime {
+    pub fn parse(s: &str, zone: Tz, now: DateTime<Utc>) -> Result<Self, SendTimeValidationError> {
+        let s = s.trim();
+        let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M")
+            .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S"))
+            .map_err(|_| SendTimeValidationError::Invalid)?;
+        // When the clocks go back, the hour that repeats is taken the first time round
+        let send_at = match zone.from_local_datetime(&naive) {
+            LocalResult::Single(send_at) | LocalResult::Ambiguous(send_at, _) => {
+                send_at.with_timezone(&Utc)
+            }
+            LocalResult::None => return Err(SendTimeValidationError::Skipped),
+        };
+        if send_at <= now {
+            return Err(SendTimeValidationError::InThePast);
+        }
+        Ok(Self(send_at))
+    }
+
+    /// The value for a `datetime-local` form field, in the time zone it will be read in.
+    pub fn form_value(send_at: &DateTime<Utc>, zone: Tz) -> String {
+        send_at
+            .with_timezone(&zone)
+            .format("%Y-%m-%dT%H:%M")
+            .to_string()
+    }
+}
+
+impl AsRef<DateTime<Utc>> for SendTime {
+    fn as_ref(&self) -> &DateTime<Utc> {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SendTime, SendTimeValidationError};
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::{America::New_York, Europe::Berlin, UTC};
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn form_values_are_read_in_the_configured_zone() {
+        let now = Utc.ymd(2022, 6, 11).and_hms(15, 0, 0);
+        let send_time = assert_ok!(SendTime::parse("2022-06-12T08:00", UTC, now));
+        assert_eq!(*send_time.as_ref(), Utc.ymd(2022, 6, 12).and_hms(8, 0, 0));
+        assert_ok!(SendTime::parse("2022-06-12T08:00:30", UTC, now));
+        assert_eq!(
+            SendTime::form_value(send_time.as_ref(), UTC),
+            "2022-06-12T08:00"
+        );
+
+        let send_time = assert_ok!(SendTime::parse("2022-06-12T08:00", Berlin, now));
+        assert_eq!(*send_time.as_ref(), Utc.ymd(2022, 6, 12).and_hms(6, 0, 0));
+        assert_eq!(
+            SendTime::form_value(send_time.as_ref(), Berlin),
+            "2022-06-12T08:00"
+        );
+        assert_eq!(
+            SendTime::form_value(send_time.as_ref(), New_York),
+            "2022-06-12T02:00"
+        );
+    }
+
+    #[test]
+    fn times_around_clock_changes_are_handled() {
+        let now = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        assert!(matches!(
+            SendTime::parse("2022-03-27T02:30", Berlin, now),
+            Err(SendTimeValidationError::Skipped)
+        ));
+        let send_time = assert_ok!(SendTime::parse("2022-10-30T02:30", Berlin, now));
+        assert_eq!(*send_time.as_ref(), Utc.ymd(2022, 10, 30).and_hms(0, 30, 0));
+    }
+
+    #[test]
+    fn malformed_times_are_rejected() {
+        let now = Utc.ymd(2022, 6, 11).and_hms(15, 0, 0);
+        for s in [
+            "",
+            "tomorrow",
+            "2022-06-12",
+            "2022-13-12T08:00",
+            "12/06/2022 08:00",
+        ] {
+            assert_err!(SendTime::parse(s, UTC, now), "{}", s);
+        }
+    }
+
+    #[test]
+    fn times_in_the_past_are_rejected() {
+        let now = Utc.ymd(2022, 6, 11).and_hms(15, 0, 0);
+        assert_err!(SendTime::parse("2022-06-11T15:00", UTC, now));
+        assert_err!(SendTime::parse("2021-06-12T08:00", UTC, now));
+        // 16:30 in Berlin has already passed at 15:00 UTC
+        assert_err!(SendTime::parse("2022-06-11T16:30", Berlin, now));
+    }
+}
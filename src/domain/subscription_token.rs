@@ -1,4 +1,10 @@
-use unicode_segmentation::UnicodeSegmentation;
+use {
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
+    sha2::{Digest, Sha256},
+    unicode_segmentation::UnicodeSegmentation,
+};
+
+const TOKEN_LENGTH: usize = 25;
 
 #[derive(Debug, thiserror::Error)]
 pub enum SubTokenValidationError {
@@ -11,8 +17,18 @@ pub enum SubTokenValidationError {
 pub struct SubscriptionToken(String);
 
 impl SubscriptionToken {
+    /// Generate a new random token.
+    pub fn generate() -> Self {
+        let mut rng = thread_rng();
+        let token = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(TOKEN_LENGTH)
+            .collect();
+        Self(token)
+    }
+
     pub fn parse(s: String) -> Result<Self, SubTokenValidationError> {
-        if s.graphemes(true).count() != 25 {
+        if s.graphemes(true).count() != TOKEN_LENGTH {
             Err(SubTokenValidationError::InvalidLength)
         } else if s.chars().any(|c| !c.is_alphanumeric()) {
             Err(SubTokenValidationError::NotAlphanumeric)
@@ -20,6 +36,14 @@ impl SubscriptionToken {
             Ok(Self(s))
         }
     }
+
+    /// Hex-encoded SHA-256 digest of the token, which is what gets stored in the database.
+    ///
+    /// Tokens are random and short-lived, so a fast unsalted hash is enough to make a leaked
+    /// table useless without slowing down lookups.
+    pub fn digest(&self) -> String {
+        hex::encode(Sha256::digest(self.0.as_bytes()))
+    }
 }
 
 impl AsRef<str> for SubscriptionToken {
@@ -27,3 +51,24 @@ impl AsRef<str> for SubscriptionToken {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionToken;
+    use claim::assert_ok;
+
+    #[test]
+    fn generated_tokens_are_valid() {
+        let token = SubscriptionToken::generate();
+        assert_ok!(SubscriptionToken::parse(token.as_ref().to_owned()));
+    }
+
+    #[test]
+    fn digest_is_hex_encoded_sha256() {
+        let token = SubscriptionToken::parse("a".repeat(25)).unwrap();
+        assert_eq!(
+            token.digest(),
+            "2f521e2a7d0bd812cbc035f4ed6806eb8d851793b04ba147e8f66b72f5d1f20f"
+        );
+    }
+}
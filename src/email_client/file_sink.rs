@@ -0,0 +1,101 @@
+use super::{build_message, Email, EmailSender, SendEmailError};
+use crate::domain::SubscriberEmail;
+
+use std::path::PathBuf;
+
+use {anyhow::Context, chrono::Utc, uuid::Uuid};
+
+/// Writes every email to its own `.eml` file instead of sending it, for local development.
+///
+/// The files can be opened with any mail client.
+pub struct FileSinkClient {
+    sender: SubscriberEmail,
+    directory: PathBuf,
+}
+
+impl FileSinkClient {
+    pub fn new(directory: impl Into<PathBuf>, sender: SubscriberEmail) -> Self {
+        Self {
+            sender,
+            directory: directory.into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileSinkClient {
+    #[tracing::instrument(name = "Write email to file sink", skip_all)]
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError> {
+        let message = build_message(&self.sender, email)?;
+
+        // Prefix with the timestamp so that a directory listing shows emails in order
+        let path = self.directory.join(format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+            Uuid::new_v4()
+        ));
+
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .context("Failed to create email sink directory")
+            .map_err(SendEmailError::Transient)?;
+        tokio::fs::write(&path, message.formatted())
+            .await
+            .with_context(|| format!("Failed to write email to {}", path.display()))
+            .map_err(SendEmailError::Transient)?;
+
+        tracing::info!("Email written to {}", path.display());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileSinkClient;
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{Email, EmailSender},
+    };
+    use {
+        claim::assert_ok,
+        fake::{faker::internet::en::SafeEmail, Fake},
+        uuid::Uuid,
+    };
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let sender = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let recipient = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let client = FileSinkClient::new(&directory, sender);
+
+        let outcome = client
+            .send(&Email {
+                recipient: recipient.clone(),
+                subject: "Subject line".into(),
+                html_content: "<p>HTML body</p>".into(),
+                text_content: "Text body".into(),
+                unsubscribe_url: Some("https://example.com/unsubscribe".into()),
+            })
+            .await;
+        assert_ok!(outcome);
+
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(contents.contains(&format!("To: {}", recipient)));
+        assert!(contents.contains("Subject: Subject line"));
+        assert!(contents.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(contents.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(contents.contains("Text body"));
+        assert!(contents.contains("<p>HTML body</p>"));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
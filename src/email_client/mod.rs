@@ -0,0 +1,157 @@
+mod file_sink;
+mod postmark;
+mod smtp;
+
+pub use {
+    file_sink::FileSinkClient,
+    postmark::PostmarkClient,
+    smtp::{SmtpClient, SmtpTls},
+};
+
+use crate::domain::SubscriberEmail;
+
+use {
+    anyhow::Context,
+    lettre::{
+        message::{
+            header::{Header, HeaderName, HeaderValue},
+            MultiPart,
+        },
+        Message,
+    },
+};
+
+/// A transport capable of delivering emails on behalf of the newsletter.
+///
+/// The backend is chosen at startup through `EmailClientSettings`, routes and workers only ever
+/// see the trait.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError>;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), SendEmailError> {
+        self.send(&Email {
+            recipient: recipient.clone(),
+            subject: subject.to_owned(),
+            html_content: html_content.to_owned(),
+            text_content: text_content.to_owned(),
+            unsubscribe_url: None,
+        })
+        .await
+    }
+
+    /// Send several emails at once, returning one outcome per email, in the same order.
+    ///
+    /// The default implementation sends them one by one, backends with a bulk API should
+    /// override it.
+    async fn send_batch(&self, emails: &[Email]) -> Vec<Result<(), SendEmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await);
+        }
+        outcomes
+    }
+}
+
+#[derive(Debug)]
+pub struct Email {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    /// Advertised through the `List-Unsubscribe` and `List-Unsubscribe-Post` headers so that
+    /// mail clients can offer one-click unsubscription (RFC 8058).
+    pub unsubscribe_url: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SendEmailError {
+    /// The email might be delivered if the request is retried later, e.g. on timeouts, 5xx
+    /// responses or rate limiting.
+    #[error("{0}")]
+    Transient(#[source] anyhow::Error),
+    /// The email was rejected and retrying won't change that, e.g. an inactive recipient.
+    #[error("{0}")]
+    Permanent(#[source] anyhow::Error),
+}
+
+impl SendEmailError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, SendEmailError::Transient(_))
+    }
+}
+
+/// Build a multipart MIME message with both the HTML and plain text bodies.
+fn build_message(sender: &SubscriberEmail, email: &Email) -> Result<Message, SendEmailError> {
+    let sender = sender
+        .as_ref()
+        .parse()
+        .context("Failed to parse sender address")
+        .map_err(SendEmailError::Permanent)?;
+    let recipient = email
+        .recipient
+        .as_ref()
+        .parse()
+        .context("Failed to parse recipient address")
+        .map_err(SendEmailError::Permanent)?;
+
+    let mut builder = Message::builder()
+        .from(sender)
+        .to(recipient)
+        .subject(&email.subject);
+    if let Some(unsubscribe_url) = &email.unsubscribe_url {
+        builder = builder
+            .header(ListUnsubscribe(format!("<{}>", unsubscribe_url)))
+            .header(ListUnsubscribePost);
+    }
+
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            email.text_content.clone(),
+            email.html_content.clone(),
+        ))
+        .context("Failed to build email message")
+        .map_err(SendEmailError::Permanent)
+}
+
+const LIST_UNSUBSCRIBE_POST: &str = "List-Unsubscribe=One-Click";
+
+#[derive(Clone)]
+struct ListUnsubscribe(String);
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.to_owned()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+#[derive(Clone)]
+struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post")
+    }
+
+    fn parse(_: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), LIST_UNSUBSCRIBE_POST.to_owned())
+    }
+}
@@ -0,0 +1,571 @@
+use super::{Email, EmailSender, SendEmailError, LIST_UNSUBSCRIBE_POST};
+use crate::domain::SubscriberEmail;
+
+use std::time::Duration;
+
+use {
+    anyhow::Context,
+    reqwest::{Client, StatusCode, Url},
+    secrecy::{ExposeSecret, Secret},
+    serde::{Deserialize, Serialize},
+};
+
+/// Postmark rejects batches with more messages than this.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Sends emails through Postmark's HTTP API.
+pub struct PostmarkClient {
+    sender: SubscriberEmail,
+    http_client: Client,
+    base_url: Url,
+    authorization_token: Secret<String>,
+}
+
+impl PostmarkClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Ok(Self {
+            http_client,
+            base_url: Url::parse(&base_url).map_err(|_| "Invalid base url")?,
+            sender,
+            authorization_token,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkClient {
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError> {
+        let url = self
+            .base_url
+            .join("email")
+            .expect("Failed to join base URL with `/email` endpoint");
+
+        let request_body = SendEmailRequest::new(&self.sender, email);
+
+        self.http_client
+            .post(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(classify_error)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Send email batch through Postmark", skip_all, fields(n_emails = emails.len()))]
+    async fn send_batch(&self, emails: &[Email]) -> Vec<Result<(), SendEmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(chunk_outcomes) => outcomes.extend(chunk_outcomes),
+                // The whole request failed, so every message in it shares the same fate
+                Err(e) => {
+                    if !e.is_transient() {
+                        tracing::error!(
+                            error.cause_chain = ?e,
+                            error.message = %e,
+                            "Postmark rejected a whole batch request, check the email client \
+                            configuration"
+                        );
+                    }
+                    outcomes.extend(chunk.iter().map(|_| Err(batch_failure(&e))))
+                }
+            }
+        }
+        outcomes
+    }
+}
+
+impl PostmarkClient {
+    async fn send_chunk(
+        &self,
+        emails: &[Email],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let url = self
+            .base_url
+            .join("email/batch")
+            .expect("Failed to join base URL with `/email/batch` endpoint");
+
+        let request_body: Vec<_> = emails
+            .iter()
+            .map(|email| SendEmailRequest::new(&self.sender, email))
+            .collect();
+
+        let responses: Vec<SendEmailResponse> = self
+            .http_client
+            .post(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(classify_error)?
+            .json()
+            .await
+            .context("Failed to parse the batch response")
+            .map_err(SendEmailError::Transient)?;
+
+        if responses.len() != emails.len() {
+            return Err(SendEmailError::Transient(anyhow::anyhow!(
+                "Expected {} results in the batch response, got {}",
+                emails.len(),
+                responses.len()
+            )));
+        }
+
+        let outcomes = responses
+            .into_iter()
+            .map(|r| match r.error_code {
+                0 => Ok(()),
+                // Per-message errors are about the message itself (e.g. an inactive recipient or
+                // an invalid address), so sending it again won't help
+                error_code => Err(SendEmailError::Permanent(anyhow::anyhow!(
+                    "Postmark rejected the email with error code {}: {}",
+                    error_code,
+                    r.message
+                ))),
+            })
+            .collect();
+
+        Ok(outcomes)
+    }
+}
+
+/// A failed batch request says nothing about the messages in it. Even a 4xx only means the
+/// request as a whole was rejected (e.g. a bad server token or a malformed field), so every
+/// message is retried rather than dead-lettered. Only per-message error codes are permanent.
+fn batch_failure(e: &SendEmailError) -> SendEmailError {
+    SendEmailError::Transient(anyhow::anyhow!("The batch request failed: {}", e))
+}
+
+fn classify_error(e: reqwest::Error) -> SendEmailError {
+    match e.status() {
+        Some(StatusCode::TOO_MANY_REQUESTS) => SendEmailError::Transient(e.into()),
+        Some(status) if status.is_client_error() => SendEmailError::Permanent(e.into()),
+        // Server errors, timeouts and connection failures
+        _ => SendEmailError::Transient(e.into()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<MessageHeader<'a>>,
+}
+
+impl<'a> SendEmailRequest<'a> {
+    fn new(sender: &'a SubscriberEmail, email: &'a Email) -> Self {
+        let mut headers = Vec::new();
+        if let Some(unsubscribe_url) = &email.unsubscribe_url {
+            headers.push(MessageHeader {
+                name: "List-Unsubscribe",
+                value: format!("<{}>", unsubscribe_url),
+            });
+            headers.push(MessageHeader {
+                name: "List-Unsubscribe-Post",
+                value: LIST_UNSUBSCRIBE_POST.to_owned(),
+            });
+        }
+
+        Self {
+            from: sender.as_ref(),
+            to: email.recipient.as_ref(),
+            subject: &email.subject,
+            html_body: &email.html_content,
+            text_body: &email.text_content,
+            headers,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MessageHeader<'a> {
+    name: &'a str,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailResponse {
+    error_code: i64,
+    message: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::PostmarkClient;
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{Email, EmailSender, SendEmailError},
+    };
+    use std::time::Duration;
+    use {
+        claim::assert_ok,
+        fake::{
+            faker::{
+                internet::en::SafeEmail,
+                lorem::en::{Paragraph, Sentence},
+            },
+            Fake, Faker,
+        },
+        secrecy::Secret,
+        wiremock::{matchers, Mock, MockServer, ResponseTemplate},
+    };
+
+    #[tokio::test]
+    async fn send_email_sends_expected_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let (subscriber_email, subject, content) = mock_content();
+
+        Mock::given(matchers::header_exists("X-Postmark-Server-Token"))
+            .and(matchers::header("Content-Type", "application/json"))
+            .and(matchers::path("/email"))
+            .and(matchers::method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content)
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_on_server_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let (subscriber_email, subject, content) = mock_content();
+
+        Mock::given(matchers::any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content)
+            .await;
+
+        assert!(matches!(outcome, Err(SendEmailError::Transient(_))));
+    }
+
+    #[tokio::test]
+    async fn send_email_is_transient_on_server_429() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let (subscriber_email, subject, content) = mock_content();
+
+        Mock::given(matchers::any())
+            .respond_with(ResponseTemplate::new(429))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content)
+            .await;
+
+        assert!(matches!(outcome, Err(SendEmailError::Transient(_))));
+    }
+
+    #[tokio::test]
+    async fn send_email_is_permanent_on_server_422() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let (subscriber_email, subject, content) = mock_content();
+
+        Mock::given(matchers::any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content)
+            .await;
+
+        assert!(matches!(outcome, Err(SendEmailError::Permanent(_))));
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let (subscriber_email, subject, content) = mock_content();
+
+        let response = ResponseTemplate::new(200).set_delay(Duration::from_secs(180));
+
+        Mock::given(matchers::any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content)
+            .await;
+
+        assert!(matches!(outcome, Err(SendEmailError::Transient(_))));
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_expected_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let emails = mock_emails(3);
+
+        Mock::given(matchers::header_exists("X-Postmark-Server-Token"))
+            .and(matchers::header("Content-Type", "application/json"))
+            .and(matchers::path("/email/batch"))
+            .and(matchers::method("POST"))
+            .and(SendBatchBodyMatcher)
+            .respond_with(BatchResponder {
+                failing_index: None,
+            })
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&emails).await;
+
+        assert_eq!(outcomes.len(), 3);
+        for outcome in outcomes {
+            assert_ok!(outcome);
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_per_message_failures() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let emails = mock_emails(3);
+
+        Mock::given(matchers::any())
+            .respond_with(BatchResponder {
+                failing_index: Some(1),
+            })
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&emails).await;
+
+        assert_ok!(&outcomes[0]);
+        assert!(matches!(outcomes[1], Err(SendEmailError::Permanent(_))));
+        assert_ok!(&outcomes[2]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_batches_larger_than_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let emails = mock_emails(501);
+
+        Mock::given(matchers::path("/email/batch"))
+            .respond_with(BatchResponder {
+                failing_index: None,
+            })
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&emails).await;
+
+        assert_eq!(outcomes.len(), 501);
+        assert!(outcomes.iter().all(|o| o.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_email_on_server_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let emails = mock_emails(3);
+
+        Mock::given(matchers::any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&emails).await;
+
+        assert_eq!(outcomes.len(), 3);
+        for outcome in outcomes {
+            assert!(matches!(outcome, Err(SendEmailError::Transient(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_retries_every_email_on_server_422() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let emails = mock_emails(3);
+
+        Mock::given(matchers::any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&emails).await;
+
+        // Rejecting the request isn't rejecting its recipients
+        assert_eq!(outcomes.len(), 3);
+        for outcome in outcomes {
+            assert!(matches!(outcome, Err(SendEmailError::Transient(_))));
+        }
+    }
+
+    fn email_client(base_url: String) -> PostmarkClient {
+        let sender = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        PostmarkClient::new(
+            base_url,
+            sender,
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        )
+        .unwrap()
+    }
+
+    fn mock_content() -> (SubscriberEmail, String, String) {
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let subject: String = Sentence(1..2).fake();
+        let content: String = Paragraph(1..10).fake();
+        (subscriber_email, subject, content)
+    }
+
+    fn mock_emails(n: usize) -> Vec<Email> {
+        (0..n)
+            .map(|_| {
+                let (recipient, subject, content) = mock_content();
+                Email {
+                    recipient,
+                    subject,
+                    html_content: content.clone(),
+                    text_content: content,
+                    unsubscribe_url: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Replies to a batch request with one result per message, all successful except the one at
+    /// `failing_index`.
+    struct BatchResponder {
+        failing_index: Option<usize>,
+    }
+
+    impl wiremock::Respond for BatchResponder {
+        fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+            let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = body
+                .iter()
+                .enumerate()
+                .map(|(i, message)| {
+                    if Some(i) == self.failing_index {
+                        serde_json::json!({
+                            "ErrorCode": 406,
+                            "Message": "You tried to send to a recipient that has been marked as inactive.",
+                            "To": message["To"],
+                        })
+                    } else {
+                        serde_json::json!({
+                            "ErrorCode": 0,
+                            "Message": "OK",
+                            "To": message["To"],
+                        })
+                    }
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        }
+    }
+
+    struct SendBatchBodyMatcher;
+    impl wiremock::Match for SendBatchBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            match serde_json::from_slice::<Vec<serde_json::Value>>(&request.body) {
+                Ok(body) => body.iter().all(|message| {
+                    message.get("From").is_some()
+                        && message.get("To").is_some()
+                        && message.get("Subject").is_some()
+                        && message.get("HtmlBody").is_some()
+                        && message.get("TextBody").is_some()
+                }),
+                Err(_) => false,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_adds_list_unsubscribe_headers() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let mut email = mock_emails(1).pop().unwrap();
+        email.unsubscribe_url = Some("https://example.com/unsubscribe?token=abc".into());
+
+        Mock::given(matchers::path("/email"))
+            .and(matchers::body_partial_json(serde_json::json!({
+                "Headers": [
+                    {
+                        "Name": "List-Unsubscribe",
+                        "Value": "<https://example.com/unsubscribe?token=abc>"
+                    },
+                    {
+                        "Name": "List-Unsubscribe-Post",
+                        "Value": "List-Unsubscribe=One-Click"
+                    }
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client.send(&email).await;
+
+        assert_ok!(outcome);
+    }
+
+    struct SendEmailBodyMatcher;
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            match serde_json::from_slice::<serde_json::Value>(&request.body) {
+                Ok(body) => {
+                    body.get("From").is_some()
+                        && body.get("To").is_some()
+                        && body.get("Subject").is_some()
+                        && body.get("HtmlBody").is_some()
+                        && body.get("TextBody").is_some()
+                }
+                Err(_) => false,
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use super::{build_message, Email, EmailSender, SendEmailError};
+use crate::domain::SubscriberEmail;
+
+use std::time::Duration;
+
+use {
+    lettre::{
+        transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+        Tokio1Executor,
+    },
+    secrecy::{ExposeSecret, Secret},
+};
+
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text connection, only suitable for local relays.
+    None,
+    /// Upgrade the connection with `STARTTLS`, usually on port 587.
+    StartTls,
+    /// Connect over TLS from the start, usually on port 465.
+    Wrapper,
+}
+
+/// Sends emails through an SMTP relay.
+pub struct SmtpClient {
+    sender: SubscriberEmail,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpClient {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, Secret<String>)>,
+        sender: SubscriberEmail,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let builder = match tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .map_err(|e| format!("Invalid SMTP relay: {}", e))?,
+            SmtpTls::Wrapper => AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+                .map_err(|e| format!("Invalid SMTP relay: {}", e))?,
+        };
+
+        let mut builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            sender,
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpClient {
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError> {
+        let message = build_message(&self.sender, email)?;
+
+        self.transport.send(message).await.map_err(|e| {
+            // Only 5xx replies are definitive, everything else (4xx replies, timeouts, connection
+            // errors) is worth another try
+            let is_permanent = e.is_permanent();
+            let e = anyhow::Error::from(e).context("Failed to send email over SMTP");
+            if is_permanent {
+                SendEmailError::Permanent(e)
+            } else {
+                SendEmailError::Transient(e)
+            }
+        })?;
+
+        Ok(())
+    }
+}
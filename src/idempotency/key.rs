@@ -0,0 +1,49 @@
+#[derive(Debug, thiserror::Error)]
+pub enum IdempotencyKeyValidationError {
+    #[error("The idempotency key cannot be empty")]
+    Empty,
+    #[error("The idempotency key must be shorter than 50 characters")]
+    TooLong,
+}
+
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    pub fn parse(s: String) -> Result<Self, IdempotencyKeyValidationError> {
+        if s.is_empty() {
+            Err(IdempotencyKeyValidationError::Empty)
+        } else if s.len() >= 50 {
+            Err(IdempotencyKeyValidationError::TooLong)
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdempotencyKey;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn empty_key_is_rejected() {
+        assert_err!(IdempotencyKey::parse("".to_string()));
+    }
+
+    #[test]
+    fn a_50_character_key_is_rejected() {
+        assert_err!(IdempotencyKey::parse("a".repeat(50)));
+    }
+
+    #[test]
+    fn a_uuid_is_a_valid_key() {
+        assert_ok!(IdempotencyKey::parse(uuid::Uuid::new_v4().to_string()));
+    }
+}
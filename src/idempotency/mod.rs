@@ -0,0 +1,7 @@
+mod key;
+mod persistence;
+
+pub use {
+    key::{IdempotencyKey, IdempotencyKeyValidationError},
+    persistence::{save_response, try_processing, NextAction},
+};
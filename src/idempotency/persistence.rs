@@ -0,0 +1,154 @@
+use super::IdempotencyKey;
+
+use {
+    actix_web::{body::to_bytes, http::StatusCode, HttpResponse},
+    anyhow::{Context, Result},
+    sqlx::{
+        postgres::{PgHasArrayType, PgTypeInfo},
+        PgPool, Postgres, Transaction,
+    },
+    uuid::Uuid,
+};
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    /// No response has been recorded for this key yet: the caller owns the transaction and must
+    /// hand it back to `save_response` once the request has been processed.
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(HttpResponse),
+}
+
+/// Claim an idempotency key for the given user.
+///
+/// Concurrent requests using the same key block on the row inserted by the first request until
+/// its transaction is committed, and then replay the response that it saved.
+#[tracing::instrument(name = "Try processing idempotent request", skip(pool))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: &Uuid,
+) -> Result<NextAction> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")?;
+
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, created_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to insert idempotency key")?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Expected a saved response, but none was found"))?;
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+#[tracing::instrument(name = "Get saved response", skip(pool))]
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: &Uuid,
+) -> Result<Option<HttpResponse>> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code AS "response_status_code!",
+            response_headers AS "response_headers!: Vec<HeaderPairRecord>",
+            response_body AS "response_body!"
+        FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve saved response")?;
+
+    match saved_response {
+        Some(r) => {
+            let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
+            let mut response = HttpResponse::build(status_code);
+            for HeaderPairRecord { name, value } in r.response_headers {
+                response.append_header((name, value));
+            }
+            Ok(Some(response.body(r.response_body)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Record the response to an idempotent request and release the idempotency key.
+#[tracing::instrument(name = "Save response", skip(transaction, http_response))]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: &Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse> {
+    let (response_head, body) = http_response.into_parts();
+    // `MessageBody::Error` is not `Send + Sync`, so it can't be converted into `anyhow::Error`
+    let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers: Vec<_> = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect();
+
+    sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to save response")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to save response")?;
+
+    Ok(response_head.set_body(body).map_into_boxed_body())
+}
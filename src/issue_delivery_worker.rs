@@ -0,0 +1,510 @@
+use crate::{
+    configuration::Settings,
+    domain::SubscriberEmail,
+    email_client::{Email, EmailSender, SendEmailError},
+    startup::get_connection_pool,
+};
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use {
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::{DateTime, Utc},
+    rand::{thread_rng, Rng},
+    sqlx::{PgPool, Postgres, Transaction},
+    tracing::Span,
+    uuid::Uuid,
+};
+
+type Trans<'c> = Transaction<'c, Postgres>;
+
+/// Maximum number of deliveries picked up and sent in a single batch.
+const DELIVERY_BATCH_SIZE: i64 = 500;
+/// Deliveries that keep failing transiently are dead-lettered after this many attempts.
+const MAX_DELIVERY_ATTEMPTS: i32 = 10;
+const BASE_RETRY_DELAY_SECS: i64 = 30;
+const MAX_RETRY_DELAY_SECS: i64 = 60 * 60;
+/// Claimed deliveries are left alone by other workers for this long. It has to outlast sending a
+/// whole batch, or a slow batch could go out twice.
+const DELIVERY_CLAIM_SECS: i64 = 10 * 60;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<()> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.application.base_url,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    base_url: String,
+) -> Result<()> {
+    loop {
+        match try_execute_task(&pool, email_client.as_ref(), &base_url).await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(10)).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// Dequeue a batch of due delivery tasks and send them in one go.
+///
+/// Tasks are claimed, and the claim committed, before anything is sent, and each outcome is
+/// recorded on its own afterwards. A failure to record an outcome therefore only affects that
+/// one delivery, which is retried once its claim runs out, instead of replaying the whole batch.
+///
+/// Transient failures are rescheduled with a jittered exponential backoff. Permanent failures,
+/// and transient ones that have used up their attempts, are moved to the dead-letter table so
+/// that admins can inspect and requeue them.
+///
+/// Every email carries an unsubscribe link pointing at `base_url`.
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+) -> Result<ExecutionOutcome> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")?;
+
+    let tasks = claim_tasks(&mut transaction, DELIVERY_BATCH_SIZE).await?;
+    if tasks.is_empty() {
+        mark_sent_issues(&mut transaction).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction to mark issues as sent")?;
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", &tasks.len());
+
+    let mut issues = HashMap::new();
+    let mut deliverable_tasks = Vec::with_capacity(tasks.len());
+    let mut emails = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let unsubscribe_token = match (&task.status, &task.unsubscribe_token) {
+            (Some(status), Some(token)) if status == "confirmed" => token.clone(),
+            // The subscriber left after the issue was published
+            _ => {
+                tracing::info!(
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a subscriber who is no longer confirmed"
+                );
+                delete_task(&mut transaction, &task).await?;
+                continue;
+            }
+        };
+
+        let recipient = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Some(recipient) => recipient,
+            None => {
+                tracing::error!(
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber. Their stored contact details are invalid"
+                );
+                // Nothing was sent, so the claim doesn't count as an attempt
+                let n_attempts = task.n_attempts - 1;
+                dead_letter_task(
+                    &mut transaction,
+                    &task,
+                    n_attempts,
+                    "The stored email address is invalid",
+                )
+                .await?;
+                continue;
+            }
+        };
+
+        let issue = match issues.entry(task.newsletter_issue_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let issue = get_issue(&mut transaction, &task.newsletter_issue_id).await?;
+                entry.insert(issue)
+            }
+        };
+        let unsubscribe_url = format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+            base_url, unsubscribe_token
+        );
+        let html_content = NewsletterEmailHtml {
+            content: &issue.html_content,
+            unsubscribe_url: &unsubscribe_url,
+        }
+        .render()
+        .context("Failed to render the HTML version of a newsletter issue")?;
+        let text_content = NewsletterEmailText {
+            content: &issue.text_content,
+            unsubscribe_url: &unsubscribe_url,
+        }
+        .render()
+        .context("Failed to render the text version of a newsletter issue")?;
+        emails.push(Email {
+            recipient,
+            subject: issue.title.clone(),
+            html_content,
+            text_content,
+            unsubscribe_url: Some(unsubscribe_url),
+        });
+        deliverable_tasks.push(task);
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to claim delivery tasks")?;
+
+    let outcomes = email_client.send_batch(&emails).await;
+    for (task, outcome) in deliverable_tasks.iter().zip(outcomes) {
+        if let Err(e) = record_outcome(pool, task, outcome).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                newsletter_issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                "Failed to record the outcome of a delivery. It will be retried once its claim \
+                runs out."
+            );
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn record_outcome(
+    pool: &PgPool,
+    task: &DeliveryTask,
+    outcome: Result<(), SendEmailError>,
+) -> Result<()> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")?;
+    let trans = &mut transaction;
+
+    let n_attempts = task.n_attempts;
+    match outcome {
+        Ok(()) => delete_task(trans, task).await?,
+        Err(e) if e.is_transient() && n_attempts < MAX_DELIVERY_ATTEMPTS => {
+            let next_attempt_at = Utc::now() + retry_delay(n_attempts);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                newsletter_issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                n_attempts,
+                %next_attempt_at,
+                "Failed to deliver issue to a confirmed subscriber. Retrying later."
+            );
+            reschedule_task(trans, task, n_attempts, next_attempt_at).await?
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                newsletter_issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                n_attempts,
+                "Failed to deliver issue to a confirmed subscriber. Giving up."
+            );
+            dead_letter_task(trans, task, n_attempts, &e.to_string()).await?
+        }
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to complete a delivery task")
+}
+
+/// Exponential backoff with jitter: the delay before the n-th retry is picked at random between
+/// half and all of `BASE_RETRY_DELAY_SECS * 2^(n - 1)`, capped at `MAX_RETRY_DELAY_SECS`.
+fn retry_delay(n_attempts: i32) -> chrono::Duration {
+    let exponent = n_attempts.saturating_sub(1).clamp(0, 32) as u32;
+    let max_delay = BASE_RETRY_DELAY_SECS
+        .saturating_mul(2i64.saturating_pow(exponent))
+        .min(MAX_RETRY_DELAY_SECS);
+    let delay = thread_rng().gen_range(max_delay / 2..=max_delay);
+    chrono::Duration::seconds(delay)
+}
+
+#[derive(Debug)]
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_attempts: i32,
+    status: Option<String>,
+    unsubscribe_token: Option<String>,
+}
+
+/// Claim a batch of due delivery tasks, counting the attempt that is about to be made.
+///
+/// Claimed tasks are pushed back by `DELIVERY_CLAIM_SECS`, so that once the claim is committed
+/// other workers skip them while they are being sent. If the worker dies before recording the
+/// outcome, the task becomes due again when the claim runs out.
+#[tracing::instrument(name = "Claim delivery tasks", skip(trans))]
+async fn claim_tasks(trans: &mut Trans<'_>, batch_size: i64) -> Result<Vec<DeliveryTask>> {
+    // Row-level locks keep concurrent workers from claiming the same tasks
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        WITH due AS (
+            SELECT newsletter_issue_id, subscriber_email
+            FROM issue_delivery_queue
+            WHERE next_attempt_at <= now()
+            ORDER BY next_attempt_at
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT $1
+        )
+        UPDATE issue_delivery_queue q
+        SET
+            n_attempts = q.n_attempts + 1,
+            next_attempt_at = $2
+        FROM due
+        LEFT JOIN subscriptions s ON s.email = due.subscriber_email
+        WHERE q.newsletter_issue_id = due.newsletter_issue_id
+            AND q.subscriber_email = due.subscriber_email
+        RETURNING
+            q.newsletter_issue_id,
+            q.subscriber_email,
+            q.n_attempts,
+            s.status AS "status?",
+            s.unsubscribe_token AS "unsubscribe_token?"
+        "#,
+        batch_size,
+        Utc::now() + chrono::Duration::seconds(DELIVERY_CLAIM_SECS),
+    )
+    .fetch_all(trans)
+    .await
+    .context("Failed to claim delivery tasks")?;
+
+    Ok(tasks)
+}
+
+#[tracing::instrument(name = "Delete delivery task", skip(trans))]
+async fn delete_task(trans: &mut Trans<'_>, task: &DeliveryTask) -> Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+    )
+    .execute(trans)
+    .await
+    .context("Failed to delete delivery task")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Reschedule delivery task", skip(trans))]
+async fn reschedule_task(
+    trans: &mut Trans<'_>,
+    task: &DeliveryTask,
+    n_attempts: i32,
+    next_attempt_at: DateTime<Utc>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_attempts = $3, next_attempt_at = $4
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_attempts,
+        next_attempt_at,
+    )
+    .execute(trans)
+    .await
+    .context("Failed to reschedule delivery task")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Dead-letter delivery task", skip(trans))]
+async fn dead_letter_task(
+    trans: &mut Trans<'_>,
+    task: &DeliveryTask,
+    n_attempts: i32,
+    last_error: &str,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_attempts,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_attempts = EXCLUDED.n_attempts,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_attempts,
+        last_error,
+    )
+    .execute(&mut *trans)
+    .await
+    .context("Failed to store dead-lettered delivery task")?;
+
+    delete_task(trans, task).await
+}
+
+/// Issues are sent once none of their deliveries are left in the queue, dead-lettered ones
+/// included.
+///
+/// This runs whenever the queue looks empty rather than after each batch, since concurrent
+/// workers can't see each other's deliveries leave the queue until they commit.
+#[tracing::instrument(name = "Mark sent issues", skip(trans))]
+async fn mark_sent_issues(trans: &mut Trans<'_>) -> Result<()> {
+    let n_sent = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET status = 'sent'
+        WHERE i.status = 'sending'
+            AND NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            )
+        "#
+    )
+    .execute(trans)
+    .await
+    .context("Failed to mark issues as sent")?
+    .rows_affected();
+
+    if n_sent > 0 {
+        tracing::info!("Marked {} issues as sent", n_sent);
+    }
+
+    Ok(())
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+/// An issue as it is sent to one subscriber, with their own unsubscribe link.
+#[derive(Template)]
+#[template(path = "emails/newsletter_issue.html")]
+struct NewsletterEmailHtml<'a> {
+    content: &'a str,
+    unsubscribe_url: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/newsletter_issue.txt")]
+struct NewsletterEmailText<'a> {
+    content: &'a str,
+    unsubscribe_url: &'a str,
+}
+
+#[tracing::instrument(name = "Get newsletter issue", skip(trans))]
+async fn get_issue(trans: &mut Trans<'_>, issue_id: &Uuid) -> Result<NewsletterIssue> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_one(trans)
+    .await
+    .context("Failed to retrieve newsletter issue")?;
+
+    Ok(issue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        retry_delay, NewsletterEmailHtml, NewsletterEmailText, BASE_RETRY_DELAY_SECS,
+        MAX_RETRY_DELAY_SECS,
+    };
+    use askama::Template;
+
+    #[test]
+    fn first_retry_waits_up_to_the_base_delay() {
+        for _ in 0..100 {
+            let delay = retry_delay(1).num_seconds();
+            assert!((BASE_RETRY_DELAY_SECS / 2..=BASE_RETRY_DELAY_SECS).contains(&delay));
+        }
+    }
+
+    #[test]
+    fn retry_delay_doubles_with_each_attempt() {
+        for _ in 0..100 {
+            let delay = retry_delay(3).num_seconds();
+            let max_delay = BASE_RETRY_DELAY_SECS * 4;
+            assert!((max_delay / 2..=max_delay).contains(&delay));
+        }
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        for n_attempts in [20, 100, i32::MAX] {
+            let delay = retry_delay(n_attempts).num_seconds();
+            assert!(delay <= MAX_RETRY_DELAY_SECS);
+            assert!(delay >= MAX_RETRY_DELAY_SECS / 2);
+        }
+    }
+
+    #[test]
+    fn issue_content_is_sent_as_written() {
+        let unsubscribe_url = "https://example.com/unsubscribe?unsubscribe_token=abc";
+        let html = NewsletterEmailHtml {
+            content: "<p>Fish &amp; chips</p>",
+            unsubscribe_url,
+        }
+        .render()
+        .unwrap();
+        assert!(html.starts_with("<p>Fish &amp; chips</p>\n"));
+        assert!(html.contains(&format!(r#"<a href="{}">"#, unsubscribe_url)));
+
+        let text = NewsletterEmailText {
+            content: "Fish & chips <3",
+            unsubscribe_url,
+        }
+        .render()
+        .unwrap();
+        assert_eq!(
+            text,
+            format!(
+                "Fish & chips <3\n\nTo unsubscribe from this newsletter, visit {}",
+                unsubscribe_url
+            )
+        );
+    }
+}
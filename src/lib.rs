@@ -1,16 +1,23 @@
+pub mod audit;
 pub mod authentication;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod newsletter_scheduler;
+pub mod password_reset_worker;
+pub mod rate_limit;
 pub mod routes;
 pub mod session_state;
 pub mod startup;
+pub mod subscription_cleanup_worker;
 pub mod telemetry;
 mod utils;
 
 pub use {
     configuration::get_configuration,
-    email_client::EmailClient,
+    email_client::EmailSender,
     startup::{get_connection_pool, Application},
     telemetry::{get_subscriber, init_subscriber},
 };
@@ -1,4 +1,13 @@
-use zero2prod::*;
+use zero2prod::{
+    issue_delivery_worker::run_worker_until_stopped,
+    newsletter_scheduler::run_scheduler_until_stopped,
+    password_reset_worker::run_password_reset_worker_until_stopped,
+    subscription_cleanup_worker::run_cleanup_until_stopped, *,
+};
+
+use std::fmt::{Debug, Display};
+
+use tokio::task::JoinError;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -8,8 +17,47 @@ async fn main() -> anyhow::Result<()> {
     let configuration = get_configuration().expect("Failed to read configuration");
     tracing::info!("All config values: {:#?}", configuration);
 
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    let application = Application::build(configuration.clone()).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
+    let scheduler_task = tokio::spawn(run_scheduler_until_stopped(configuration.clone()));
+    let reset_task = tokio::spawn(run_password_reset_worker_until_stopped(
+        configuration.clone(),
+    ));
+    let cleanup_task = tokio::spawn(run_cleanup_until_stopped(configuration));
+
+    // Bring the whole process down as soon as any task exits
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Background worker", o),
+        o = scheduler_task => report_exit("Newsletter scheduler", o),
+        o = reset_task => report_exit("Password reset worker", o),
+        o = cleanup_task => report_exit("Subscription cleanup", o),
+    };
 
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{
+    configuration::Settings, domain::IssueStatus, issue_delivery_worker::ExecutionOutcome,
+    routes::send_newsletter_issue, startup::get_connection_pool,
+};
+
+use std::time::Duration;
+
+use {
+    anyhow::{Context, Result},
+    sqlx::PgPool,
+    tracing::Span,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+pub async fn run_scheduler_until_stopped(configuration: Settings) -> Result<()> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    scheduler_loop(connection_pool).await
+}
+
+async fn scheduler_loop(pool: PgPool) -> Result<()> {
+    loop {
+        match send_due_issue(&pool).await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(POLL_INTERVAL).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// Start delivering the scheduled issue that has been due the longest, if any.
+///
+/// Every app instance runs a scheduler. The issue's row stays locked until its deliveries are
+/// queued up, so other schedulers skip it, and admins can't cancel or reschedule it halfway.
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id = tracing::field::Empty, n_recipients = tracing::field::Empty),
+    err
+)]
+pub async fn send_due_issue(pool: &PgPool) -> Result<ExecutionOutcome> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")?;
+
+    let issue_id = match sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND send_at <= now()
+        ORDER BY send_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to look for due issues")?
+    {
+        Some(row) => row.newsletter_issue_id,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("newsletter_issue_id", &tracing::field::display(&issue_id));
+
+    let n_recipients = send_newsletter_issue(&issue_id, IssueStatus::Scheduled, &mut transaction)
+        .await
+        .context("Failed to enqueue delivery tasks")?
+        .context("The due issue is no longer scheduled")?;
+    Span::current().record("n_recipients", &n_recipients);
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to send a scheduled issue")?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
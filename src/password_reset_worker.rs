@@ -0,0 +1,277 @@
+use crate::{
+    authentication::PasswordResetToken, configuration::Settings, domain::SubscriberEmail,
+    email_client::EmailSender, issue_delivery_worker::ExecutionOutcome,
+    startup::get_connection_pool,
+};
+
+use std::{sync::Arc, time::Duration};
+
+use {
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::Utc,
+    sqlx::PgPool,
+    tracing::Span,
+    uuid::Uuid,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Requests whose email keeps failing transiently are dropped after this many attempts.
+const MAX_RESET_ATTEMPTS: i32 = 5;
+const RETRY_DELAY_SECS: i64 = 60;
+/// Claimed requests are left alone by other workers for this long, see `claim_request`.
+const RESET_CLAIM_SECS: i64 = 5 * 60;
+
+pub async fn run_password_reset_worker_until_stopped(configuration: Settings) -> Result<()> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    let reset_expiry = configuration.application.password_reset_expiry();
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.application.base_url,
+        reset_expiry,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    base_url: String,
+    reset_expiry: chrono::Duration,
+) -> Result<()> {
+    loop {
+        match try_send_reset_email(&pool, email_client.as_ref(), &base_url, reset_expiry).await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(POLL_INTERVAL).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// Answer the password reset request that has been waiting the longest, if any.
+///
+/// Requests are queued by username, whether or not it belongs to anyone, so that
+/// `POST /login/forgot` can't be used to find out which accounts exist. Requests for unknown or
+/// inactive users are dropped here. Otherwise a new reset link, valid for `reset_expiry`, is
+/// emailed to the user.
+#[tracing::instrument(
+    skip_all,
+    fields(request_id = tracing::field::Empty, user_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_send_reset_email(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+    reset_expiry: chrono::Duration,
+) -> Result<ExecutionOutcome> {
+    let request = match claim_request(pool).await? {
+        Some(request) => request,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("request_id", &tracing::field::display(&request.request_id));
+
+    if request.n_attempts > MAX_RESET_ATTEMPTS {
+        tracing::error!("Giving up on a password reset request that keeps failing");
+        delete_request(pool, &request).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+
+    let (user_id, recipient) = match get_user_email(&request.username, pool).await? {
+        Some(user) => user,
+        None => {
+            delete_request(pool, &request).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    Span::current().record("user_id", &tracing::field::display(&user_id));
+
+    let token = PasswordResetToken::generate();
+    let reset_link = format!("{}/login/reset?token={}", base_url, token.as_ref());
+    let html_body = PasswordResetEmailHtml {
+        reset_link: &reset_link,
+    }
+    .render()
+    .context("Failed to render the HTML version of the password reset email")?;
+    let text_body = PasswordResetEmailText {
+        reset_link: &reset_link,
+    }
+    .render()
+    .context("Failed to render the text version of the password reset email")?;
+    store_reset_token(&user_id, &token, reset_expiry, pool).await?;
+
+    match email_client
+        .send_email(&recipient, "Reset your password", &html_body, &text_body)
+        .await
+    {
+        Ok(()) => delete_request(pool, &request).await?,
+        Err(e) if e.is_transient() && request.n_attempts < MAX_RESET_ATTEMPTS => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                n_attempts = request.n_attempts,
+                "Failed to send a password reset email. Retrying later."
+            );
+            let next_attempt_at = Utc::now() + chrono::Duration::seconds(RETRY_DELAY_SECS);
+            reschedule_request(pool, &request, next_attempt_at).await?
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                n_attempts = request.n_attempts,
+                "Failed to send a password reset email. Giving up."
+            );
+            delete_request(pool, &request).await?
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[derive(Debug)]
+struct ResetRequest {
+    request_id: Uuid,
+    username: String,
+    n_attempts: i32,
+}
+
+/// Claim the request that has been due the longest, counting the attempt that is about to be
+/// made.
+///
+/// The claim pushes the request back by `RESET_CLAIM_SECS`, so that other workers skip it while
+/// its email is being sent without a lock being held. If the worker dies halfway, the request
+/// becomes due again when the claim runs out.
+#[tracing::instrument(name = "Claim password reset request", skip(pool))]
+async fn claim_request(pool: &PgPool) -> Result<Option<ResetRequest>> {
+    let request = sqlx::query_as!(
+        ResetRequest,
+        r#"
+        WITH due AS (
+            SELECT request_id
+            FROM password_reset_queue
+            WHERE next_attempt_at <= now()
+            ORDER BY next_attempt_at
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+        )
+        UPDATE password_reset_queue q
+        SET
+            n_attempts = q.n_attempts + 1,
+            next_attempt_at = $1
+        FROM due
+        WHERE q.request_id = due.request_id
+        RETURNING q.request_id, q.username, q.n_attempts
+        "#,
+        Utc::now() + chrono::Duration::seconds(RESET_CLAIM_SECS),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to claim a password reset request")?;
+
+    Ok(request)
+}
+
+#[tracing::instrument(name = "Delete password reset request", skip(pool))]
+async fn delete_request(pool: &PgPool, request: &ResetRequest) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM password_reset_queue WHERE request_id = $1",
+        request.request_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete password reset request")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Reschedule password reset request", skip(pool))]
+async fn reschedule_request(
+    pool: &PgPool,
+    request: &ResetRequest,
+    next_attempt_at: chrono::DateTime<Utc>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_queue
+        SET next_attempt_at = $2
+        WHERE request_id = $1
+        "#,
+        request.request_id,
+        next_attempt_at,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to reschedule password reset request")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get user email", skip(db_pool))]
+async fn get_user_email(
+    username: &str,
+    db_pool: &PgPool,
+) -> Result<Option<(Uuid, SubscriberEmail)>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, email AS "email!"
+        FROM users
+        WHERE username = $1 AND is_active AND email IS NOT NULL
+        "#,
+        username
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to retrieve the user's email address")?;
+
+    match row {
+        Some(row) => match SubscriberEmail::parse(row.email) {
+            Some(email) => Ok(Some((row.user_id, email))),
+            None => {
+                tracing::warn!(user_id = %row.user_id, "Skipping a user with an invalid stored email");
+                Ok(None)
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+#[tracing::instrument(name = "Store password reset token", skip(token, db_pool))]
+async fn store_reset_token(
+    user_id: &Uuid,
+    token: &PasswordResetToken,
+    expiry: chrono::Duration,
+    db_pool: &PgPool,
+) -> Result<()> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        token.digest(),
+        user_id,
+        now,
+        now + expiry,
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to store password reset token")?;
+
+    Ok(())
+}
+
+#[derive(Template)]
+#[template(path = "emails/password_reset.html")]
+struct PasswordResetEmailHtml<'a> {
+    reset_link: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/password_reset.txt")]
+struct PasswordResetEmailText<'a> {
+    reset_link: &'a str,
+}
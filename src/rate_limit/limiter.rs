@@ -0,0 +1,93 @@
+use crate::configuration::RateLimitSettings;
+
+use std::time::Duration;
+
+use {
+    anyhow::{Context, Result},
+    chrono::Utc,
+    redis::{aio::ConnectionManager, Script},
+    secrecy::{ExposeSecret, Secret},
+};
+
+/// Takes a token from the bucket in `KEYS[1]`, refilling it first.
+///
+/// ARGV holds the capacity, the refill interval and the current time, both in milliseconds.
+/// Returns 0 if a token was taken, otherwise the number of milliseconds until one is available.
+/// Running it as a script keeps the read-modify-write atomic across instances.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local interval = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'refilled_at')
+local tokens = tonumber(bucket[1]) or capacity
+local refilled_at = tonumber(bucket[2]) or now
+
+local refills = math.floor((now - refilled_at) / interval)
+if refills > 0 then
+    tokens = math.min(capacity, tokens + refills)
+    refilled_at = refilled_at + refills * interval
+end
+if tokens >= capacity then
+    refilled_at = now
+end
+
+local retry_after = 0
+if tokens > 0 then
+    tokens = tokens - 1
+else
+    retry_after = refilled_at + interval - now
+end
+
+redis.call('HSET', KEYS[1], 'tokens', tokens, 'refilled_at', refilled_at)
+redis.call('PEXPIRE', KEYS[1], capacity * interval)
+return retry_after
+"#;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RateLimitDecision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+/// Token bucket rate limiting, stored in Redis so that limits hold across instances.
+pub struct RateLimiter {
+    connection: ConnectionManager,
+    script: Script,
+}
+
+impl RateLimiter {
+    pub async fn new(redis_uri: &Secret<String>) -> Result<Self> {
+        let client =
+            redis::Client::open(redis_uri.expose_secret().as_str()).context("Invalid Redis URI")?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis")?;
+
+        Ok(Self {
+            connection,
+            script: Script::new(TOKEN_BUCKET_SCRIPT),
+        })
+    }
+
+    /// Take a token from the bucket stored under `key`.
+    #[tracing::instrument(name = "Acquire rate limit token", skip(self))]
+    pub async fn acquire(&self, key: &str, limit: &RateLimitSettings) -> Result<RateLimitDecision> {
+        let retry_after_millis: u64 = self
+            .script
+            .key(key)
+            .arg(limit.capacity)
+            .arg(limit.refill_interval_seconds * 1000)
+            .arg(Utc::now().timestamp_millis())
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .context("Failed to run the rate limiting script")?;
+
+        Ok(match retry_after_millis {
+            0 => RateLimitDecision::Allowed,
+            millis => RateLimitDecision::Limited {
+                retry_after: Duration::from_millis(millis),
+            },
+        })
+    }
+}
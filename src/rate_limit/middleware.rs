@@ -0,0 +1,158 @@
+use super::{RateLimitDecision, RateLimiter};
+use crate::{
+    configuration::{
+        PasswordResetRateLimitSettings, RateLimitSettings, SubscriptionRateLimitSettings,
+    },
+    utils::{client_ip, peek_body},
+};
+
+use {
+    actix_web::{
+        body::MessageBody,
+        dev::{ServiceRequest, ServiceResponse},
+        http::header::RETRY_AFTER,
+        web, HttpResponse,
+    },
+    actix_web_lab::middleware::Next,
+    sha2::{Digest, Sha256},
+};
+
+#[derive(serde::Deserialize)]
+struct EmailField {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+struct UsernameField {
+    username: String,
+}
+
+/// Limit how often a client can make us send subscription emails, and how many a single
+/// address can receive, answering with `429 Too Many Requests` once either limit is reached.
+///
+/// If Redis can't be reached the request goes through, a signup form that is down is worse than
+/// a few extra emails.
+pub async fn limit_subscription_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .expect("The rate limiter is not registered as app data")
+        .clone();
+    let settings = req
+        .app_data::<web::Data<SubscriptionRateLimitSettings>>()
+        .expect("The subscription rate limits are not registered as app data")
+        .clone();
+
+    let client_ip = client_ip(req.parts_mut().0);
+    let body = peek_body(&mut req).await?;
+    let email = serde_urlencoded::from_bytes::<EmailField>(&body)
+        .ok()
+        .map(|f| f.email.trim().to_lowercase());
+
+    // Addresses are hashed so that they don't end up in Redis in clear
+    let mut buckets = vec![(
+        format!("{}:subscriptions:ip:{}", settings.key_prefix, client_ip),
+        &settings.per_ip,
+    )];
+    if let Some(email) = email {
+        buckets.push((
+            format!(
+                "{}:subscriptions:email:{}",
+                settings.key_prefix,
+                hex::encode(Sha256::digest(email.as_bytes()))
+            ),
+            &settings.per_email,
+        ));
+    }
+
+    if let Some(response) = check_buckets(&limiter, buckets).await {
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Limit how often a client can ask for password reset emails, and how many can be asked for a
+/// single account, answering with `429 Too Many Requests` once either limit is reached.
+///
+/// Accounts are limited by the username in the form, whether or not it exists, so the limit
+/// doesn't tell existing accounts apart either. If Redis can't be reached the request goes
+/// through, like for subscriptions.
+pub async fn limit_password_reset_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .expect("The rate limiter is not registered as app data")
+        .clone();
+    let settings = req
+        .app_data::<web::Data<PasswordResetRateLimitSettings>>()
+        .expect("The password reset rate limits are not registered as app data")
+        .clone();
+
+    let client_ip = client_ip(req.parts_mut().0);
+    let body = peek_body(&mut req).await?;
+    let username = serde_urlencoded::from_bytes::<UsernameField>(&body)
+        .ok()
+        .map(|f| f.username.trim().to_owned());
+
+    let mut buckets = vec![(
+        format!("{}:password_reset:ip:{}", settings.key_prefix, client_ip),
+        &settings.per_ip,
+    )];
+    if let Some(username) = username {
+        buckets.push((
+            format!(
+                "{}:password_reset:account:{}",
+                settings.key_prefix,
+                hex::encode(Sha256::digest(username.as_bytes()))
+            ),
+            &settings.per_account,
+        ));
+    }
+
+    if let Some(response) = check_buckets(&limiter, buckets).await {
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Take a token from each bucket in turn, returning the response to reject the request with if
+/// one of them is empty.
+async fn check_buckets(
+    limiter: &RateLimiter,
+    buckets: Vec<(String, &RateLimitSettings)>,
+) -> Option<HttpResponse> {
+    for (key, limit) in buckets {
+        match limiter.acquire(&key, limit).await {
+            Ok(RateLimitDecision::Allowed) => {}
+            Ok(RateLimitDecision::Limited { retry_after }) => {
+                tracing::warn!(
+                    rate_limit_key = %key,
+                    retry_after_seconds = retry_after.as_secs(),
+                    "Rejected a request over its rate limit"
+                );
+                // Round up, retrying a little early would only be rejected again
+                let retry_after = (retry_after.as_millis() + 999) / 1000;
+                return Some(
+                    HttpResponse::TooManyRequests()
+                        .insert_header((RETRY_AFTER, retry_after.to_string()))
+                        .finish(),
+                );
+            }
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Failed to check rate limits");
+                break;
+            }
+        }
+    }
+    None
+}
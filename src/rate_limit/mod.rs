@@ -0,0 +1,7 @@
+mod limiter;
+mod middleware;
+
+pub use {
+    limiter::{RateLimitDecision, RateLimiter},
+    middleware::{limit_password_reset_requests, limit_subscription_requests},
+};
@@ -0,0 +1,77 @@
+use crate::{
+    authentication::{form_csrf_token, ApiScope, Role, UserId},
+    session_state::TypedSession,
+    utils::{e500, render_page},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::{DateTime, Utc},
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+struct ApiTokenSummary {
+    api_token_id: Uuid,
+    name: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/api_tokens/list.html")]
+struct ApiTokensPage {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+    tokens: Vec<ApiTokenSummary>,
+    /// The scopes new tokens can have.
+    allowed_scopes: Vec<ApiScope>,
+}
+
+#[tracing::instrument(name = "Get API tokens page", skip(flash_messages, db_pool, session))]
+pub async fn api_tokens_page(
+    flash_messages: IncomingFlashMessages,
+    db_pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let role = role.into_inner();
+    let tokens = get_api_tokens(&user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+
+    render_page(&ApiTokensPage {
+        flash_messages,
+        csrf_token: form_csrf_token(&session)?,
+        tokens,
+        // Only offer the scopes the user's role allows
+        allowed_scopes: ApiScope::ALL
+            .into_iter()
+            .filter(|scope| scope.required_role() <= role)
+            .collect(),
+    })
+}
+
+#[tracing::instrument(name = "Get API tokens", skip(db_pool))]
+async fn get_api_tokens(user_id: &Uuid, db_pool: &PgPool) -> Result<Vec<ApiTokenSummary>> {
+    let tokens = sqlx::query_as!(
+        ApiTokenSummary,
+        r#"
+        SELECT api_token_id, name, scopes, created_at, last_used_at
+        FROM api_tokens
+        WHERE user_id = $1 AND revoked_at IS NULL
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve API tokens")?;
+
+    Ok(tokens)
+}
@@ -0,0 +1,7 @@
+mod get;
+mod post;
+
+pub use {
+    get::api_tokens_page,
+    post::{create_api_token, revoke_api_token},
+};
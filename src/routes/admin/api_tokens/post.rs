@@ -0,0 +1,146 @@
+use crate::{
+    authentication::{ApiScope, ApiToken, Role, UserId},
+    utils::{e500, see_other},
+};
+
+use std::collections::HashMap;
+
+use {
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::Utc,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(serde::Deserialize)]
+pub struct NewApiTokenFormData {
+    name: String,
+    /// Every checked scope is a field of its own, named after the scope.
+    #[serde(flatten)]
+    fields: HashMap<String, String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/api_tokens/created.html")]
+struct ApiTokenCreatedPage<'a> {
+    name: &'a str,
+    token: &'a ApiToken,
+}
+
+#[tracing::instrument(
+    name = "Create an API token",
+    skip(form, db_pool),
+    fields(name = %form.name)
+)]
+pub async fn create_api_token(
+    form: web::Form<NewApiTokenFormData>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let role = role.into_inner();
+    let NewApiTokenFormData { name, fields } = form.0;
+    let name = name.trim();
+    let scopes: Vec<ApiScope> = ApiScope::ALL
+        .into_iter()
+        .filter(|scope| fields.contains_key(scope.as_str()))
+        .collect();
+
+    let problem = if name.is_empty() {
+        Some("Please give the token a name.".to_string())
+    } else if name.chars().count() > MAX_NAME_LENGTH {
+        Some(format!(
+            "Token names must be no more than {} characters.",
+            MAX_NAME_LENGTH
+        ))
+    } else if scopes.is_empty() {
+        Some("Please choose at least one scope.".to_string())
+    } else {
+        scopes
+            .iter()
+            .find(|scope| scope.required_role() > role)
+            .map(|scope| format!("Your role does not allow the {} scope.", scope))
+    };
+    if let Some(problem) = problem {
+        FlashMessage::error(problem).send();
+        return Ok(see_other("/admin/api-tokens"));
+    }
+
+    let token = ApiToken::generate();
+    insert_api_token(&user_id.into_inner(), name, &scopes, &token, &db_pool)
+        .await
+        .map_err(e500)?;
+
+    // The token is never shown again, so it is rendered straight away rather than redirecting
+    let body = ApiTokenCreatedPage {
+        name,
+        token: &token,
+    }
+    .render()
+    .map_err(e500)?;
+    Ok(HttpResponse::Created()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "Revoke an API token", skip(db_pool))]
+pub async fn revoke_api_token(
+    api_token_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let n_revoked = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE api_token_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        *api_token_id,
+        **user_id,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .context("Failed to revoke the API token")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_revoked == 0 {
+        FlashMessage::error("That token does not exist.").send();
+    } else {
+        FlashMessage::info("The token has been revoked.").send();
+    }
+    Ok(see_other("/admin/api-tokens"))
+}
+
+#[tracing::instrument(name = "Store API token", skip(token, db_pool))]
+async fn insert_api_token(
+    user_id: &Uuid,
+    name: &str,
+    scopes: &[ApiScope],
+    token: &ApiToken,
+    db_pool: &PgPool,
+) -> Result<()> {
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_owned()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (api_token_id, user_id, name, token_hash, scopes, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        token.digest(),
+        &scopes,
+        Utc::now(),
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to store the API token")?;
+
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use crate::{
+    audit::AuditAction,
+    utils::{e400, e500, render_page},
+};
+
+use std::fmt::Write;
+
+use {
+    actix_web::{
+        http::header::{ContentDisposition, DispositionParam, DispositionType},
+        web, HttpResponse,
+    },
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::{DateTime, Utc},
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+const EVENTS_PER_PAGE: i64 = 25;
+/// Exports are meant for a spreadsheet or a SIEM, not for dumping the table in one go.
+const MAX_EXPORTED_EVENTS: i64 = 10_000;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct AuditQueryParams {
+    action: Option<String>,
+    user_id: Option<String>,
+    page: Option<i64>,
+}
+
+/// What the audit log is filtered on, parsed from `AuditQueryParams`.
+#[derive(Debug, Default)]
+struct AuditFilter {
+    action: Option<AuditAction>,
+    user_id: Option<Uuid>,
+}
+
+impl AuditFilter {
+    /// Empty fields are what the filter form sends for "any".
+    fn parse(params: &AuditQueryParams) -> Result<Self, actix_web::Error> {
+        let action = match params.action.as_deref() {
+            None | Some("") => None,
+            Some(action) => Some(AuditAction::parse(action).map_err(e400)?),
+        };
+        let user_id = match params.user_id.as_deref() {
+            None | Some("") => None,
+            Some(user_id) => Some(Uuid::parse_str(user_id).map_err(e400)?),
+        };
+        Ok(Self { action, user_id })
+    }
+
+    /// The filter as a query string, to carry it over to other pages.
+    fn query_string(&self) -> String {
+        let mut query = String::new();
+        if let Some(action) = self.action {
+            write!(query, "&action={}", action).unwrap();
+        }
+        if let Some(user_id) = self.user_id {
+            write!(query, "&user_id={}", user_id).unwrap();
+        }
+        query
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct AuditEntry {
+    audit_event_id: Uuid,
+    occurred_at: DateTime<Utc>,
+    user_id: Option<Uuid>,
+    username: Option<String>,
+    action: String,
+    client_ip: String,
+    details: serde_json::Value,
+}
+
+struct Collaborator {
+    user_id: Uuid,
+    username: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/audit.html")]
+struct AuditLogPage {
+    filter: AuditFilter,
+    /// The filter as a query string, for the paging and export links.
+    filter_query: String,
+    collaborators: Vec<Collaborator>,
+    entries: Vec<AuditEntry>,
+    page: i64,
+    has_next_page: bool,
+}
+
+#[tracing::instrument(name = "Get audit log page", skip(db_pool))]
+pub async fn audit_log_page(
+    params: web::Query<AuditQueryParams>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = AuditFilter::parse(&params)?;
+    let page = params.page.unwrap_or(1).max(1);
+    let offset = (page - 1)
+        .checked_mul(EVENTS_PER_PAGE)
+        .ok_or_else(|| e400("The page number is too large"))?;
+
+    // One more than needed, to know whether there is a next page
+    let mut entries = get_audit_entries(&filter, EVENTS_PER_PAGE + 1, offset, &db_pool)
+        .await
+        .map_err(e500)?;
+    let has_next_page = entries.len() as i64 > EVENTS_PER_PAGE;
+    entries.truncate(EVENTS_PER_PAGE as usize);
+
+    render_page(&AuditLogPage {
+        collaborators: get_collaborators(&db_pool).await.map_err(e500)?,
+        filter_query: filter.query_string(),
+        filter,
+        entries,
+        page,
+        has_next_page,
+    })
+}
+
+/// The most recent events matching the filter, as a JSON download.
+#[tracing::instrument(name = "Export audit log", skip(db_pool))]
+pub async fn export_audit_log(
+    params: web::Query<AuditQueryParams>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = AuditFilter::parse(&params)?;
+    let entries = get_audit_entries(&filter, MAX_EXPORTED_EVENTS, 0, &db_pool)
+        .await
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("audit-log.json".into())],
+        })
+        .json(serde_json::json!({ "events": entries })))
+}
+
+#[tracing::instrument(name = "Get audit log entries", skip(db_pool))]
+async fn get_audit_entries(
+    filter: &AuditFilter,
+    limit: i64,
+    offset: i64,
+    db_pool: &PgPool,
+) -> Result<Vec<AuditEntry>> {
+    let entries = sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT
+            e.audit_event_id,
+            e.occurred_at,
+            e.user_id,
+            u.username AS "username?",
+            e.action,
+            e.client_ip,
+            e.details
+        FROM audit_events e
+        LEFT JOIN users u ON u.user_id = e.user_id
+        WHERE ($1::text IS NULL OR e.action = $1)
+            AND ($2::uuid IS NULL OR e.user_id = $2)
+        ORDER BY e.occurred_at DESC
+        LIMIT $3 OFFSET $4
+        "#,
+        filter.action.map(|action| action.as_str()),
+        filter.user_id,
+        limit,
+        offset,
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve audit events")?;
+
+    Ok(entries)
+}
+
+#[tracing::instrument(name = "Get collaborators to filter on", skip(db_pool))]
+async fn get_collaborators(db_pool: &PgPool) -> Result<Vec<Collaborator>> {
+    let collaborators = sqlx::query_as!(
+        Collaborator,
+        r#"SELECT user_id, username FROM users ORDER BY username"#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve collaborators")?;
+
+    Ok(collaborators)
+}
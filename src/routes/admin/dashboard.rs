@@ -1,48 +1,45 @@
-use crate::{authentication::UserId, utils::e500};
+use crate::{
+    authentication::{form_csrf_token, Role, UserId},
+    session_state::TypedSession,
+    utils::{e500, render_page},
+};
 
 use {
-    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web::{web, HttpResponse},
     anyhow::{Context, Result},
+    askama::Template,
     sqlx::PgPool,
     uuid::Uuid,
 };
 
-#[tracing::instrument(name = "Get admin dashboard", skip(db_pool))]
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardPage {
+    username: String,
+    role: Role,
+    can_edit: bool,
+    can_manage: bool,
+    csrf_token: String,
+}
+
+#[tracing::instrument(name = "Get admin dashboard", skip(db_pool, session))]
 pub async fn admin_dashboard(
     user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
     db_pool: web::Data<PgPool>,
+    session: TypedSession,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
+    let role = role.into_inner();
     let username = get_username(&user_id, &db_pool).await.map_err(e500)?;
 
-    let response = HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"
-<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Admin dashboard</title>
-</head>
-<body>
-    <p>Welcome {username}!</p>
-    <p>Available actions:</p>
-    <ol>
-            <li><a href="/admin/newsletters">Send a new issue</a></li>
-            <li><a href="/admin/password">Change password</a></li>
-            <li>
-                <form name="logoutForm" action="/admin/logout" method="post">
-                    <input type="submit" value="Logout">
-                </form>
-            </li
-    </ol>
-</body>
-</html>
-            "#
-        ));
-
-    Ok(response)
+    render_page(&DashboardPage {
+        username,
+        role,
+        can_edit: role >= Role::Editor,
+        can_manage: role >= Role::Owner,
+        csrf_token: form_csrf_token(&session)?,
+    })
 }
 
 pub async fn get_username(user_id: &Uuid, db_pool: &PgPool) -> Result<String> {
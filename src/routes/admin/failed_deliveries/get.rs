@@ -0,0 +1,76 @@
+use crate::{
+    authentication::{form_csrf_token, Role},
+    session_state::TypedSession,
+    utils::{e500, render_page},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::{DateTime, Utc},
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+struct FailedDelivery {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_attempts: i32,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletters/failed_deliveries.html")]
+struct FailedDeliveriesPage {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+    failed_deliveries: Vec<FailedDelivery>,
+    /// Viewers can look, but not requeue deliveries.
+    can_edit: bool,
+}
+
+#[tracing::instrument(
+    name = "Get failed deliveries page",
+    skip(flash_messages, db_pool, session)
+)]
+pub async fn failed_deliveries_page(
+    flash_messages: IncomingFlashMessages,
+    db_pool: web::Data<PgPool>,
+    session: TypedSession,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_page(&FailedDeliveriesPage {
+        flash_messages,
+        csrf_token: form_csrf_token(&session)?,
+        failed_deliveries: get_failed_deliveries(&db_pool).await.map_err(e500)?,
+        can_edit: *role >= Role::Editor,
+    })
+}
+
+#[tracing::instrument(name = "Get failed deliveries", skip(db_pool))]
+async fn get_failed_deliveries(db_pool: &PgPool) -> Result<Vec<FailedDelivery>> {
+    let failed_deliveries = sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT
+            d.newsletter_issue_id,
+            i.title,
+            d.subscriber_email,
+            d.n_attempts,
+            d.last_error,
+            d.failed_at
+        FROM issue_delivery_dead_letters d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        ORDER BY d.failed_at DESC
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve failed deliveries")?;
+
+    Ok(failed_deliveries)
+}
@@ -0,0 +1,4 @@
+mod get;
+mod post;
+
+pub use {get::failed_deliveries_page, post::requeue_failed_delivery};
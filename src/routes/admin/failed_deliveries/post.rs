@@ -0,0 +1,89 @@
+use crate::utils::{e500, see_other};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::{Context, Result},
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct FormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+#[tracing::instrument(name = "Requeue a failed delivery", skip(form, db_pool))]
+pub async fn requeue_failed_delivery(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let requeued = requeue_dead_letter(&form.newsletter_issue_id, &form.subscriber_email, &db_pool)
+        .await
+        .map_err(e500)?;
+
+    if requeued {
+        FlashMessage::info("The delivery has been requeued.").send();
+    } else {
+        FlashMessage::error("That delivery is no longer in the list of failed deliveries.").send();
+    }
+
+    Ok(see_other("/admin/newsletters/failed"))
+}
+
+/// Move a dead-lettered delivery back into the queue with a fresh set of attempts.
+async fn requeue_dead_letter(
+    newsletter_issue_id: &Uuid,
+    subscriber_email: &str,
+    db_pool: &PgPool,
+) -> Result<bool> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")?;
+
+    let n_deleted = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_dead_letters
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_email,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to remove failed delivery")?
+    .rows_affected();
+
+    if n_deleted == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to requeue delivery")?;
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET status = 'sending' WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to mark the issue as sending")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to requeue delivery")?;
+
+    Ok(true)
+}
@@ -1,10 +1,35 @@
-use crate::{session_state::TypedSession, utils::see_other};
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{SessionRegistry, UserId},
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
 
-use {actix_web::HttpResponse, actix_web_flash_messages::FlashMessage};
+use {
+    actix_web::{web, HttpRequest, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    sqlx::PgPool,
+};
 
-#[tracing::instrument(skip(session))]
-pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
+#[tracing::instrument(skip(session, registry, db_pool, request))]
+pub async fn log_out(
+    session: TypedSession,
+    registry: web::Data<SessionRegistry>,
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
     // Assume middleware will have rejected a non-logged-in user already.
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        registry
+            .revoke(&user_id.into_inner(), &session_id)
+            .await
+            .map_err(e500)?;
+    }
+    let event = AuditEvent::new(AuditAction::Logout, serde_json::json!({}));
+    record_audit_event(&request, event, db_pool.get_ref())
+        .await
+        .map_err(e500)?;
     session.log_out();
     tracing::info!("User logged out");
     FlashMessage::info("You have successfully logged out.").send();
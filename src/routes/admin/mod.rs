@@ -1,6 +1,19 @@
+mod api_tokens;
+mod audit;
 mod dashboard;
+mod failed_deliveries;
 mod logout;
 mod newsletter;
+mod newsletter_drafts;
+mod newsletter_history;
+mod newsletter_schedule;
 mod password;
+mod sessions;
+mod two_factor;
+mod users;
 
-pub use {dashboard::*, logout::*, newsletter::*, password::*};
+pub use {
+    api_tokens::*, audit::*, dashboard::*, failed_deliveries::*, logout::*, newsletter::*,
+    newsletter_drafts::*, newsletter_history::*, newsletter_schedule::*, password::*, sessions::*,
+    two_factor::*, users::*,
+};
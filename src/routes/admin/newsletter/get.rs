@@ -1,46 +1,25 @@
-use std::fmt::Write;
+use crate::{authentication::form_csrf_token, session_state::TypedSession, utils::render_page};
 
-use {actix_web::HttpResponse, actix_web_flash_messages::IncomingFlashMessages};
+use {
+    actix_web::HttpResponse, actix_web_flash_messages::IncomingFlashMessages, askama::Template,
+    uuid::Uuid,
+};
 
-pub async fn get_newsletter_page(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
-    }
-
-    let body = format!(
-        r#"
-<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>New newsletter issue</title>
-</head>
-<body>
-    {msg_html}
-    <form name="newIssue" action="/admin/newsletters" method="post">
-        <label>
-            Title
-            <input type="text" placeholder="Issue Title" name="title">
-        </label>
-        <br />
-        <label>
-            Text content
-            <input type="text" placeholder="Text content" name="text_content">
-        </label>
-        <br />
-        <label>
-            HTML content
-            <input type="text" placeholder="HTML Content" name="html_content">
-        </label>
-        <br />
-        <button type="submit">Send</button>
-    </form>
-    <p><a href="/admin/dashboard">&lt;- Back</a></p>
-</body>
-</html>
-        "#
-    );
+#[derive(Template)]
+#[template(path = "admin/newsletters/new_issue.html")]
+struct NewIssuePage {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+    idempotency_key: Uuid,
+}
 
-    HttpResponse::Ok().body(body)
+pub async fn get_newsletter_page(
+    flash_messages: IncomingFlashMessages,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_page(&NewIssuePage {
+        flash_messages,
+        csrf_token: form_csrf_token(&session)?,
+        idempotency_key: Uuid::new_v4(),
+    })
 }
@@ -1,93 +1,209 @@
 use crate::{
-    domain::SubscriberEmail,
-    email_client::EmailClient,
-    utils::{e500, see_other},
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::UserId,
+    domain::{IssueStatus, NewsletterContent},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    utils::{e400, e500, see_other},
 };
 
 use {
     actix_web::{web, HttpRequest, HttpResponse},
     actix_web_flash_messages::FlashMessage,
     anyhow::{Context, Result},
-    sqlx::PgPool,
+    chrono::Utc,
+    sqlx::{PgPool, Postgres, Transaction},
+    uuid::Uuid,
 };
 
+type Trans<'c> = Transaction<'c, Postgres>;
+
 #[derive(Debug, serde::Deserialize)]
 pub struct BodyData {
     title: String,
-    html_content: String,
-    text_content: String,
-}
-
-#[derive(Debug)]
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+    markdown_content: Option<String>,
+    /// Sent instead of the HTML rendered from the Markdown.
+    html_content: Option<String>,
+    /// Sent instead of the plain text rendered from the Markdown.
+    text_content: Option<String>,
+    idempotency_key: String,
 }
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client),
-    fields(title = %body.title)
+    skip(body, pool, user_id, request),
+    fields(title = %body.title, user_id = %*user_id)
 )]
 pub async fn publish_newsletter(
     body: web::Form<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    user_id: web::ReqData<UserId>,
     request: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let subscribers = get_confirmed_subscribers(&pool)
+    let user_id = user_id.into_inner();
+    let BodyData {
+        title,
+        markdown_content,
+        html_content,
+        text_content,
+        idempotency_key,
+    } = body.0;
+    let idempotency_key = IdempotencyKey::parse(idempotency_key).map_err(e400)?;
+    let content = match NewsletterContent::parse(markdown_content, html_content, text_content) {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+
+    // Replay the saved response if this form has already been submitted
+    let mut transaction = match try_processing(&pool, &idempotency_key, &user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message().send();
+            return Ok(saved_response);
+        }
+    };
+
+    // Store the issue and queue up one delivery per confirmed subscriber. The emails themselves
+    // are sent by the background delivery worker.
+    let issue_id = insert_newsletter_issue(&title, &content, &user_id, &mut transaction)
+        .await
+        .context("Failed to store newsletter issue details")
+        .map_err(e500)?;
+    send_newsletter_issue(&issue_id, IssueStatus::Draft, &mut transaction)
+        .await
+        .context("Failed to enqueue delivery tasks")
+        .map_err(e500)?;
+    let event = AuditEvent::new(
+        AuditAction::NewsletterPublished,
+        serde_json::json!({ "newsletter_issue_id": issue_id, "title": &title }),
+    );
+    record_audit_event(&request, event, &mut transaction)
         .await
-        .context("Failed to get list of confirmed subscribers")
         .map_err(e500)?;
 
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => email_client
-                .send_email(
-                    &subscriber.email,
-                    &body.title,
-                    &body.html_content,
-                    &body.text_content,
-                )
-                .await
-                .with_context(|| {
-                    format!("Failed to send newsletter issue to {}", &subscriber.email)
-                })
-                .map_err(e500)?,
-            Err(error) => {
-                tracing::warn!(error.cause_chain = ?error, "Skipping a confirmed subscriber. Their stored contact details are invalid")
-            }
-        }
+    success_message().send();
+    let response = see_other("/admin/newsletters");
+    let response = save_response(transaction, &idempotency_key, &user_id, response)
+        .await
+        .map_err(e500)?;
+
+    Ok(response)
+}
+
+pub(crate) fn success_message() -> FlashMessage {
+    FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly.")
+}
+
+/// Store a new issue as a draft.
+#[tracing::instrument(name = "Store newsletter issue", skip(title, content, trans))]
+pub(crate) async fn insert_newsletter_issue(
+    title: &str,
+    content: &NewsletterContent,
+    author_id: &Uuid,
+    trans: &mut Trans<'_>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let now = Utc::now();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            markdown_content,
+            text_content,
+            html_content,
+            status,
+            author_id,
+            created_at,
+            updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, 'draft', $6, $7, $7)
+        "#,
+        newsletter_issue_id,
+        title,
+        content.markdown(),
+        content.text(),
+        content.html(),
+        author_id,
+        now,
+    )
+    .execute(trans)
+    .await?;
+
+    Ok(newsletter_issue_id)
+}
+
+/// Queue up a delivery of an issue to every confirmed subscriber, returning how many there are.
+///
+/// Returns `None` if the issue's status isn't `from`, e.g. because it has already been sent.
+#[tracing::instrument(name = "Send newsletter issue", skip(trans))]
+pub(crate) async fn send_newsletter_issue(
+    newsletter_issue_id: &Uuid,
+    from: IssueStatus,
+    trans: &mut Trans<'_>,
+) -> Result<Option<u64>, sqlx::Error> {
+    // The row lock keeps concurrent requests and schedulers from sending the same issue
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'sending', send_at = NULL, published_at = $2, updated_at = $2
+        WHERE newsletter_issue_id = $1 AND status = $3
+        "#,
+        newsletter_issue_id,
+        Utc::now(),
+        from.as_str(),
+    )
+    .execute(&mut *trans)
+    .await?
+    .rows_affected();
+    if n_updated == 0 {
+        return Ok(None);
     }
 
-    FlashMessage::info("Newsletter delivered successfully").send();
-    Ok(see_other("/admin/newsletters"))
+    let n_recipients = enqueue_delivery_tasks(newsletter_issue_id, trans).await?;
+    // With nobody to send it to, the issue is done already
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            n_recipients = $2,
+            status = CASE WHEN $2 = 0 THEN 'sent' ELSE status END
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        n_recipients as i32,
+    )
+    .execute(trans)
+    .await?;
+
+    Ok(Some(n_recipients))
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, sqlx::Error> {
-    let confirmed_subscribers: Vec<_> =
-        sqlx::query!(r#"SELECT email FROM subscriptions WHERE status = 'confirmed'"#)
-            .fetch_all(pool)
-            .await?
-            .into_iter()
-            .map(|r| {
-                SubscriberEmail::parse(r.email.clone())
-                    .map(|email| ConfirmedSubscriber { email })
-                    .ok_or_else(|| {
-                        anyhow::anyhow!(
-                            "A confirmed subscriber is using an invalid email address: {}",
-                            r.email
-                        )
-                    })
-            })
-            .collect();
-
-    tracing::debug!(
-        "Found {} confirmed subscribers",
-        confirmed_subscribers.len()
-    );
+#[tracing::instrument(name = "Enqueue delivery tasks", skip(trans))]
+async fn enqueue_delivery_tasks(
+    newsletter_issue_id: &Uuid,
+    trans: &mut Trans<'_>,
+) -> Result<u64, sqlx::Error> {
+    let n_enqueued = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(trans)
+    .await?
+    .rows_affected();
+
+    tracing::debug!("Enqueued {} delivery tasks", n_enqueued);
 
-    Ok(confirmed_subscribers)
+    Ok(n_enqueued)
 }
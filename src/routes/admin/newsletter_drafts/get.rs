@@ -0,0 +1,157 @@
+use crate::{
+    authentication::{form_csrf_token, Role},
+    domain::{render_html, render_text, IssueStatus},
+    session_state::TypedSession,
+    startup::SendTimeZone,
+    utils::{e500, render_page, see_other},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::{FlashMessage, IncomingFlashMessages},
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono_tz::Tz,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+struct StoredIssue {
+    title: String,
+    markdown_content: Option<String>,
+    text_content: String,
+    html_content: String,
+    status: IssueStatus,
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletters/edit_draft.html")]
+struct EditDraftPage<'a> {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+    issue_id: Uuid,
+    idempotency_key: Uuid,
+    title: &'a str,
+    markdown_content: &'a str,
+    html_override: &'a str,
+    text_override: &'a str,
+    time_zone: Tz,
+}
+
+#[tracing::instrument(
+    name = "Get draft page",
+    skip(flash_messages, db_pool, send_time_zone, session)
+)]
+pub async fn edit_draft_page(
+    newsletter_issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    db_pool: web::Data<PgPool>,
+    send_time_zone: web::Data<SendTimeZone>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = newsletter_issue_id.into_inner();
+    let issue = match get_issue(&issue_id, &db_pool).await.map_err(e500)? {
+        Some(issue) if issue.status == IssueStatus::Draft => issue,
+        Some(_) => {
+            FlashMessage::error("Only drafts can be edited.").send();
+            return Ok(see_other("/admin/newsletters/history"));
+        }
+        None => {
+            FlashMessage::error("That issue does not exist.").send();
+            return Ok(see_other("/admin/newsletters/history"));
+        }
+    };
+
+    // Only versions that differ from what the Markdown renders to were written by hand
+    let markdown = issue.markdown_content.as_deref();
+    let html_override = if markdown.map(render_html).as_ref() == Some(&issue.html_content) {
+        ""
+    } else {
+        &issue.html_content
+    };
+    let text_override = if markdown.map(render_text).as_ref() == Some(&issue.text_content) {
+        ""
+    } else {
+        &issue.text_content
+    };
+
+    render_page(&EditDraftPage {
+        flash_messages,
+        csrf_token: form_csrf_token(&session)?,
+        issue_id,
+        idempotency_key: Uuid::new_v4(),
+        title: &issue.title,
+        markdown_content: markdown.unwrap_or_default(),
+        html_override,
+        text_override,
+        time_zone: send_time_zone.0,
+    })
+}
+
+/// Show an issue the way subscribers will see it.
+#[tracing::instrument(name = "Preview newsletter issue", skip(db_pool))]
+pub async fn preview_newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = newsletter_issue_id.into_inner();
+    let issue = match get_issue(&issue_id, &db_pool).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => {
+            FlashMessage::error("That issue does not exist.").send();
+            return Ok(see_other("/admin/newsletters/history"));
+        }
+    };
+
+    // Only editors can open the draft to go back to
+    let back_link = if issue.status == IssueStatus::Draft && *role >= Role::Editor {
+        format!("/admin/newsletters/drafts/{}", issue_id)
+    } else {
+        "/admin/newsletters/history".to_string()
+    };
+    render_page(&PreviewPage {
+        title: &issue.title,
+        html_content: &issue.html_content,
+        text_content: &issue.text_content,
+        back_link: Some(&back_link),
+    })
+}
+
+/// The HTML is the author's own, so it is shown in a sandboxed frame where it can't run scripts
+/// against the admin pages.
+#[derive(Template)]
+#[template(path = "admin/newsletters/preview.html")]
+pub(super) struct PreviewPage<'a> {
+    pub(super) title: &'a str,
+    pub(super) html_content: &'a str,
+    pub(super) text_content: &'a str,
+    pub(super) back_link: Option<&'a str>,
+}
+
+#[tracing::instrument(name = "Get newsletter issue", skip(db_pool))]
+async fn get_issue(issue_id: &Uuid, db_pool: &PgPool) -> Result<Option<StoredIssue>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT title, markdown_content, text_content, html_content, status
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to retrieve newsletter issue")?;
+
+    row.map(|row| {
+        Ok(StoredIssue {
+            title: row.title,
+            markdown_content: row.markdown_content,
+            text_content: row.text_content,
+            html_content: row.html_content,
+            status: IssueStatus::parse(&row.status)
+                .context("Invalid issue status stored in the database")?,
+        })
+    })
+    .transpose()
+}
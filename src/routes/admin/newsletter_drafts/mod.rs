@@ -0,0 +1,7 @@
+mod get;
+mod post;
+
+pub use {
+    get::{edit_draft_page, preview_newsletter_issue},
+    post::{create_draft, preview_newsletter_content, send_draft, update_draft},
+};
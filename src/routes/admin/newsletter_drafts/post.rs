@@ -0,0 +1,232 @@
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::UserId,
+    domain::{IssueStatus, NewsletterContent, NewsletterContentValidationError},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    routes::{insert_newsletter_issue, send_newsletter_issue, success_message},
+    utils::{e400, e500, render_page, see_other},
+};
+
+use super::get::PreviewPage;
+
+use {
+    actix_web::{web, HttpRequest, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::{Context, Result},
+    chrono::Utc,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct DraftData {
+    title: String,
+    markdown_content: Option<String>,
+    /// Sent instead of the HTML rendered from the Markdown.
+    html_content: Option<String>,
+    /// Sent instead of the plain text rendered from the Markdown.
+    text_content: Option<String>,
+}
+
+impl DraftData {
+    fn content(&self) -> Result<NewsletterContent, NewsletterContentValidationError> {
+        NewsletterContent::parse(
+            self.markdown_content.clone(),
+            self.html_content.clone(),
+            self.text_content.clone(),
+        )
+    }
+}
+
+#[tracing::instrument(
+    name = "Save a new draft",
+    skip(form, db_pool, user_id),
+    fields(title = %form.title, user_id = %*user_id)
+)]
+pub async fn create_draft(
+    form: web::Form<DraftData>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let content = match form.content() {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")
+        .map_err(e500)?;
+    let issue_id = insert_newsletter_issue(
+        &form.title,
+        &content,
+        &user_id.into_inner(),
+        &mut transaction,
+    )
+    .await
+    .context("Failed to store the draft")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to store a draft")
+        .map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!(
+        "/admin/newsletters/drafts/{}",
+        issue_id
+    )))
+}
+
+#[tracing::instrument(name = "Update a draft", skip(form, db_pool))]
+pub async fn update_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<DraftData>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = newsletter_issue_id.into_inner();
+    let content = match form.content() {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other(&format!(
+                "/admin/newsletters/drafts/{}",
+                issue_id
+            )));
+        }
+    };
+    if !update_draft_content(&issue_id, &form.title, &content, &db_pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("Only drafts can be edited.").send();
+        return Ok(see_other("/admin/newsletters/history"));
+    }
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!(
+        "/admin/newsletters/drafts/{}",
+        issue_id
+    )))
+}
+
+/// Show what the content of the issue form would look like, without saving it.
+#[tracing::instrument(name = "Preview newsletter content", skip(form))]
+pub async fn preview_newsletter_content(
+    form: web::Form<DraftData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let content = form.content().map_err(e400)?;
+    render_page(&PreviewPage {
+        title: &form.title,
+        html_content: content.html(),
+        text_content: content.text(),
+        back_link: None,
+    })
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SendFormData {
+    idempotency_key: String,
+}
+
+#[tracing::instrument(
+    name = "Send a draft",
+    skip(form, db_pool, user_id, request),
+    fields(user_id = %*user_id)
+)]
+pub async fn send_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<SendFormData>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = newsletter_issue_id.into_inner();
+    let user_id = user_id.into_inner();
+    let idempotency_key = IdempotencyKey::parse(form.0.idempotency_key).map_err(e400)?;
+
+    let mut transaction = match try_processing(&db_pool, &idempotency_key, &user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message().send();
+            return Ok(saved_response);
+        }
+    };
+
+    // Nothing is saved for the idempotency key, a retry gets the same answer anyway
+    if send_newsletter_issue(&issue_id, IssueStatus::Draft, &mut transaction)
+        .await
+        .context("Failed to enqueue delivery tasks")
+        .map_err(e500)?
+        .is_none()
+    {
+        FlashMessage::error("Only drafts can be sent.").send();
+        return Ok(see_other("/admin/newsletters/history"));
+    }
+    let title = sqlx::query!(
+        r#"SELECT title FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to retrieve the issue's title")
+    .map_err(e500)?
+    .title;
+    let event = AuditEvent::new(
+        AuditAction::NewsletterPublished,
+        serde_json::json!({ "newsletter_issue_id": issue_id, "title": title }),
+    );
+    record_audit_event(&request, event, &mut transaction)
+        .await
+        .map_err(e500)?;
+
+    success_message().send();
+    let response = see_other("/admin/newsletters/history");
+    let response = save_response(transaction, &idempotency_key, &user_id, response)
+        .await
+        .map_err(e500)?;
+
+    Ok(response)
+}
+
+/// Returns `false` if the issue is not a draft.
+#[tracing::instrument(name = "Update draft content", skip(title, content, db_pool))]
+async fn update_draft_content(
+    issue_id: &Uuid,
+    title: &str,
+    content: &NewsletterContent,
+    db_pool: &PgPool,
+) -> Result<bool> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            markdown_content = $3,
+            text_content = $4,
+            html_content = $5,
+            updated_at = $6
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        issue_id,
+        title,
+        content.markdown(),
+        content.text(),
+        content.html(),
+        Utc::now(),
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to update the draft")?
+    .rows_affected();
+
+    Ok(n_updated > 0)
+}
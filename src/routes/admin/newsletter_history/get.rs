@@ -0,0 +1,82 @@
+use crate::{
+    authentication::Role,
+    domain::IssueStatus,
+    utils::{e500, render_page},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::{DateTime, Utc},
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    status: IssueStatus,
+    author: Option<String>,
+    published_at: Option<DateTime<Utc>>,
+    n_recipients: Option<i32>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletters/history.html")]
+struct NewsletterHistoryPage {
+    flash_messages: IncomingFlashMessages,
+    issues: Vec<IssueSummary>,
+    /// Viewers can look, but not write or edit issues.
+    can_edit: bool,
+}
+
+#[tracing::instrument(name = "Get newsletter history page", skip(flash_messages, db_pool))]
+pub async fn newsletter_history_page(
+    flash_messages: IncomingFlashMessages,
+    db_pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_page(&NewsletterHistoryPage {
+        flash_messages,
+        issues: get_issues(&db_pool).await.map_err(e500)?,
+        can_edit: *role >= Role::Editor,
+    })
+}
+
+/// Every issue, drafts included, most recently sent or edited first.
+#[tracing::instrument(name = "Get newsletter issues", skip(db_pool))]
+async fn get_issues(db_pool: &PgPool) -> Result<Vec<IssueSummary>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            i.status,
+            u.username AS "author?",
+            i.published_at,
+            i.n_recipients
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        ORDER BY COALESCE(i.published_at, i.updated_at) DESC
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve newsletter issues")?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(IssueSummary {
+                newsletter_issue_id: row.newsletter_issue_id,
+                title: row.title,
+                status: IssueStatus::parse(&row.status)
+                    .context("Invalid issue status stored in the database")?,
+                author: row.author,
+                published_at: row.published_at,
+                n_recipients: row.n_recipients,
+            })
+        })
+        .collect()
+}
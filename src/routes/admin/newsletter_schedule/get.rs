@@ -0,0 +1,89 @@
+use crate::{
+    authentication::{form_csrf_token, Role},
+    domain::SendTime,
+    session_state::TypedSession,
+    startup::SendTimeZone,
+    utils::{e500, render_page},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::{DateTime, Utc},
+    chrono_tz::Tz,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    author: Option<String>,
+    send_at: DateTime<Utc>,
+}
+
+impl ScheduledIssue {
+    fn local_send_at(&self, zone: &Tz) -> DateTime<Tz> {
+        self.send_at.with_timezone(zone)
+    }
+
+    fn send_at_form_value(&self, zone: &Tz) -> String {
+        SendTime::form_value(&self.send_at, *zone)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletters/scheduled.html")]
+struct ScheduledIssuesPage {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+    issues: Vec<ScheduledIssue>,
+    time_zone: Tz,
+    /// Viewers can look, but not reschedule or cancel issues.
+    can_edit: bool,
+}
+
+#[tracing::instrument(
+    name = "Get scheduled issues page",
+    skip(flash_messages, db_pool, send_time_zone, session)
+)]
+pub async fn scheduled_issues_page(
+    flash_messages: IncomingFlashMessages,
+    db_pool: web::Data<PgPool>,
+    send_time_zone: web::Data<SendTimeZone>,
+    session: TypedSession,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_page(&ScheduledIssuesPage {
+        flash_messages,
+        csrf_token: form_csrf_token(&session)?,
+        issues: get_scheduled_issues(&db_pool).await.map_err(e500)?,
+        time_zone: send_time_zone.0,
+        can_edit: *role >= Role::Editor,
+    })
+}
+
+#[tracing::instrument(name = "Get scheduled issues", skip(db_pool))]
+async fn get_scheduled_issues(db_pool: &PgPool) -> Result<Vec<ScheduledIssue>> {
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            u.username AS "author?",
+            i.send_at AS "send_at!"
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        WHERE i.status = 'scheduled'
+        ORDER BY i.send_at
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve scheduled issues")?;
+
+    Ok(issues)
+}
@@ -0,0 +1,7 @@
+mod get;
+mod post;
+
+pub use {
+    get::scheduled_issues_page,
+    post::{cancel_scheduled_issue, reschedule_issue, schedule_draft},
+};
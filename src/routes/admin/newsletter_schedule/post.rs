@@ -0,0 +1,203 @@
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    domain::SendTime,
+    startup::SendTimeZone,
+    utils::{e500, see_other},
+};
+
+use {
+    actix_web::{web, HttpRequest, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::{Context, Result},
+    chrono::Utc,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ScheduleFormData {
+    send_at: String,
+}
+
+#[tracing::instrument(
+    name = "Schedule a draft",
+    skip(form, db_pool, send_time_zone, request)
+)]
+pub async fn schedule_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<ScheduleFormData>,
+    db_pool: web::Data<PgPool>,
+    send_time_zone: web::Data<SendTimeZone>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = newsletter_issue_id.into_inner();
+    let send_at = match SendTime::parse(&form.send_at, send_time_zone.0, Utc::now()) {
+        Ok(send_at) => send_at,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other(&format!(
+                "/admin/newsletters/drafts/{}",
+                issue_id
+            )));
+        }
+    };
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")
+        .map_err(e500)?;
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'scheduled', send_at = $2, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        issue_id,
+        send_at.as_ref(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to schedule the draft")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        FlashMessage::error("Only drafts can be scheduled.").send();
+        return Ok(see_other("/admin/newsletters/history"));
+    }
+    let event = AuditEvent::new(
+        AuditAction::NewsletterScheduled,
+        serde_json::json!({ "newsletter_issue_id": issue_id, "send_at": send_at.as_ref() }),
+    );
+    record_audit_event(&request, event, &mut transaction)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to schedule a draft")
+        .map_err(e500)?;
+
+    FlashMessage::info("The issue has been scheduled.").send();
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[tracing::instrument(
+    name = "Reschedule an issue",
+    skip(form, db_pool, send_time_zone, request)
+)]
+pub async fn reschedule_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<ScheduleFormData>,
+    db_pool: web::Data<PgPool>,
+    send_time_zone: web::Data<SendTimeZone>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = newsletter_issue_id.into_inner();
+    let send_at = match SendTime::parse(&form.send_at, send_time_zone.0, Utc::now()) {
+        Ok(send_at) => send_at,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/admin/newsletters/scheduled"));
+        }
+    };
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")
+        .map_err(e500)?;
+    // Once the scheduler has picked the issue up, it is no longer scheduled
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET send_at = $2, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        issue_id,
+        send_at.as_ref(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to reschedule the issue")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        FlashMessage::error("That issue is no longer scheduled.").send();
+        return Ok(see_other("/admin/newsletters/scheduled"));
+    }
+    let event = AuditEvent::new(
+        AuditAction::NewsletterScheduled,
+        serde_json::json!({ "newsletter_issue_id": issue_id, "send_at": send_at.as_ref() }),
+    );
+    record_audit_event(&request, event, &mut transaction)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to reschedule an issue")
+        .map_err(e500)?;
+
+    FlashMessage::info("The issue has been rescheduled.").send();
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+/// Turn a scheduled issue back into a draft, unless delivery has already started.
+#[tracing::instrument(name = "Cancel a scheduled issue", skip(db_pool, request))]
+pub async fn cancel_scheduled_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = newsletter_issue_id.into_inner();
+    if !unschedule_issue(&issue_id, &request, &db_pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("That issue is no longer scheduled.").send();
+        return Ok(see_other("/admin/newsletters/scheduled"));
+    }
+
+    FlashMessage::info("The scheduled send has been cancelled, the issue is a draft again.").send();
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[tracing::instrument(name = "Unschedule an issue", skip(request, db_pool))]
+async fn unschedule_issue(
+    issue_id: &Uuid,
+    request: &HttpRequest,
+    db_pool: &PgPool,
+) -> Result<bool> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")?;
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'draft', send_at = NULL, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        issue_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to unschedule the issue")?
+    .rows_affected();
+    if n_updated == 0 {
+        return Ok(false);
+    }
+
+    let event = AuditEvent::new(
+        AuditAction::NewsletterScheduleCancelled,
+        serde_json::json!({ "newsletter_issue_id": issue_id }),
+    );
+    record_audit_event(request, event, &mut transaction).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to unschedule an issue")?;
+
+    Ok(true)
+}
@@ -1,66 +1,20 @@
-use std::fmt::Write;
+use crate::{authentication::form_csrf_token, session_state::TypedSession, utils::render_page};
 
-use {
-    actix_web::{http::header, HttpResponse},
-    actix_web_flash_messages::IncomingFlashMessages,
-};
+use {actix_web::HttpResponse, actix_web_flash_messages::IncomingFlashMessages, askama::Template};
+
+#[derive(Template)]
+#[template(path = "admin/change_password.html")]
+struct ChangePasswordPage {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+}
 
 pub async fn change_password_form(
     flash_messages: IncomingFlashMessages,
+    session: TypedSession,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
-    }
-
-    let body = format!(
-        r#"
-<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Change Password</title>
-</head>
-<body>
-    {msg_html}
-    <form action="/admin/password" method="post">
-        <label>
-            Current password
-            <input
-                type="password"
-                placeholder="Enter current password"
-                name="current_password"
-            >
-        </label>
-        <br>
-        <label>
-            New password
-            <input
-                type="password"
-                placeholder="Enter new password"
-                name="new_password"
-            >
-        </label>
-        <br>
-        <label>
-            Confirm new password
-            <input
-                type="password"
-                placeholder="Type the new password again"
-                name="new_password_check"
-            >
-        </label>
-        <br>
-        <button type="submit">Change password</button>
-    </form>
-    <p><a href="/admin/dashboard">&lt;- Back</a></p>
-</body>
-</html>
-        "#
-    );
-
-    let response = HttpResponse::Ok()
-        .content_type(header::ContentType::html())
-        .body(body);
-    Ok(response)
+    render_page(&ChangePasswordPage {
+        flash_messages,
+        csrf_token: form_csrf_token(&session)?,
+    })
 }
@@ -1,4 +1,4 @@
 mod get;
 mod post;
 
-pub use {get::*, post::*};
+pub use {get::change_password_form, post::change_password};
@@ -1,11 +1,17 @@
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials, UserId},
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{
+        check_login_throttle, lockout_minutes, record_login_failure, validate_credentials,
+        AuthError, Credentials, PasswordHashingParams, ThrottleDecision, UserId,
+    },
+    configuration::LoginThrottleSettings,
+    domain::PasswordPolicy,
     routes::admin::dashboard::get_username,
-    utils::{e500, see_other},
+    utils::{client_ip, e500, see_other},
 };
 
 use {
-    actix_web::{web, HttpResponse},
+    actix_web::{web, HttpRequest, HttpResponse},
     actix_web_flash_messages::FlashMessage,
     secrecy::{ExposeSecret, Secret},
     sqlx::PgPool,
@@ -21,7 +27,11 @@ pub struct FormData {
 pub async fn change_password(
     form: web::Form<FormData>,
     db_pool: web::Data<PgPool>,
+    hashing_params: web::Data<PasswordHashingParams>,
+    password_policy: web::Data<PasswordPolicy>,
+    throttle: web::Data<LoginThrottleSettings>,
     user_id: web::ReqData<UserId>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
 
@@ -33,23 +43,41 @@ pub async fn change_password(
         return Ok(see_other("/admin/password"));
     }
 
-    if form.new_password.expose_secret().len() < 12 {
-        FlashMessage::error("The new password must be at least 12 characters").send();
+    let username = get_username(&user_id, &db_pool).await.map_err(e500)?;
+    if let Err(violation) = password_policy.check(&form.new_password, &username) {
+        FlashMessage::error(violation.to_string()).send();
         return Ok(see_other("/admin/password"));
     }
-    if form.new_password.expose_secret().len() > 128 {
-        FlashMessage::error("The new password must be no more than 128 chars").send();
-        return Ok(see_other("/admin/password"));
+
+    // Guessing the current password is throttled like logging in, a stolen session is no
+    // shortcut around the lockout
+    let client_ip = client_ip(&request);
+    match check_login_throttle(&username, &client_ip, &throttle, &db_pool)
+        .await
+        .map_err(e500)?
+    {
+        ThrottleDecision::Allowed { delay } => tokio::time::sleep(delay).await,
+        ThrottleDecision::LockedOut { until } => {
+            tracing::info!(%until, "Rejected a password change during a lockout");
+            FlashMessage::error(format!(
+                "Too many failed login attempts. Please try again in {} minutes.",
+                lockout_minutes(until)
+            ))
+            .send();
+            return Ok(see_other("/admin/password"));
+        }
     }
 
-    let username = get_username(&user_id, &db_pool).await.map_err(e500)?;
     let creds = Credentials {
-        username,
+        username: username.clone(),
         password: form.0.current_password,
     };
-    if let Err(e) = validate_credentials(creds, &db_pool).await {
+    if let Err(e) = validate_credentials(creds, &hashing_params, &db_pool).await {
         return match e {
             AuthError::InvalidCredentials(_) => {
+                record_login_failure(&username, &client_ip, &throttle, &db_pool)
+                    .await
+                    .map_err(e500)?;
                 FlashMessage::error("The current password is incorrect.").send();
                 Ok(see_other("/admin/password"))
             }
@@ -57,7 +85,19 @@ pub async fn change_password(
         };
     }
 
-    crate::authentication::change_password(&user_id, form.0.new_password, &db_pool)
+    crate::authentication::change_password(
+        &user_id,
+        form.0.new_password,
+        &hashing_params,
+        db_pool.get_ref(),
+    )
+    .await
+    .map_err(e500)?;
+    let event = AuditEvent::new(
+        AuditAction::PasswordChanged,
+        serde_json::json!({ "method": "change" }),
+    );
+    record_audit_event(&request, event, db_pool.get_ref())
         .await
         .map_err(e500)?;
 
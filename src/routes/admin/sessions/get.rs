@@ -0,0 +1,36 @@
+use crate::{
+    authentication::{form_csrf_token, SessionRecord, SessionRegistry, UserId},
+    session_state::TypedSession,
+    utils::{e500, render_page},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    askama::Template,
+    uuid::Uuid,
+};
+
+#[derive(Template)]
+#[template(path = "admin/sessions.html")]
+struct SessionsPage {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+    current_session_id: Option<Uuid>,
+    sessions: Vec<SessionRecord>,
+}
+
+#[tracing::instrument(name = "Get sessions page", skip(flash_messages, registry, session))]
+pub async fn sessions_page(
+    flash_messages: IncomingFlashMessages,
+    registry: web::Data<SessionRegistry>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_page(&SessionsPage {
+        flash_messages,
+        csrf_token: form_csrf_token(&session)?,
+        current_session_id: session.get_session_id().map_err(e500)?,
+        sessions: registry.list(&user_id.into_inner()).await.map_err(e500)?,
+    })
+}
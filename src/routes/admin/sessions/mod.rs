@@ -0,0 +1,7 @@
+mod get;
+mod post;
+
+pub use {
+    get::sessions_page,
+    post::{revoke_session, sign_out_everywhere},
+};
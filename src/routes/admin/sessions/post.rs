@@ -0,0 +1,76 @@
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{invalidate_sessions, SessionRegistry, UserId},
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+use {
+    actix_web::{web, HttpRequest, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+#[tracing::instrument(name = "Revoke a session", skip(registry, db_pool, session, request))]
+pub async fn revoke_session(
+    session_id: web::Path<Uuid>,
+    registry: web::Data<SessionRegistry>,
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let is_current = session.get_session_id().map_err(e500)? == Some(*session_id);
+
+    if !registry.revoke(&user_id, &session_id).await.map_err(e500)? {
+        FlashMessage::error("That session has already ended.").send();
+        return Ok(see_other("/admin/sessions"));
+    }
+    tracing::info!("Session revoked");
+    let event = AuditEvent::new(
+        AuditAction::Logout,
+        serde_json::json!({ "revoked_session_id": *session_id }),
+    );
+    record_audit_event(&request, event, db_pool.get_ref())
+        .await
+        .map_err(e500)?;
+
+    if is_current {
+        session.log_out();
+        FlashMessage::info("You have successfully logged out.").send();
+        return Ok(see_other("/login"));
+    }
+    FlashMessage::info("The session has been signed out.").send();
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(
+    name = "Sign out everywhere",
+    skip(db_pool, registry, session, request)
+)]
+pub async fn sign_out_everywhere(
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    registry: web::Data<SessionRegistry>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Sessions that were never registered are caught by the database cutoff
+    invalidate_sessions(&user_id.into_inner(), &db_pool, &registry)
+        .await
+        .map_err(e500)?;
+    let event = AuditEvent::new(
+        AuditAction::Logout,
+        serde_json::json!({ "all_sessions": true }),
+    );
+    record_audit_event(&request, event, db_pool.get_ref())
+        .await
+        .map_err(e500)?;
+    session.log_out();
+    tracing::info!("Signed out of every session");
+
+    FlashMessage::info("You have been signed out of every session.").send();
+    Ok(see_other("/login"))
+}
@@ -0,0 +1,82 @@
+use crate::{
+    authentication::{
+        count_unused_recovery_codes, form_csrf_token, get_totp_secret, TotpSecret, UserId,
+    },
+    routes::admin::dashboard::get_username,
+    session_state::TypedSession,
+    utils::{e500, render_page},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    askama::Template,
+    secrecy::{ExposeSecret, Secret},
+    sqlx::PgPool,
+};
+
+enum TwoFactorState {
+    Enabled {
+        n_unused_codes: i64,
+    },
+    Enrolling {
+        otpauth_uri: Secret<String>,
+        secret: Secret<String>,
+    },
+}
+
+#[derive(Template)]
+#[template(path = "admin/two_factor/settings.html")]
+struct TwoFactorPage {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+    state: TwoFactorState,
+}
+
+#[tracing::instrument(
+    name = "Get two-factor authentication page",
+    skip(db_pool, session, flash_messages)
+)]
+pub async fn two_factor_page(
+    db_pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let csrf_token = form_csrf_token(&session)?;
+
+    let state = if get_totp_secret(&user_id, &db_pool)
+        .await
+        .map_err(e500)?
+        .is_some()
+    {
+        let n_unused_codes = count_unused_recovery_codes(&user_id, &db_pool)
+            .await
+            .map_err(e500)?;
+        TwoFactorState::Enabled { n_unused_codes }
+    } else {
+        // Keep showing the same secret until it is confirmed, in case the page is reloaded
+        let secret = match session.get_totp_enrolment_secret().map_err(e500)? {
+            Some(secret) => TotpSecret::from_base32(&secret).map_err(e500)?,
+            None => {
+                let secret = TotpSecret::generate();
+                session
+                    .insert_totp_enrolment_secret(secret.to_base32().expose_secret())
+                    .map_err(e500)?;
+                secret
+            }
+        };
+        let username = get_username(&user_id, &db_pool).await.map_err(e500)?;
+        TwoFactorState::Enrolling {
+            otpauth_uri: secret.otpauth_uri(&username),
+            secret: secret.to_base32(),
+        }
+    };
+
+    render_page(&TwoFactorPage {
+        flash_messages,
+        csrf_token,
+        state,
+    })
+}
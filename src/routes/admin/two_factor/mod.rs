@@ -0,0 +1,4 @@
+mod get;
+mod post;
+
+pub use {get::two_factor_page, post::enable_two_factor};
@@ -0,0 +1,77 @@
+use crate::{
+    authentication::{get_totp_secret, PasswordHashingParams, TotpSecret, UserId},
+    session_state::TypedSession,
+    utils::{e500, render_page, see_other},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    askama::Template,
+    chrono::Utc,
+    secrecy::{ExposeSecret, Secret},
+    sqlx::PgPool,
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: Secret<String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/two_factor/recovery_codes.html")]
+struct RecoveryCodesPage {
+    recovery_codes: Vec<Secret<String>>,
+}
+
+#[tracing::instrument(
+    name = "Enable two-factor authentication",
+    skip(form, db_pool, hashing_params, session)
+)]
+pub async fn enable_two_factor(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+    hashing_params: web::Data<PasswordHashingParams>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    if get_totp_secret(&user_id, &db_pool)
+        .await
+        .map_err(e500)?
+        .is_some()
+    {
+        FlashMessage::error("Two-factor authentication is already enabled.").send();
+        return Ok(see_other("/admin/two-factor"));
+    }
+    let secret = match session.get_totp_enrolment_secret().map_err(e500)? {
+        Some(secret) => TotpSecret::from_base32(&secret).map_err(e500)?,
+        None => return Ok(see_other("/admin/two-factor")),
+    };
+    let confirmed_step =
+        match secret.verify(form.code.expose_secret().trim(), Utc::now().timestamp()) {
+            Some(step) => step,
+            None => {
+                FlashMessage::error(
+                "That code is not valid. Check that your device's clock is correct and try again.",
+            )
+            .send();
+                return Ok(see_other("/admin/two-factor"));
+            }
+        };
+
+    let recovery_codes = crate::authentication::enable_two_factor(
+        &user_id,
+        &secret,
+        confirmed_step,
+        &hashing_params,
+        &db_pool,
+    )
+    .await
+    .map_err(e500)?;
+    session.remove_totp_enrolment_secret();
+
+    // Shown once rather than through a redirect, the codes are not stored anywhere in clear
+    render_page(&RecoveryCodesPage { recovery_codes })
+}
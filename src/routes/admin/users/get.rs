@@ -0,0 +1,71 @@
+use crate::{
+    authentication::{form_csrf_token, Role, UserId},
+    session_state::TypedSession,
+    utils::{e500, render_page},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::{Context, Result},
+    askama::Template,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+struct Collaborator {
+    user_id: Uuid,
+    username: String,
+    role: String,
+    is_active: bool,
+}
+
+impl Collaborator {
+    fn role(&self) -> Option<Role> {
+        Role::parse(&self.role).ok()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/users.html")]
+struct CollaboratorsPage {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+    current_user_id: Uuid,
+    collaborators: Vec<Collaborator>,
+}
+
+#[tracing::instrument(
+    name = "Get collaborators page",
+    skip(flash_messages, db_pool, session)
+)]
+pub async fn users_page(
+    flash_messages: IncomingFlashMessages,
+    db_pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_page(&CollaboratorsPage {
+        flash_messages,
+        csrf_token: form_csrf_token(&session)?,
+        current_user_id: *user_id.into_inner(),
+        collaborators: get_collaborators(&db_pool).await.map_err(e500)?,
+    })
+}
+
+#[tracing::instrument(name = "Get collaborators", skip(db_pool))]
+async fn get_collaborators(db_pool: &PgPool) -> Result<Vec<Collaborator>> {
+    let collaborators = sqlx::query_as!(
+        Collaborator,
+        r#"
+        SELECT user_id, username, role, is_active
+        FROM users
+        ORDER BY is_active DESC, username
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to retrieve collaborators")?;
+
+    Ok(collaborators)
+}
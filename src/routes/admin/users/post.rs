@@ -0,0 +1,334 @@
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{InvitationToken, Role, UserId},
+    domain::SubscriberEmail,
+    email_client::EmailSender,
+    startup::{ApplicationBaseUrl, HmacSecret, InvitationExpiry},
+    utils::{e500, see_other},
+};
+
+use {
+    actix_web::{web, HttpRequest, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::Utc,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+#[derive(serde::Deserialize)]
+pub struct InviteFormData {
+    email: String,
+    role: Role,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Invite a collaborator",
+    skip(form, db_pool, email_client, base_url, hmac_secret, invitation_expiry, request),
+    fields(invitee_email = %form.email, role = %form.role)
+)]
+pub async fn invite_user(
+    form: web::Form<InviteFormData>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    invitation_expiry: web::Data<InvitationExpiry>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let InviteFormData { email, role } = form.0;
+    let email = match SubscriberEmail::parse(email.trim().to_owned()) {
+        Some(email) => email,
+        None => {
+            FlashMessage::error("Please enter a valid email address.").send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+
+    if email_has_account(&email, &db_pool).await.map_err(e500)? {
+        FlashMessage::error(format!("{} already has an account.", email.as_ref())).send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let invitation_id = insert_invitation(
+        &email,
+        role,
+        &user_id.into_inner(),
+        invitation_expiry.0,
+        &db_pool,
+    )
+    .await
+    .map_err(e500)?;
+    let event = AuditEvent::new(
+        AuditAction::CollaboratorChanged,
+        serde_json::json!({
+            "invitation_id": invitation_id,
+            "invited_email": email.as_ref(),
+            "role": role.as_str(),
+        }),
+    );
+    record_audit_event(&request, event, db_pool.get_ref())
+        .await
+        .map_err(e500)?;
+
+    let token = InvitationToken::new(invitation_id).sign(&hmac_secret);
+    send_invitation_email(&email, role, email_client.get_ref(), &base_url.0, &token)
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "An invitation has been sent to {}.",
+        email.as_ref()
+    ))
+    .send();
+    Ok(see_other("/admin/users"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleFormData {
+    role: Role,
+}
+
+#[tracing::instrument(
+    name = "Change a collaborator's role",
+    skip(form, db_pool, request),
+    fields(role = %form.role)
+)]
+pub async fn change_user_role(
+    user_id: web::Path<Uuid>,
+    form: web::Form<RoleFormData>,
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let outcome = update_user(&user_id, UserUpdate::Role(form.role), &request, &db_pool)
+        .await
+        .map_err(e500)?;
+    outcome.send_flash_message("The role has been changed.");
+
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Deactivate a collaborator", skip(db_pool, request))]
+pub async fn deactivate_user(
+    user_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let outcome = update_user(&user_id, UserUpdate::Active(false), &request, &db_pool)
+        .await
+        .map_err(e500)?;
+    outcome.send_flash_message("The collaborator has been deactivated.");
+
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Reactivate a collaborator", skip(db_pool, request))]
+pub async fn reactivate_user(
+    user_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let outcome = update_user(&user_id, UserUpdate::Active(true), &request, &db_pool)
+        .await
+        .map_err(e500)?;
+    outcome.send_flash_message("The collaborator has been reactivated.");
+
+    Ok(see_other("/admin/users"))
+}
+
+enum UpdateOutcome {
+    Updated,
+    UnknownUser,
+    NoOwnerLeft,
+}
+
+impl UpdateOutcome {
+    fn send_flash_message(&self, success_message: &str) {
+        match self {
+            UpdateOutcome::Updated => FlashMessage::info(success_message).send(),
+            UpdateOutcome::UnknownUser => {
+                FlashMessage::error("That collaborator does not exist.").send()
+            }
+            UpdateOutcome::NoOwnerLeft => {
+                FlashMessage::error("There must always be at least one active owner.").send()
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum UserUpdate {
+    Role(Role),
+    Active(bool),
+}
+
+/// Apply `update` to a collaborator, rolling it back if it would leave no active owner.
+#[tracing::instrument(name = "Update a collaborator", skip(request, db_pool))]
+async fn update_user(
+    user_id: &Uuid,
+    update: UserUpdate,
+    request: &HttpRequest,
+    db_pool: &PgPool,
+) -> Result<UpdateOutcome> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")?;
+
+    // Lock the owners so that two of them can't demote each other at the same time
+    sqlx::query!(r#"SELECT user_id FROM users WHERE role = 'owner' AND is_active FOR UPDATE"#)
+        .fetch_all(&mut transaction)
+        .await
+        .context("Failed to lock owners")?;
+
+    let n_updated = match &update {
+        UserUpdate::Role(role) => sqlx::query!(
+            r#"UPDATE users SET role = $1 WHERE user_id = $2"#,
+            role.as_str(),
+            user_id,
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to change the collaborator's role")?,
+        UserUpdate::Active(is_active) => sqlx::query!(
+            r#"UPDATE users SET is_active = $1 WHERE user_id = $2"#,
+            is_active,
+            user_id,
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to change whether the collaborator is active")?,
+    }
+    .rows_affected();
+    if n_updated == 0 {
+        return Ok(UpdateOutcome::UnknownUser);
+    }
+
+    let n_owners = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM users WHERE role = 'owner' AND is_active"#
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to count active owners")?
+    .count;
+    if n_owners == 0 {
+        // Dropping the transaction rolls the update back
+        return Ok(UpdateOutcome::NoOwnerLeft);
+    }
+
+    let details = match update {
+        UserUpdate::Role(role) => serde_json::json!({ "user_id": user_id, "role": role.as_str() }),
+        UserUpdate::Active(is_active) => {
+            serde_json::json!({ "user_id": user_id, "is_active": is_active })
+        }
+    };
+    let event = AuditEvent::new(AuditAction::CollaboratorChanged, details);
+    record_audit_event(request, event, &mut transaction).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to update collaborator")?;
+
+    Ok(UpdateOutcome::Updated)
+}
+
+#[tracing::instrument(name = "Check for an existing account", skip(db_pool))]
+async fn email_has_account(email: &SubscriberEmail, db_pool: &PgPool) -> Result<bool> {
+    let row = sqlx::query!(
+        r#"SELECT user_id FROM users WHERE email = $1"#,
+        email.as_ref()
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to look up users by email")?;
+
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(name = "Store a collaborator invitation", skip(db_pool))]
+async fn insert_invitation(
+    email: &SubscriberEmail,
+    role: Role,
+    invited_by: &UserId,
+    expiry: chrono::Duration,
+    db_pool: &PgPool,
+) -> Result<Uuid> {
+    let invitation_id = Uuid::new_v4();
+    let now = Utc::now();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invitations (
+            invitation_id, email, role, invited_by, created_at, expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        invitation_id,
+        email.as_ref(),
+        role.as_str(),
+        **invited_by,
+        now,
+        now + expiry,
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to store collaborator invitation")?;
+
+    Ok(invitation_id)
+}
+
+#[derive(Template)]
+#[template(path = "emails/invitation.html")]
+struct InvitationEmailHtml<'a> {
+    role: Role,
+    invitation_link: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/invitation.txt")]
+struct InvitationEmailText<'a> {
+    role: Role,
+    invitation_link: &'a str,
+}
+
+#[tracing::instrument(
+    name = "Send a collaborator invitation",
+    skip(email_client, base_url, token)
+)]
+async fn send_invitation_email(
+    recipient: &SubscriberEmail,
+    role: Role,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+    token: &str,
+) -> Result<()> {
+    let invitation_link = format!("{}/invitations/accept?token={}", base_url, token);
+    let html_body = InvitationEmailHtml {
+        role,
+        invitation_link: &invitation_link,
+    }
+    .render()
+    .context("Failed to render the HTML version of the invitation email")?;
+    let text_body = InvitationEmailText {
+        role,
+        invitation_link: &invitation_link,
+    }
+    .render()
+    .context("Failed to render the text version of the invitation email")?;
+
+    email_client
+        .send_email(
+            recipient,
+            "You have been invited to collaborate",
+            &html_body,
+            &text_body,
+        )
+        .await
+        .context("Failed to send invitation email")?;
+
+    Ok(())
+}
@@ -0,0 +1,3 @@
+mod newsletters;
+
+pub use newsletters::*;
@@ -0,0 +1,113 @@
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{ApiCaller, ApiError, ApiScope},
+    domain::{IssueStatus, NewsletterContent},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    routes::{insert_newsletter_issue, send_newsletter_issue},
+};
+
+use {
+    actix_web::{web, HttpRequest, HttpResponse},
+    anyhow::Context,
+    chrono::{DateTime, Utc},
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+#[derive(serde::Serialize)]
+struct PublishedIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "List newsletter issues through the API",
+    skip(pool, caller),
+    fields(api_token_id = %caller.api_token_id)
+)]
+pub async fn api_list_newsletters(
+    pool: web::Data<PgPool>,
+    caller: web::ReqData<ApiCaller>,
+) -> Result<HttpResponse, ApiError> {
+    caller.require(ApiScope::ReadNewsletters)?;
+
+    let issues = sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        SELECT newsletter_issue_id, title, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE published_at IS NOT NULL
+        ORDER BY published_at DESC
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve newsletter issues")?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "issues": issues })))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ApiNewsletterData {
+    title: String,
+    markdown_content: Option<String>,
+    /// Sent instead of the HTML rendered from the Markdown.
+    html_content: Option<String>,
+    /// Sent instead of the plain text rendered from the Markdown.
+    text_content: Option<String>,
+    idempotency_key: String,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue through the API",
+    skip(body, pool, caller, request),
+    fields(title = %body.title, api_token_id = %caller.api_token_id)
+)]
+pub async fn api_publish_newsletter(
+    body: web::Json<ApiNewsletterData>,
+    pool: web::Data<PgPool>,
+    caller: web::ReqData<ApiCaller>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    caller.require(ApiScope::PublishNewsletters)?;
+    let ApiNewsletterData {
+        title,
+        markdown_content,
+        html_content,
+        text_content,
+        idempotency_key,
+    } = body.0;
+    let idempotency_key = IdempotencyKey::parse(idempotency_key)
+        .map_err(|e| ApiError::InvalidRequest(e.to_string()))?;
+    let content = NewsletterContent::parse(markdown_content, html_content, text_content)
+        .map_err(|e| ApiError::InvalidRequest(e.to_string()))?;
+
+    // Retries from the same user get the response to their first attempt
+    let mut transaction = match try_processing(&pool, &idempotency_key, &caller.user_id).await? {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+
+    let issue_id = insert_newsletter_issue(&title, &content, &caller.user_id, &mut transaction)
+        .await
+        .context("Failed to store newsletter issue details")?;
+    send_newsletter_issue(&issue_id, IssueStatus::Draft, &mut transaction)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    let event = AuditEvent::new(
+        AuditAction::NewsletterPublished,
+        serde_json::json!({
+            "newsletter_issue_id": issue_id,
+            "title": &title,
+            "api_token_id": caller.api_token_id,
+        }),
+    );
+    record_audit_event(&request, event, &mut transaction).await?;
+
+    let response =
+        HttpResponse::Accepted().json(serde_json::json!({ "newsletter_issue_id": issue_id }));
+    let response = save_response(transaction, &idempotency_key, &caller.user_id, response).await?;
+
+    Ok(response)
+}
@@ -1,7 +1,11 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use crate::utils::render_page;
 
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+use {actix_web::HttpResponse, askama::Template};
+
+#[derive(Template)]
+#[template(path = "home.html")]
+struct HomePage;
+
+pub async fn home() -> Result<HttpResponse, actix_web::Error> {
+    render_page(&HomePage)
 }
@@ -0,0 +1,134 @@
+use crate::{
+    authentication::{InvitationToken, Role},
+    startup::HmacSecret,
+    utils::{e500, render_page, see_other},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::{FlashMessage, IncomingFlashMessages},
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::{DateTime, Utc},
+    sqlx::PgExecutor,
+    uuid::Uuid,
+};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+#[derive(Template)]
+#[template(path = "invitations/accept.html")]
+struct AcceptInvitationPage<'a> {
+    flash_messages: IncomingFlashMessages,
+    role: Role,
+    token: &'a str,
+}
+
+#[tracing::instrument(
+    name = "Get invitation page",
+    skip(parameters, db_pool, hmac_secret, flash_messages)
+)]
+pub async fn accept_invitation_form(
+    parameters: web::Query<Parameters>,
+    db_pool: web::Data<sqlx::PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let invitation = match InvitationToken::verify(&parameters.token, &hmac_secret) {
+        Ok(token) => get_invitation(token.invitation_id(), db_pool.get_ref())
+            .await
+            .map_err(e500)?,
+        Err(_) => None,
+    };
+    let invitation = match check_invitation(invitation) {
+        Ok(invitation) => invitation,
+        Err(problem) => return Ok(problem.reject()),
+    };
+
+    render_page(&AcceptInvitationPage {
+        flash_messages,
+        role: invitation.role,
+        token: &parameters.token,
+    })
+}
+
+pub(super) struct Invitation {
+    pub(super) email: String,
+    pub(super) role: Role,
+    expires_at: DateTime<Utc>,
+    accepted_at: Option<DateTime<Utc>>,
+}
+
+pub(super) enum InvitationProblem {
+    Invalid,
+    Expired,
+    AlreadyUsed,
+    EmailTaken,
+}
+
+impl InvitationProblem {
+    /// Send the invitee back to the login page, explaining why the link did not work.
+    pub(super) fn reject(self) -> HttpResponse {
+        let message = match self {
+            InvitationProblem::Invalid => "This invitation link is not valid.",
+            InvitationProblem::Expired => {
+                "This invitation link has expired. Ask an owner to invite you again."
+            }
+            InvitationProblem::AlreadyUsed => {
+                "This invitation link has already been used. Log in with the account you created."
+            }
+            InvitationProblem::EmailTaken => {
+                "An account already exists for this email address. Log in with it instead."
+            }
+        };
+        FlashMessage::error(message).send();
+        see_other("/login")
+    }
+}
+
+pub(super) fn check_invitation(
+    invitation: Option<Invitation>,
+) -> Result<Invitation, InvitationProblem> {
+    let invitation = invitation.ok_or(InvitationProblem::Invalid)?;
+    if invitation.accepted_at.is_some() {
+        return Err(InvitationProblem::AlreadyUsed);
+    }
+    if invitation.expires_at <= Utc::now() {
+        return Err(InvitationProblem::Expired);
+    }
+    Ok(invitation)
+}
+
+/// Within a transaction the row stays locked until it ends, so an invitation can only be
+/// accepted once.
+#[tracing::instrument(name = "Get invitation", skip(executor))]
+pub(super) async fn get_invitation(
+    invitation_id: Uuid,
+    executor: impl PgExecutor<'_>,
+) -> Result<Option<Invitation>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT email, role, expires_at, accepted_at
+        FROM user_invitations
+        WHERE invitation_id = $1
+        FOR UPDATE
+        "#,
+        invitation_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to retrieve invitation")?;
+
+    row.map(|r| {
+        Ok(Invitation {
+            email: r.email,
+            role: Role::parse(&r.role).context("Invalid role stored in the database")?,
+            expires_at: r.expires_at,
+            accepted_at: r.accepted_at,
+        })
+    })
+    .transpose()
+}
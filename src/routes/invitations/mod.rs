@@ -0,0 +1,4 @@
+mod get;
+mod post;
+
+pub use {get::accept_invitation_form, post::accept_invitation};
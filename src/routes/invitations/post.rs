@@ -0,0 +1,184 @@
+use super::get::{check_invitation, get_invitation, Invitation, InvitationProblem};
+use crate::{
+    authentication::{compute_password_hash, InvitationToken, PasswordHashingParams},
+    domain::PasswordPolicy,
+    startup::HmacSecret,
+    telemetry::spawn_blocking_with_tracing,
+    utils::{e500, see_other},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::{Context, Result},
+    chrono::Utc,
+    secrecy::{ExposeSecret, Secret},
+    sqlx::{PgPool, Postgres, Transaction},
+    uuid::Uuid,
+};
+
+type Trans<'c> = Transaction<'c, Postgres>;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    token: String,
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Accept an invitation",
+    skip(form, db_pool, hmac_secret, hashing_params, password_policy),
+    fields(username = %form.username)
+)]
+pub async fn accept_invitation(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    hashing_params: web::Data<PasswordHashingParams>,
+    password_policy: web::Data<PasswordPolicy>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData {
+        token,
+        username,
+        password,
+        password_check,
+    } = form.0;
+    let invitation_id = match InvitationToken::verify(&token, &hmac_secret) {
+        Ok(token) => token.invitation_id(),
+        Err(_) => return Ok(InvitationProblem::Invalid.reject()),
+    };
+    let retry_url = format!("/invitations/accept?token={}", urlencoding::encode(&token));
+
+    let username = username.trim();
+    if username.is_empty() {
+        FlashMessage::error("Please choose a username.").send();
+        return Ok(see_other(&retry_url));
+    }
+    if password.expose_secret() != password_check.expose_secret() {
+        FlashMessage::error("You entered two different passwords - the field values must match.")
+            .send();
+        return Ok(see_other(&retry_url));
+    }
+    if let Err(violation) = password_policy.check(&password, username) {
+        FlashMessage::error(violation.to_string()).send();
+        return Ok(see_other(&retry_url));
+    }
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")
+        .map_err(e500)?;
+    let invitation = get_invitation(invitation_id, &mut transaction)
+        .await
+        .map_err(e500)?;
+    let invitation = match check_invitation(invitation) {
+        Ok(invitation) => invitation,
+        Err(problem) => return Ok(problem.reject()),
+    };
+
+    let hashing_params = hashing_params.get_ref().clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(&password, &hashing_params))
+            .await
+            .context("Failed to spawn blocking task")
+            .map_err(e500)?
+            .map_err(e500)?;
+
+    match insert_user(username, &invitation, password_hash, &mut transaction)
+        .await
+        .map_err(e500)?
+    {
+        NewUser::Created => {}
+        NewUser::UsernameTaken => {
+            FlashMessage::error("That username is already taken.").send();
+            return Ok(see_other(&retry_url));
+        }
+        NewUser::EmailTaken => return Ok(InvitationProblem::EmailTaken.reject()),
+    }
+    mark_invitations_accepted(&invitation.email, &mut transaction)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to accept invitation")
+        .map_err(e500)?;
+
+    FlashMessage::info("Your account has been created. You can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+enum NewUser {
+    Created,
+    UsernameTaken,
+    /// Someone already has an account with the invited address, e.g. through another invitation.
+    EmailTaken,
+}
+
+#[tracing::instrument(
+    name = "Create invited user",
+    skip(invitation, password_hash, transaction)
+)]
+async fn insert_user(
+    username: &str,
+    invitation: &Invitation,
+    password_hash: Secret<String>,
+    transaction: &mut Trans<'_>,
+) -> Result<NewUser> {
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role, email)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+        invitation.role.as_str(),
+        invitation.email,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to create invited user")?
+    .rows_affected();
+    if n_inserted == 1 {
+        return Ok(NewUser::Created);
+    }
+
+    let email_taken = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM users WHERE email = $1) AS "taken!""#,
+        invitation.email,
+    )
+    .fetch_one(transaction)
+    .await
+    .context("Failed to look up users by email")?
+    .taken;
+
+    Ok(if email_taken {
+        NewUser::EmailTaken
+    } else {
+        NewUser::UsernameTaken
+    })
+}
+
+/// Every pending invitation to the address is settled at once, since the invitee now has an
+/// account and could not create another one with it.
+#[tracing::instrument(name = "Mark invitations as accepted", skip(transaction))]
+async fn mark_invitations_accepted(email: &str, transaction: &mut Trans<'_>) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE user_invitations SET accepted_at = $1
+        WHERE email = $2 AND accepted_at IS NULL
+        "#,
+        Utc::now(),
+        email
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to mark invitations as accepted")?;
+
+    Ok(())
+}
@@ -1,50 +1,47 @@
-use std::fmt::Write;
-
-use {
-    actix_web::{http::header::ContentType, HttpResponse},
-    actix_web_flash_messages::IncomingFlashMessages,
+use crate::{
+    authentication::form_csrf_token,
+    session_state::TypedSession,
+    utils::{e500, render_page, see_other},
 };
 
-#[tracing::instrument(name = "Get login page", skip(flash_messages))]
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut error_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap()
+use {actix_web::HttpResponse, actix_web_flash_messages::IncomingFlashMessages, askama::Template};
+
+#[derive(Template)]
+#[template(path = "login/login.html")]
+struct LoginPage {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+}
+
+#[tracing::instrument(name = "Get login page", skip(flash_messages, session))]
+pub async fn login_form(
+    flash_messages: IncomingFlashMessages,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_page(&LoginPage {
+        flash_messages,
+        csrf_token: form_csrf_token(&session)?,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "login/second_factor.html")]
+struct SecondFactorPage {
+    flash_messages: IncomingFlashMessages,
+    csrf_token: String,
+}
+
+#[tracing::instrument(name = "Get second factor page", skip(flash_messages, session))]
+pub async fn second_factor_form(
+    flash_messages: IncomingFlashMessages,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_pending_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
     }
 
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"
-<!DOCTYPE html>
-<html lang="en">
-    <head>
-        <meta http-equiv="content-type" content="text/html; charset=utf-8">
-        <title>Login</title>
-    </head>
-    <body>
-        {error_html}
-        <form action="/login" method="post">
-            <label>
-                Username
-                <input
-                    type="text"
-                    placeholder="Enter Username"
-                    name="username"
-                >
-            </label>
-            <label>
-                Password
-                <input
-                    type="password"
-                    placeholder="Enter Password"
-                    name="password"
-                >
-            </label>
-            <button type="submit">Login</button>
-        </form>
-    </body>
-</html>
-            "#,
-        ))
+    render_page(&SecondFactorPage {
+        flash_messages,
+        csrf_token: form_csrf_token(&session)?,
+    })
 }
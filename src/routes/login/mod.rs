@@ -1,4 +1,7 @@
 mod get;
 mod post;
 
-pub use {get::login_form, post::login};
+pub use {
+    get::{login_form, second_factor_form},
+    post::{login, second_factor},
+};
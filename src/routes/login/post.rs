@@ -1,15 +1,24 @@
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials},
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{
+        check_login_throttle, clear_login_failures, get_totp_secret, lockout_minutes,
+        record_login_failure, validate_credentials, verify_second_factor, AuthError, Credentials,
+        PasswordHashingParams, SessionClient, SessionRegistry, ThrottleDecision,
+    },
+    configuration::LoginThrottleSettings,
+    routes::get_username,
     session_state::TypedSession,
-    utils::see_other,
+    utils::{client_ip, e500, see_other},
 };
 
 use {
-    actix_web::{error::InternalError, web, HttpResponse},
+    actix_web::{error::InternalError, web, HttpRequest, HttpResponse},
     actix_web_flash_messages::FlashMessage,
+    chrono::Utc,
     secrecy::Secret,
     serde::Deserialize,
     sqlx::PgPool,
+    uuid::Uuid,
 };
 
 #[derive(Debug, Deserialize)]
@@ -18,31 +27,91 @@ pub struct FormData {
     password: Secret<String>,
 }
 
-#[tracing::instrument(name = "Login", skip(form, db_pool, session))]
+#[tracing::instrument(
+    name = "Login",
+    skip(form, db_pool, hashing_params, throttle, registry, request, session),
+    fields(
+        username = tracing::field::Empty,
+        user_id = tracing::field::Empty,
+        client_ip = tracing::field::Empty
+    )
+)]
 pub async fn login(
     form: web::Form<FormData>,
     db_pool: web::Data<PgPool>,
+    hashing_params: web::Data<PasswordHashingParams>,
+    throttle: web::Data<LoginThrottleSettings>,
+    registry: web::Data<SessionRegistry>,
+    request: HttpRequest,
     session: TypedSession,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    let username = credentials.username.clone();
+    let client_ip = client_ip(&request);
+    tracing::Span::current().record("username", &tracing::field::display(&username));
+    tracing::Span::current().record("client_ip", &tracing::field::display(&client_ip));
 
-    match validate_credentials(credentials, &db_pool).await {
+    if let Err(e) = wait_for_throttle(&username, &client_ip, &throttle, &db_pool).await {
+        if let LoginError::LockedOut(_) = e {
+            audit_login_failure(&request, &username, None, "locked_out", &db_pool)
+                .await
+                .map_err(login_redirect)?;
+        }
+        return Err(login_redirect(e));
+    }
+
+    match validate_credentials(credentials, &hashing_params, &db_pool).await {
         Ok(user_id) => {
             tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            let has_second_factor = get_totp_secret(&user_id, &db_pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+                .is_some();
+
             session.renew();
-            session
-                .insert_user_id(&user_id)
-                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            if has_second_factor {
+                // Whoever was logged in before stays logged out until the code is given
+                end_previous_session(&session, &registry)
+                    .await
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                session
+                    .insert_pending_user_id(&user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(see_other("/login/two-factor"));
+            }
+            // Only once the whole login succeeded, or the password alone would reset the
+            // lockout that wrong codes count towards
+            clear_login_failures(&username, &db_pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            start_session(&session, &user_id, &request, &registry)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            let event = AuditEvent::new(
+                AuditAction::LoginSucceeded,
+                serde_json::json!({ "username": &username, "second_factor": false }),
+            )
+            .by(user_id);
+            record_audit_event(&request, event, db_pool.get_ref())
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
 
             Ok(see_other("/admin/dashboard"))
         }
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::InvalidCredentials(_) => {
+                    record_login_failure(&username, &client_ip, &throttle, &db_pool)
+                        .await
+                        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                    audit_login_failure(&request, &username, None, "invalid_credentials", &db_pool)
+                        .await
+                        .map_err(login_redirect)?;
+                    LoginError::AuthError(e.into())
+                }
                 AuthError::Unexpected(_) => LoginError::UnexpectedError(e.into()),
             };
             Err(login_redirect(e))
@@ -50,6 +119,164 @@ pub async fn login(
     }
 }
 
+#[derive(Deserialize)]
+pub struct SecondFactorFormData {
+    code: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Second factor",
+    skip(form, db_pool, throttle, registry, request, session),
+    fields(user_id = tracing::field::Empty, client_ip = tracing::field::Empty)
+)]
+pub async fn second_factor(
+    form: web::Form<SecondFactorFormData>,
+    db_pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottleSettings>,
+    registry: web::Data<SessionRegistry>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_pending_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    let client_ip = client_ip(&request);
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    tracing::Span::current().record("client_ip", &tracing::field::display(&client_ip));
+
+    // Codes are much easier to guess than passwords, so they count towards the same limits
+    let username = get_username(&user_id, &db_pool).await.map_err(e500)?;
+    if let Err(e) = wait_for_throttle(&username, &client_ip, &throttle, &db_pool).await {
+        return match e {
+            LoginError::LockedOut(_) => {
+                audit_login_failure(&request, &username, Some(user_id), "locked_out", &db_pool)
+                    .await
+                    .map_err(e500)?;
+                session.log_out();
+                FlashMessage::error(e.to_string()).send();
+                Ok(see_other("/login"))
+            }
+            e => Err(e500(e)),
+        };
+    }
+
+    if !verify_second_factor(&user_id, form.0.code, &db_pool)
+        .await
+        .map_err(e500)?
+    {
+        record_login_failure(&username, &client_ip, &throttle, &db_pool)
+            .await
+            .map_err(e500)?;
+        audit_login_failure(
+            &request,
+            &username,
+            Some(user_id),
+            "invalid_second_factor",
+            &db_pool,
+        )
+        .await
+        .map_err(e500)?;
+        FlashMessage::error("That code is not valid.").send();
+        return Ok(see_other("/login/two-factor"));
+    }
+
+    clear_login_failures(&username, &db_pool)
+        .await
+        .map_err(e500)?;
+    session.renew();
+    session.remove_pending_user_id();
+    start_session(&session, &user_id, &request, &registry)
+        .await
+        .map_err(e500)?;
+    let event = AuditEvent::new(
+        AuditAction::LoginSucceeded,
+        serde_json::json!({ "username": &username, "second_factor": true }),
+    )
+    .by(user_id);
+    record_audit_event(&request, event, db_pool.get_ref())
+        .await
+        .map_err(e500)?;
+
+    Ok(see_other("/admin/dashboard"))
+}
+
+/// Failed attempts are attributed to the user when we know who they are, i.e. when only their
+/// second factor was wrong.
+async fn audit_login_failure(
+    request: &HttpRequest,
+    username: &str,
+    user_id: Option<Uuid>,
+    reason: &str,
+    db_pool: &PgPool,
+) -> Result<(), LoginError> {
+    let mut event = AuditEvent::new(
+        AuditAction::LoginFailed,
+        serde_json::json!({ "username": username, "reason": reason }),
+    );
+    if let Some(user_id) = user_id {
+        event = event.by(user_id);
+    }
+    record_audit_event(request, event, db_pool).await?;
+    Ok(())
+}
+
+/// Hold the attempt back after recent failures, or reject it outright during a lockout.
+async fn wait_for_throttle(
+    username: &str,
+    client_ip: &str,
+    throttle: &LoginThrottleSettings,
+    db_pool: &PgPool,
+) -> Result<(), LoginError> {
+    match check_login_throttle(username, client_ip, throttle, db_pool).await? {
+        ThrottleDecision::Allowed { delay } => {
+            tokio::time::sleep(delay).await;
+            Ok(())
+        }
+        ThrottleDecision::LockedOut { until } => {
+            tracing::info!(%until, "Rejected a login attempt during a lockout");
+            Err(LoginError::LockedOut(lockout_minutes(until)))
+        }
+    }
+}
+
+async fn start_session(
+    session: &TypedSession,
+    user_id: &Uuid,
+    request: &HttpRequest,
+    registry: &SessionRegistry,
+) -> Result<(), anyhow::Error> {
+    end_previous_session(session, registry).await?;
+
+    let login_time = Utc::now();
+    let session_id = registry
+        .register(user_id, &SessionClient::new(request), login_time)
+        .await?;
+    session.insert_user_id(user_id)?;
+    session.insert_login_time(login_time)?;
+    session.insert_session_id(&session_id)?;
+    // Minted now rather than by the first form, so that pages loaded at the same time don't
+    // each create one and overwrite each other's
+    session.csrf_token()?;
+    Ok(())
+}
+
+/// Logging in again from a session replaces it, even before the new login is complete.
+async fn end_previous_session(
+    session: &TypedSession,
+    registry: &SessionRegistry,
+) -> Result<(), anyhow::Error> {
+    if let (Some(previous_user_id), Some(previous_session_id)) =
+        (session.get_user_id()?, session.get_session_id()?)
+    {
+        registry
+            .revoke(&previous_user_id, &previous_session_id)
+            .await?;
+    }
+    session.remove_login();
+    Ok(())
+}
+
 fn login_redirect(e: LoginError) -> InternalError<LoginError> {
     FlashMessage::error(e.to_string()).send();
     InternalError::from_response(e, see_other("/login"))
@@ -59,6 +286,8 @@ fn login_redirect(e: LoginError) -> InternalError<LoginError> {
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("Too many failed login attempts. Please try again in {0} minutes.")]
+    LockedOut(i64),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
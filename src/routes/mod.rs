@@ -1,10 +1,15 @@
 mod admin;
+mod api;
 mod health_check;
 mod home;
+mod invitations;
 mod login;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
 
 pub use {
-    admin::*, health_check::*, home::*, login::*, subscriptions::*, subscriptions_confirm::*,
+    admin::*, api::*, health_check::*, home::*, invitations::*, login::*, password_reset::*,
+    subscriptions::*, subscriptions_confirm::*, subscriptions_unsubscribe::*,
 };
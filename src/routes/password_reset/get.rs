@@ -0,0 +1,131 @@
+use crate::{
+    authentication::PasswordResetToken,
+    utils::{e500, render_page, see_other},
+};
+
+use {
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::{FlashMessage, IncomingFlashMessages},
+    anyhow::{Context, Result},
+    askama::Template,
+    chrono::{DateTime, Utc},
+    sqlx::{PgExecutor, PgPool},
+    uuid::Uuid,
+};
+
+#[derive(Template)]
+#[template(path = "password_reset/forgot_password.html")]
+struct ForgotPasswordPage {
+    flash_messages: IncomingFlashMessages,
+}
+
+#[tracing::instrument(name = "Get forgotten password page", skip(flash_messages))]
+pub async fn forgot_password_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_page(&ForgotPasswordPage { flash_messages })
+}
+
+#[derive(Template)]
+#[template(path = "password_reset/reset_password.html")]
+struct ResetPasswordPage {
+    flash_messages: IncomingFlashMessages,
+    token: PasswordResetToken,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+#[tracing::instrument(
+    name = "Get reset password page",
+    skip(parameters, db_pool, flash_messages)
+)]
+pub async fn reset_password_form(
+    parameters: web::Query<Parameters>,
+    db_pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let reset_request = match PasswordResetToken::parse(parameters.0.token) {
+        Ok(token) => get_reset_request(&token, db_pool.get_ref())
+            .await
+            .map_err(e500)?
+            .map(|request| (token, request)),
+        Err(_) => None,
+    };
+    let token = match reset_request {
+        Some((token, request)) => match request.check() {
+            Ok(()) => token,
+            Err(problem) => return Ok(problem.reject()),
+        },
+        None => return Ok(ResetProblem::Invalid.reject()),
+    };
+
+    render_page(&ResetPasswordPage {
+        flash_messages,
+        token,
+    })
+}
+
+pub(super) struct ResetRequest {
+    pub(super) user_id: Uuid,
+    pub(super) username: String,
+    expires_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+}
+
+impl ResetRequest {
+    pub(super) fn check(&self) -> Result<(), ResetProblem> {
+        if self.used_at.is_some() {
+            Err(ResetProblem::AlreadyUsed)
+        } else if self.expires_at <= Utc::now() {
+            Err(ResetProblem::Expired)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub(super) enum ResetProblem {
+    Invalid,
+    Expired,
+    AlreadyUsed,
+}
+
+impl ResetProblem {
+    /// Send the user back to the forgotten password page so that they can ask for a new link.
+    pub(super) fn reject(self) -> HttpResponse {
+        let message = match self {
+            ResetProblem::Invalid => "This password reset link is not valid.",
+            ResetProblem::Expired => "This password reset link has expired.",
+            ResetProblem::AlreadyUsed => "This password reset link has already been used.",
+        };
+        FlashMessage::error(message).send();
+        see_other("/login/forgot")
+    }
+}
+
+/// Within a transaction the row stays locked until it ends, so a link can only be used once.
+#[tracing::instrument(name = "Get password reset request", skip(token, executor))]
+pub(super) async fn get_reset_request(
+    token: &PasswordResetToken,
+    executor: impl PgExecutor<'_>,
+) -> Result<Option<ResetRequest>> {
+    let request = sqlx::query_as!(
+        ResetRequest,
+        r#"
+        SELECT t.user_id, u.username, t.expires_at, t.used_at
+        FROM password_reset_tokens t
+        JOIN users u ON u.user_id = t.user_id
+        WHERE t.token_hash = $1
+        FOR UPDATE OF t
+        "#,
+        token.digest()
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to retrieve password reset request")?;
+
+    Ok(request)
+}
@@ -0,0 +1,7 @@
+mod get;
+mod post;
+
+pub use {
+    get::{forgot_password_form, reset_password_form},
+    post::{forgot_password, reset_password},
+};
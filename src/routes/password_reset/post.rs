@@ -0,0 +1,179 @@
+use super::get::{get_reset_request, ResetProblem};
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    authentication::{
+        invalidate_sessions, PasswordHashingParams, PasswordResetToken, SessionRegistry,
+    },
+    domain::PasswordPolicy,
+    utils::{e500, see_other},
+};
+
+use {
+    actix_web::{web, HttpRequest, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::{Context, Result},
+    chrono::Utc,
+    secrecy::{ExposeSecret, Secret},
+    sqlx::{PgPool, Postgres, Transaction},
+    uuid::Uuid,
+};
+
+type Trans<'c> = Transaction<'c, Postgres>;
+
+#[derive(serde::Deserialize)]
+pub struct ForgotFormData {
+    username: String,
+}
+
+/// Queue a reset email for `password_reset_worker` to send.
+///
+/// Nothing here depends on whether the account exists, so neither the answer nor the time it
+/// takes gives that away.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, db_pool),
+    fields(username = %form.username)
+)]
+pub async fn forgot_password(
+    form: web::Form<ForgotFormData>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    queue_reset_request(form.username.trim(), &db_pool)
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info(
+        "If that account exists, a link to reset its password has been sent to its email address.",
+    )
+    .send();
+    Ok(see_other("/login"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetFormData {
+    token: String,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(form, db_pool, hashing_params, password_policy, registry, http_request),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn reset_password(
+    form: web::Form<ResetFormData>,
+    db_pool: web::Data<PgPool>,
+    hashing_params: web::Data<PasswordHashingParams>,
+    password_policy: web::Data<PasswordPolicy>,
+    registry: web::Data<SessionRegistry>,
+    http_request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ResetFormData {
+        token,
+        new_password,
+        new_password_check,
+    } = form.0;
+    let token = match PasswordResetToken::parse(token) {
+        Ok(token) => token,
+        Err(_) => return Ok(ResetProblem::Invalid.reject()),
+    };
+    let retry_url = format!("/login/reset?token={}", token.as_ref());
+
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other(&retry_url));
+    }
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")
+        .map_err(e500)?;
+    let request = match get_reset_request(&token, &mut transaction)
+        .await
+        .map_err(e500)?
+    {
+        Some(request) => request,
+        None => return Ok(ResetProblem::Invalid.reject()),
+    };
+    if let Err(problem) = request.check() {
+        return Ok(problem.reject());
+    }
+    // Checked before the token is used up, so that the user can try another password
+    if let Err(violation) = password_policy.check(&new_password, &request.username) {
+        FlashMessage::error(violation.to_string()).send();
+        return Ok(see_other(&retry_url));
+    }
+    let user_id = request.user_id;
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+
+    // The token is only used up along with the password actually changing
+    use_reset_tokens(&user_id, &mut transaction)
+        .await
+        .map_err(e500)?;
+    crate::authentication::change_password(
+        &user_id,
+        new_password,
+        &hashing_params,
+        &mut transaction,
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to reset password")
+        .map_err(e500)?;
+
+    invalidate_sessions(&user_id, &db_pool, &registry)
+        .await
+        .map_err(e500)?;
+    let event = AuditEvent::new(
+        AuditAction::PasswordChanged,
+        serde_json::json!({ "method": "reset" }),
+    )
+    .by(user_id);
+    record_audit_event(&http_request, event, db_pool.get_ref())
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("Your password has been reset. You can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+#[tracing::instrument(name = "Queue password reset request", skip(db_pool))]
+async fn queue_reset_request(username: &str, db_pool: &PgPool) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO password_reset_queue (request_id, username) VALUES ($1, $2)",
+        Uuid::new_v4(),
+        username,
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to queue password reset request")?;
+
+    Ok(())
+}
+
+/// Mark every outstanding link for the user as used, not only the one that was followed.
+#[tracing::instrument(name = "Use password reset tokens", skip(transaction))]
+async fn use_reset_tokens(user_id: &Uuid, transaction: &mut Trans<'_>) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = $1
+        WHERE user_id = $2 AND used_at IS NULL
+        "#,
+        Utc::now(),
+        user_id
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to mark password reset tokens as used")?;
+
+    Ok(())
+}
@@ -1,14 +1,21 @@
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriberNameValidationError},
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    domain::{
+        NewSubscriber, SubscriberEmail, SubscriberName, SubscriberNameValidationError,
+        SubscriptionToken,
+    },
+    email_client::EmailSender,
     startup::ApplicationBaseUrl,
-    EmailClient,
 };
 
 use {
-    actix_web::{http::StatusCode, web, HttpResponse, ResponseError},
+    actix_web::{
+        http::{header::ContentType, StatusCode},
+        web, HttpRequest, HttpResponse, ResponseError,
+    },
     anyhow::Context,
+    askama::Template,
     chrono::Utc,
-    rand::{distributions::Alphanumeric, thread_rng, Rng},
     sqlx::{PgPool, Postgres, Transaction},
     uuid::Uuid,
 };
@@ -59,7 +66,7 @@ impl ResponseError for SubscribeError {
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, email_client, base_url, request),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name,
@@ -68,8 +75,9 @@ impl ResponseError for SubscribeError {
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    email_client: web::Data<dyn EmailSender>,
     base_url: web::Data<ApplicationBaseUrl>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, SubscribeError> {
     let new_sub: NewSubscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
 
@@ -78,25 +86,87 @@ pub async fn subscribe(
         .await
         .context("Failed to find existing subscription for email")?
     {
-        // The user has already subscribed, so we refresh their subscription
-        Some(user_id) => refresh_existing_subscription(user_id, &pool).await?,
+        // Anyone can submit an address, so a confirmed subscription is left as it is rather
+        // than sent back to confirmation, where it would eventually be cleaned up
+        Some(existing) if existing.status == "confirmed" => {
+            tracing::info!("The subscription is already confirmed");
+            return Ok(HttpResponse::Ok().finish());
+        }
+        // The user hasn't confirmed yet or has left, so we refresh their subscription
+        Some(existing) => refresh_existing_subscription(existing.id, &pool).await?,
         // This is a new user, so store their info as a new subscription
-        None => create_new_subscription(&new_sub, &pool).await?,
+        None => create_new_subscription(&new_sub, &request, &pool).await?,
     };
 
     // Send a confirmation email to the new subscriber
-    send_confirmation_email(&new_sub, &email_client, &base_url.0, &subscription_token)
+    send_confirmation_email(
+        &new_sub.email,
+        email_client.get_ref(),
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send confirmation email")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ResendFormData {
+    email: String,
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/resend_confirmation.html")]
+struct ResendConfirmationPage;
+
+/// Send a fresh confirmation link to a subscriber whose previous one expired.
+///
+/// The response is the same whether or not the address is pending confirmation, so that it
+/// can't be used to find out who is subscribed.
+#[tracing::instrument(
+    name = "Resending a confirmation email",
+    skip(form, pool, email_client, base_url),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, SubscribeError> {
+    let email =
+        SubscriberEmail::parse(form.0.email).ok_or(NewSubscriberValidationError::InvalidEmail)?;
+
+    if let Some(subscriber_id) = find_pending_subscriber(&email, &pool)
+        .await
+        .context("Failed to find pending subscription for email")?
+    {
+        let subscription_token = refresh_existing_subscription(subscriber_id, &pool).await?;
+        send_confirmation_email(
+            &email,
+            email_client.get_ref(),
+            &base_url.0,
+            &subscription_token,
+        )
         .await
         .context("Failed to send confirmation email")?;
+    }
 
-    Ok(HttpResponse::Ok().finish())
+    let body = ResendConfirmationPage
+        .render()
+        .context("Failed to render the resend confirmation page")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
 }
 
-#[tracing::instrument(name = "Creating a new subscription", skip(new_sub, pool))]
+#[tracing::instrument(name = "Creating a new subscription", skip(new_sub, request, pool))]
 async fn create_new_subscription(
     new_sub: &NewSubscriber,
+    request: &HttpRequest,
     pool: &PgPool,
-) -> Result<String, SubscribeError> {
+) -> Result<SubscriptionToken, SubscribeError> {
     // Transaction start
     let mut transaction = pool
         .begin()
@@ -109,11 +179,18 @@ async fn create_new_subscription(
         .context("Failed to insert new subscriber in the database")?;
 
     // Store the subscriber's token
-    let subscription_token = generate_subscription_token();
+    let subscription_token = SubscriptionToken::generate();
     store_token(&subscriber_id, &subscription_token, &mut transaction)
         .await
         .context("Failed to store the confirmation token for a new subscriber")?;
 
+    // Subscribers are identified by ID only, the log is no place for their addresses
+    let event = AuditEvent::new(
+        AuditAction::SubscriberAdded,
+        serde_json::json!({ "subscriber_id": subscriber_id }),
+    );
+    record_audit_event(request, event, &mut transaction).await?;
+
     // Transaction commit
     transaction
         .commit()
@@ -130,7 +207,7 @@ async fn create_new_subscription(
 async fn refresh_existing_subscription(
     subscriber_id: Uuid,
     pool: &PgPool,
-) -> Result<String, SubscribeError> {
+) -> Result<SubscriptionToken, SubscribeError> {
     // Transaction start
     let mut transaction = pool
         .begin()
@@ -148,7 +225,7 @@ async fn refresh_existing_subscription(
         .context("Failed to invalidate existing subscription token")?;
 
     // Create a new subscription token
-    let subscription_token = generate_subscription_token();
+    let subscription_token = SubscriptionToken::generate();
     store_token(&subscriber_id, &subscription_token, &mut transaction)
         .await
         .context("Failed to store the confirmation token for a new subscriber")?;
@@ -162,18 +239,23 @@ async fn refresh_existing_subscription(
     Ok(subscription_token)
 }
 
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+}
+
 #[tracing::instrument("Find existing subscription by email", skip(email, pool))]
 async fn find_existing_subscriber(
     email: &SubscriberEmail,
     pool: &PgPool,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let existing = sqlx::query!(
-        r#"SELECT id FROM subscriptions WHERE email = $1"#,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    let existing = sqlx::query_as!(
+        ExistingSubscriber,
+        r#"SELECT id, status FROM subscriptions WHERE email = $1"#,
         email.as_ref()
     )
     .fetch_optional(pool)
-    .await?
-    .map(|r| r.id);
+    .await?;
 
     if existing.is_some() {
         tracing::info!("Existing subscription found for email {}", email.as_ref());
@@ -182,6 +264,22 @@ async fn find_existing_subscriber(
     Ok(existing)
 }
 
+#[tracing::instrument("Find pending subscription by email", skip(email, pool))]
+async fn find_pending_subscriber(
+    email: &SubscriberEmail,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let pending = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE email = $1 AND status = 'pending_confirmation'"#,
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|r| r.id);
+
+    Ok(pending)
+}
+
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_sub, trans)
@@ -191,16 +289,18 @@ async fn insert_subscriber(
     trans: &mut Trans<'_>,
 ) -> Result<Uuid, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
+    let unsubscribe_token = SubscriptionToken::generate();
 
     sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+        VALUES ($1, $2, $3, $4, 'pending_confirmation', $5)
         "#,
         subscriber_id,
         new_sub.email.as_ref(),
         new_sub.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        unsubscribe_token.as_ref(),
     )
     .execute(trans)
     .await?;
@@ -248,13 +348,13 @@ async fn invalidate_previous_tokens(
 )]
 async fn store_token(
     subscriber_id: &Uuid,
-    subscription_token: &str,
+    subscription_token: &SubscriptionToken,
     trans: &mut Trans<'_>,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id, is_valid)
+        r#"INSERT INTO subscription_tokens (subscription_token_hash, subscriber_id, is_valid)
         VALUES ($1, $2, true)"#,
-        subscription_token,
+        subscription_token.digest(),
         subscriber_id,
     )
     .execute(trans)
@@ -263,41 +363,47 @@ async fn store_token(
     Ok(())
 }
 
+#[derive(Template)]
+#[template(path = "emails/confirmation.html")]
+struct ConfirmationEmailHtml<'a> {
+    confirmation_link: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/confirmation.txt")]
+struct ConfirmationEmailText<'a> {
+    confirmation_link: &'a str,
+}
+
 #[tracing::instrument(
     name = "Sending confirmation email to new subscriber",
-    skip(email_client, new_sub, base_url, subscription_token)
+    skip(email_client, recipient, base_url, subscription_token)
 )]
 async fn send_confirmation_email(
-    new_sub: &NewSubscriber,
-    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    email_client: &dyn EmailSender,
     base_url: &str,
-    subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+    subscription_token: &SubscriptionToken,
+) -> anyhow::Result<()> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
-        base_url, subscription_token
-    );
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-        Click <a href=\"{}\"> here to confirm your subscription.",
-        confirmation_link
-    );
-    let text_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
+        base_url,
+        subscription_token.as_ref()
     );
+    let html_body = ConfirmationEmailHtml {
+        confirmation_link: &confirmation_link,
+    }
+    .render()
+    .context("Failed to render the HTML version of the confirmation email")?;
+    let text_body = ConfirmationEmailText {
+        confirmation_link: &confirmation_link,
+    }
+    .render()
+    .context("Failed to render the text version of the confirmation email")?;
 
     email_client
-        .send_email(&new_sub.email.clone(), "Welcome!", &html_body, &text_body)
+        .send_email(recipient, "Welcome!", &html_body, &text_body)
         .await?;
 
     Ok(())
 }
-
-fn generate_subscription_token() -> String {
-    let mut rng = thread_rng();
-    std::iter::repeat_with(|| rng.sample(Alphanumeric))
-        .map(char::from)
-        .take(25)
-        .collect()
-}
@@ -1,8 +1,17 @@
-use crate::domain::{SubTokenValidationError, SubscriptionToken};
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    domain::{SubTokenValidationError, SubscriptionToken},
+    startup::ConfirmationTokenExpiry,
+};
 
 use {
-    actix_web::{http::StatusCode, web, HttpResponse, ResponseError},
+    actix_web::{
+        http::{header::ContentType, StatusCode},
+        web, HttpRequest, HttpResponse, ResponseError,
+    },
     anyhow::Context,
+    askama::Template,
+    chrono::{DateTime, Utc},
     serde::Deserialize,
     sqlx::PgPool,
     uuid::Uuid,
@@ -27,35 +36,69 @@ pub enum SubConfirmationError {
     MalformedToken(#[from] SubTokenValidationError),
     #[error("Token is not valid")]
     InvalidToken,
+    #[error("Token has expired")]
+    ExpiredToken,
     #[error(transparent)]
     Unexpected(#[from] anyhow::Error),
 }
 
+#[derive(Template)]
+#[template(path = "subscriptions/expired_token.html")]
+struct ExpiredTokenPage;
+
 impl ResponseError for SubConfirmationError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubConfirmationError::MalformedToken(_) => StatusCode::BAD_REQUEST,
             SubConfirmationError::InvalidToken => StatusCode::UNAUTHORIZED,
+            SubConfirmationError::ExpiredToken => StatusCode::GONE,
             SubConfirmationError::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        // Offer to send a new link, falling back to the plain error if the page can't be rendered
+        if let SubConfirmationError::ExpiredToken = self {
+            if let Ok(body) = ExpiredTokenPage.render() {
+                return response.content_type(ContentType::html()).body(body);
+            }
+        }
+        response
+            .content_type(ContentType::plaintext())
+            .body(self.to_string())
+    }
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(params, pool))]
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(params, pool, token_expiry, request)
+)]
 pub async fn confirm(
     params: web::Query<Parameters>,
     pool: web::Data<PgPool>,
+    token_expiry: web::Data<ConfirmationTokenExpiry>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, SubConfirmationError> {
     let subscription_token: SubscriptionToken = params.0.try_into()?;
 
-    let sub_id = get_subscriber_id_from_token(&subscription_token, &pool)
+    let (sub_id, created_at) = get_subscriber_id_from_token(&subscription_token, &pool)
         .await
         .context("Failed to get subscriber ID from token")?
         .ok_or(SubConfirmationError::InvalidToken)?;
 
+    if created_at + token_expiry.0 < Utc::now() {
+        return Err(SubConfirmationError::ExpiredToken);
+    }
+
     confirm_subscriber(&sub_id, &pool)
         .await
         .context("Failed to mark subscriber as confirmed")?;
+    let event = AuditEvent::new(
+        AuditAction::SubscriberConfirmed,
+        serde_json::json!({ "subscriber_id": sub_id }),
+    );
+    record_audit_event(&request, event, pool.get_ref()).await?;
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -76,13 +119,13 @@ async fn confirm_subscriber(sub_id: &Uuid, pool: &PgPool) -> Result<(), sqlx::Er
 async fn get_subscriber_id_from_token(
     token: &SubscriptionToken,
     pool: &PgPool,
-) -> Result<Option<Uuid>, sqlx::Error> {
+) -> Result<Option<(Uuid, DateTime<Utc>)>, sqlx::Error> {
     let result = sqlx::query!(
-        r#"SELECT subscriber_id, is_valid FROM subscription_tokens WHERE subscription_token = $1 AND is_valid = true"#,
-        token.as_ref(),
+        r#"SELECT subscriber_id, created_at FROM subscription_tokens WHERE subscription_token_hash = $1 AND is_valid = true"#,
+        token.digest(),
     )
     .fetch_optional(pool)
     .await?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(result.map(|r| (r.subscriber_id, r.created_at)))
 }
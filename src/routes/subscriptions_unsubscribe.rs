@@ -0,0 +1,171 @@
+use crate::{
+    audit::{record_audit_event, AuditAction, AuditEvent},
+    domain::{SubTokenValidationError, SubscriptionToken},
+};
+
+use {
+    actix_web::{
+        http::{header::ContentType, StatusCode},
+        web, HttpRequest, HttpResponse, ResponseError,
+    },
+    anyhow::Context,
+    askama::Template,
+    serde::Deserialize,
+    sqlx::{PgPool, Postgres, Transaction},
+    uuid::Uuid,
+};
+
+type Trans<'c> = Transaction<'c, Postgres>;
+
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeParameters {
+    unsubscribe_token: String,
+}
+
+impl TryFrom<UnsubscribeParameters> for SubscriptionToken {
+    type Error = SubTokenValidationError;
+
+    fn try_from(params: UnsubscribeParameters) -> Result<Self, Self::Error> {
+        SubscriptionToken::parse(params.unsubscribe_token)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("{0}")]
+    MalformedToken(#[from] SubTokenValidationError),
+    #[error("Token is not valid")]
+    InvalidToken,
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/unsubscribe.html")]
+struct UnsubscribePage {
+    unsubscribe_token: SubscriptionToken,
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/unsubscribed.html")]
+struct UnsubscribedPage;
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::MalformedToken(_) => StatusCode::BAD_REQUEST,
+            UnsubscribeError::InvalidToken => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Ask the subscriber to confirm before unsubscribing them.
+///
+/// Link scanners and mail clients prefetch links, so a `GET` must never change the
+/// subscription. Clients supporting RFC 8058 skip this page and `POST` straight away.
+#[tracing::instrument(name = "Get unsubscribe page", skip(params, pool))]
+pub async fn unsubscribe_form(
+    params: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let unsubscribe_token: SubscriptionToken = params.0.try_into()?;
+
+    get_subscriber_id_from_unsubscribe_token(&unsubscribe_token, &pool)
+        .await
+        .context("Failed to get subscriber ID from unsubscribe token")?
+        .ok_or(UnsubscribeError::InvalidToken)?;
+
+    let body = UnsubscribePage { unsubscribe_token }
+        .render()
+        .context("Failed to render the unsubscribe page")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(params, pool, request))]
+pub async fn unsubscribe(
+    params: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let unsubscribe_token: SubscriptionToken = params.0.try_into()?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")?;
+
+    let sub_id = mark_subscriber_as_unsubscribed(&unsubscribe_token, &mut transaction)
+        .await
+        .context("Failed to mark subscriber as unsubscribed")?
+        .ok_or(UnsubscribeError::InvalidToken)?;
+
+    // A pending confirmation link must not bring the subscriber back
+    invalidate_confirmation_tokens(&sub_id, &mut transaction)
+        .await
+        .context("Failed to invalidate confirmation tokens")?;
+    let event = AuditEvent::new(
+        AuditAction::SubscriberUnsubscribed,
+        serde_json::json!({ "subscriber_id": sub_id }),
+    );
+    record_audit_event(&request, event, &mut transaction).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to unsubscribe a subscriber")?;
+
+    let body = UnsubscribedPage
+        .render()
+        .context("Failed to render the unsubscribed page")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "Get subscriber_id from unsubscribe token", skip(token, pool))]
+async fn get_subscriber_id_from_unsubscribe_token(
+    token: &SubscriptionToken,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE unsubscribe_token = $1"#,
+        token.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result.map(|r| r.id))
+}
+
+#[tracing::instrument(name = "Mark a subscriber as unsubscribed", skip(token, trans))]
+async fn mark_subscriber_as_unsubscribed(
+    token: &SubscriptionToken,
+    trans: &mut Trans<'_>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE unsubscribe_token = $1 RETURNING id"#,
+        token.as_ref(),
+    )
+    .fetch_optional(trans)
+    .await?;
+
+    Ok(result.map(|r| r.id))
+}
+
+#[tracing::instrument(name = "Invalidate confirmation tokens", skip(sub_id, trans))]
+async fn invalidate_confirmation_tokens(
+    sub_id: &Uuid,
+    trans: &mut Trans<'_>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscription_tokens SET is_valid = false WHERE subscriber_id = $1"#,
+        sub_id
+    )
+    .execute(trans)
+    .await?;
+
+    Ok(())
+}
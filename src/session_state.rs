@@ -3,6 +3,8 @@ use std::future::{ready, Ready};
 use {
     actix_session::{Session, SessionExt},
     actix_web::{dev::Payload, FromRequest, HttpRequest},
+    chrono::{DateTime, TimeZone, Utc},
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
     uuid::Uuid,
 };
 
@@ -10,8 +12,16 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const LOGIN_TIME_KEY: &'static str = "login_time";
+    const LAST_ACTIVITY_KEY: &'static str = "last_activity";
+    const PENDING_USER_ID_KEY: &'static str = "pending_user_id";
+    const TOTP_ENROLMENT_KEY: &'static str = "totp_enrolment_secret";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
+    const SESSION_ID_KEY: &'static str = "session_id";
 
+    /// Change the session ID, and the CSRF token with it.
     pub fn renew(&self) {
+        self.0.remove(Self::CSRF_TOKEN_KEY);
         self.0.renew()
     }
 
@@ -23,6 +33,93 @@ impl TypedSession {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    pub fn insert_login_time(&self, login_time: DateTime<Utc>) -> Result<(), serde_json::Error> {
+        self.0
+            .insert(Self::LOGIN_TIME_KEY, login_time.timestamp_millis())
+    }
+
+    pub fn get_login_time(&self) -> Result<Option<DateTime<Utc>>, serde_json::Error> {
+        Ok(self
+            .0
+            .get(Self::LOGIN_TIME_KEY)?
+            .map(|millis| Utc.timestamp_millis(millis)))
+    }
+
+    /// The ID the session is known by in `authentication::SessionRegistry`.
+    pub fn insert_session_id(&self, session_id: &Uuid) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    pub fn insert_last_activity(&self, at: DateTime<Utc>) -> Result<(), serde_json::Error> {
+        self.0
+            .insert(Self::LAST_ACTIVITY_KEY, at.timestamp_millis())
+    }
+
+    pub fn get_last_activity(&self) -> Result<Option<DateTime<Utc>>, serde_json::Error> {
+        Ok(self
+            .0
+            .get(Self::LAST_ACTIVITY_KEY)?
+            .map(|millis| Utc.timestamp_millis(millis)))
+    }
+
+    /// Forget who is logged in, keeping the rest of the session, e.g. flash messages.
+    pub fn remove_login(&self) {
+        self.0.remove(Self::USER_ID_KEY);
+        self.0.remove(Self::LOGIN_TIME_KEY);
+        self.0.remove(Self::LAST_ACTIVITY_KEY);
+        self.0.remove(Self::SESSION_ID_KEY);
+    }
+
+    /// Remember a user who got their password right but still has to give their second factor.
+    pub fn insert_pending_user_id(&self, user_id: &Uuid) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_user_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::PENDING_USER_ID_KEY)
+    }
+
+    pub fn remove_pending_user_id(&self) {
+        self.0.remove(Self::PENDING_USER_ID_KEY);
+    }
+
+    /// A TOTP secret that has been shown to the user but not yet confirmed, base32-encoded.
+    pub fn insert_totp_enrolment_secret(&self, secret: &str) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::TOTP_ENROLMENT_KEY, secret)
+    }
+
+    pub fn get_totp_enrolment_secret(&self) -> Result<Option<String>, serde_json::Error> {
+        self.0.get(Self::TOTP_ENROLMENT_KEY)
+    }
+
+    pub fn remove_totp_enrolment_secret(&self) {
+        self.0.remove(Self::TOTP_ENROLMENT_KEY);
+    }
+
+    /// The token that forms have to send back, see `authentication::reject_forged_requests`.
+    /// It is created when the user logs in, or by the first form rendered before that.
+    pub fn csrf_token(&self) -> Result<String, serde_json::Error> {
+        if let Some(token) = self.get_csrf_token()? {
+            return Ok(token);
+        }
+
+        let token: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .map(char::from)
+            .take(32)
+            .collect();
+        self.0.insert(Self::CSRF_TOKEN_KEY, &token)?;
+        Ok(token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, serde_json::Error> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
     pub fn log_out(self) {
         self.0.purge()
     }
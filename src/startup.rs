@@ -1,18 +1,28 @@
 use crate::{
-    authentication::reject_anonymous_users,
+    authentication::{
+        reject_anonymous_users, reject_forged_requests, reject_invalid_api_tokens, require_editor,
+        require_owner, PasswordHashingParams, SessionRegistry, SessionTimeouts,
+    },
     configuration::{DatabaseSettings, Settings},
-    email_client::EmailClient,
+    email_client::EmailSender,
+    rate_limit::{limit_password_reset_requests, limit_subscription_requests, RateLimiter},
     routes,
 };
 
-use std::net::TcpListener;
+use std::net::{IpAddr, TcpListener};
 
 use {
-    actix_session::{storage::RedisSessionStore, SessionMiddleware},
-    actix_web::{cookie::Key, dev::Server, web, App, HttpServer},
+    actix_session::{storage::RedisSessionStore, SessionLength, SessionMiddleware},
+    actix_web::{
+        cookie::{time, Key},
+        dev::Server,
+        guard, web, App, HttpServer,
+    },
     actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework},
     actix_web_lab::middleware,
     anyhow::Result,
+    chrono_tz::Tz,
+    ipnet::IpNet,
     secrecy::{ExposeSecret, Secret},
     sqlx::{postgres::PgPoolOptions, PgPool},
     tracing_actix_web::TracingLogger,
@@ -27,34 +37,13 @@ impl Application {
     pub async fn build(configuration: Settings) -> Result<Self> {
         let connection_pool = get_connection_pool(&configuration.database);
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        )
-        .unwrap();
-
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port,
         );
         let listener = std::net::TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
-        let server = run(
-            listener,
-            connection_pool,
-            email_client,
-            configuration.application.base_url,
-            HmacSecret(configuration.application.hmac_secret),
-            configuration.redis_uri,
-        )
-        .await?;
+        let server = run(listener, connection_pool, configuration).await?;
 
         Ok(Self { port, server })
     }
@@ -76,17 +65,51 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 
 pub struct ApplicationBaseUrl(pub String);
 
-async fn run(
-    listener: TcpListener,
-    pool: PgPool,
-    email_client: EmailClient,
-    base_url: String,
-    hmac_secret: HmacSecret,
-    redis_uri: Secret<String>,
-) -> Result<Server> {
+pub struct ConfirmationTokenExpiry(pub chrono::Duration);
+
+pub struct InvitationExpiry(pub chrono::Duration);
+
+pub struct TrustedProxies(pub Vec<IpNet>);
+
+pub struct SendTimeZone(pub Tz);
+
+impl TrustedProxies {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+}
+
+async fn run(listener: TcpListener, pool: PgPool, settings: Settings) -> Result<Server> {
+    let Settings {
+        application: configuration,
+        email_client,
+        login_throttle,
+        password_hashing,
+        password_policy,
+        subscription_rate_limit,
+        password_reset_rate_limit,
+        redis_uri,
+        ..
+    } = settings;
     let pool = web::Data::new(pool);
-    let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let email_client: web::Data<dyn EmailSender> = web::Data::from(email_client.client());
+    let confirmation_token_expiry = web::Data::new(ConfirmationTokenExpiry(
+        configuration.confirmation_token_expiry(),
+    ));
+    let invitation_expiry = web::Data::new(InvitationExpiry(configuration.invitation_expiry()));
+    let session_timeouts = web::Data::new(SessionTimeouts {
+        idle: configuration.session_idle_timeout(),
+        absolute: configuration.session_absolute_timeout(),
+    });
+    let base_url = web::Data::new(ApplicationBaseUrl(configuration.base_url));
+    let trusted_proxies = web::Data::new(TrustedProxies(configuration.trusted_proxies));
+    let send_time_zone = web::Data::new(SendTimeZone(configuration.timezone));
+    let hmac_secret = HmacSecret(configuration.hmac_secret);
+    let login_throttle = web::Data::new(login_throttle);
+    let password_hashing_params = web::Data::new(PasswordHashingParams::new(&password_hashing)?);
+    let password_policy = web::Data::new(password_policy.policy()?);
+    let subscription_rate_limit = web::Data::new(subscription_rate_limit);
+    let password_reset_rate_limit = web::Data::new(password_reset_rate_limit);
 
     let secret_key = Key::from(hmac_secret.0.expose_secret().as_bytes());
 
@@ -94,34 +117,202 @@ async fn run(
     let message_framework = FlashMessagesFramework::builder(message_store).build();
 
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let rate_limiter = web::Data::new(RateLimiter::new(&redis_uri).await?);
+    let session_registry =
+        web::Data::new(SessionRegistry::new(&redis_uri, session_timeouts.idle).await?);
+    // Idle sessions are kept until their absolute timeout, so that `reject_anonymous_users` can
+    // still tell their users why they have been logged out
+    let session_length = SessionLength::BrowserSession {
+        state_ttl: Some(time::Duration::seconds(
+            session_timeouts.absolute.num_seconds(),
+        )),
+    };
 
     let server = HttpServer::new(move || {
         App::new()
             .wrap(message_framework.clone())
             .wrap(TracingLogger::default())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
+            .wrap(
+                SessionMiddleware::builder(redis_store.clone(), secret_key.clone())
+                    .session_length(session_length.clone())
+                    .build(),
+            )
             .route("/health_check", web::get().to(routes::health_check))
-            .route("/subscriptions", web::post().to(routes::subscribe))
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(middleware::from_fn(limit_subscription_requests))
+                    .route(web::post().to(routes::subscribe)),
+            )
             .route("/subscriptions/confirm", web::get().to(routes::confirm))
+            .service(
+                web::resource("/subscriptions/confirm/resend")
+                    .wrap(middleware::from_fn(limit_subscription_requests))
+                    .route(web::post().to(routes::resend_confirmation)),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(routes::unsubscribe_form),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::post().to(routes::unsubscribe),
+            )
             .route("/", web::get().to(routes::home))
-            .route("/login", web::get().to(routes::login_form))
-            .route("/login", web::post().to(routes::login))
+            .service(
+                web::resource("/login")
+                    .wrap(middleware::from_fn(reject_forged_requests))
+                    .route(web::get().to(routes::login_form))
+                    .route(web::post().to(routes::login)),
+            )
+            .service(
+                web::resource("/login/two-factor")
+                    .wrap(middleware::from_fn(reject_forged_requests))
+                    .route(web::get().to(routes::second_factor_form))
+                    .route(web::post().to(routes::second_factor)),
+            )
+            .route("/login/forgot", web::get().to(routes::forgot_password_form))
+            .service(
+                web::resource("/login/forgot")
+                    .guard(guard::Post())
+                    .wrap(middleware::from_fn(limit_password_reset_requests))
+                    .route(web::post().to(routes::forgot_password)),
+            )
+            .route("/login/reset", web::get().to(routes::reset_password_form))
+            .route("/login/reset", web::post().to(routes::reset_password))
+            .route(
+                "/invitations/accept",
+                web::get().to(routes::accept_invitation_form),
+            )
+            .route(
+                "/invitations/accept",
+                web::post().to(routes::accept_invitation),
+            )
             .service(
                 web::scope("/admin")
+                    // Anonymous users are sent to the login page before their token is checked
+                    .wrap(middleware::from_fn(reject_forged_requests))
                     .wrap(middleware::from_fn(reject_anonymous_users))
                     .route("/dashboard", web::get().to(routes::admin_dashboard))
                     .route("/password", web::get().to(routes::change_password_form))
                     .route("/password", web::post().to(routes::change_password))
+                    .route("/two-factor", web::get().to(routes::two_factor_page))
+                    .route("/two-factor", web::post().to(routes::enable_two_factor))
                     .route("/logout", web::post().to(routes::log_out))
-                    .route("/newsletters", web::get().to(routes::get_newsletter_page))
-                    .route("/newsletters", web::post().to(routes::publish_newsletter)),
+                    .route("/api-tokens", web::get().to(routes::api_tokens_page))
+                    .route("/api-tokens", web::post().to(routes::create_api_token))
+                    .route(
+                        "/api-tokens/{api_token_id}/revoke",
+                        web::post().to(routes::revoke_api_token),
+                    )
+                    .route("/sessions", web::get().to(routes::sessions_page))
+                    .route(
+                        "/sessions/revoke-all",
+                        web::post().to(routes::sign_out_everywhere),
+                    )
+                    .route(
+                        "/sessions/{session_id}/revoke",
+                        web::post().to(routes::revoke_session),
+                    )
+                    // Viewers can see what has been and will be sent, they are matched before
+                    // the editors' scope below
+                    .route(
+                        "/newsletters/history",
+                        web::get().to(routes::newsletter_history_page),
+                    )
+                    .route(
+                        "/newsletters/scheduled",
+                        web::get().to(routes::scheduled_issues_page),
+                    )
+                    .route(
+                        "/newsletters/failed",
+                        web::get().to(routes::failed_deliveries_page),
+                    )
+                    .route(
+                        "/newsletters/issues/{newsletter_issue_id}/preview",
+                        web::get().to(routes::preview_newsletter_issue),
+                    )
+                    .service(
+                        web::scope("/newsletters")
+                            .wrap(middleware::from_fn(require_editor))
+                            .route("", web::get().to(routes::get_newsletter_page))
+                            .route("", web::post().to(routes::publish_newsletter))
+                            .route("/failed", web::post().to(routes::requeue_failed_delivery))
+                            .route("/drafts", web::post().to(routes::create_draft))
+                            .route(
+                                "/drafts/{newsletter_issue_id}",
+                                web::get().to(routes::edit_draft_page),
+                            )
+                            .route(
+                                "/drafts/{newsletter_issue_id}",
+                                web::post().to(routes::update_draft),
+                            )
+                            .route(
+                                "/drafts/{newsletter_issue_id}/send",
+                                web::post().to(routes::send_draft),
+                            )
+                            .route(
+                                "/drafts/{newsletter_issue_id}/schedule",
+                                web::post().to(routes::schedule_draft),
+                            )
+                            .route(
+                                "/scheduled/{newsletter_issue_id}/reschedule",
+                                web::post().to(routes::reschedule_issue),
+                            )
+                            .route(
+                                "/scheduled/{newsletter_issue_id}/cancel",
+                                web::post().to(routes::cancel_scheduled_issue),
+                            )
+                            .route(
+                                "/preview",
+                                web::post().to(routes::preview_newsletter_content),
+                            ),
+                    )
+                    .service(
+                        web::scope("/audit")
+                            .wrap(middleware::from_fn(require_owner))
+                            .route("", web::get().to(routes::audit_log_page))
+                            .route("/export", web::get().to(routes::export_audit_log)),
+                    )
+                    .service(
+                        web::scope("/users")
+                            .wrap(middleware::from_fn(require_owner))
+                            .route("", web::get().to(routes::users_page))
+                            .route("", web::post().to(routes::invite_user))
+                            .route("/{user_id}/role", web::post().to(routes::change_user_role))
+                            .route(
+                                "/{user_id}/deactivate",
+                                web::post().to(routes::deactivate_user),
+                            )
+                            .route(
+                                "/{user_id}/reactivate",
+                                web::post().to(routes::reactivate_user),
+                            ),
+                    ),
+            )
+            .service(
+                web::scope("/api/v1")
+                    .wrap(middleware::from_fn(reject_invalid_api_tokens))
+                    .route("/newsletters", web::get().to(routes::api_list_newsletters))
+                    .route(
+                        "/newsletters",
+                        web::post().to(routes::api_publish_newsletter),
+                    ),
             )
             .app_data(pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(send_time_zone.clone())
+            .app_data(confirmation_token_expiry.clone())
+            .app_data(invitation_expiry.clone())
+            .app_data(login_throttle.clone())
+            .app_data(password_hashing_params.clone())
+            .app_data(password_policy.clone())
+            .app_data(subscription_rate_limit.clone())
+            .app_data(password_reset_rate_limit.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(session_registry.clone())
+            .app_data(session_timeouts.clone())
             .app_data(web::Data::new(hmac_secret.clone()))
     })
     .listen(listener)?
@@ -0,0 +1,78 @@
+use crate::{configuration::Settings, startup::get_connection_pool};
+
+use std::time::Duration;
+
+use {
+    anyhow::{Context, Result},
+    chrono::Utc,
+    sqlx::PgPool,
+};
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub async fn run_cleanup_until_stopped(configuration: Settings) -> Result<()> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let retention = configuration
+        .application
+        .unconfirmed_subscription_retention();
+    cleanup_loop(connection_pool, retention).await
+}
+
+async fn cleanup_loop(pool: PgPool, retention: chrono::Duration) -> Result<()> {
+    loop {
+        // Failures are logged by the instrumentation, the next run will try again
+        let _ = delete_unconfirmed_subscriptions(&pool, retention).await;
+        tokio::time::sleep(CLEANUP_INTERVAL).await;
+    }
+}
+
+/// Delete subscriptions that are still pending confirmation after `retention`, along with
+/// their confirmation tokens.
+///
+/// Returns the number of deleted subscriptions.
+#[tracing::instrument(skip(pool), fields(n_deleted = tracing::field::Empty), err)]
+pub async fn delete_unconfirmed_subscriptions(
+    pool: &PgPool,
+    retention: chrono::Duration,
+) -> Result<u64> {
+    let cutoff = Utc::now() - retention;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from database pool")?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE subscriber_id IN (
+            SELECT id FROM subscriptions
+            WHERE status = 'pending_confirmation' AND subscribed_at < $1
+        )
+        "#,
+        cutoff,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete tokens of unconfirmed subscriptions")?;
+
+    let n_deleted = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions
+        WHERE status = 'pending_confirmation' AND subscribed_at < $1
+        "#,
+        cutoff,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete unconfirmed subscriptions")?
+    .rows_affected();
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction to delete unconfirmed subscriptions")?;
+
+    tracing::Span::current().record("n_deleted", &n_deleted);
+    Ok(n_deleted)
+}
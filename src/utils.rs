@@ -1,4 +1,9 @@
-use actix_web::{http::header, HttpResponse};
+use crate::startup::TrustedProxies;
+
+use {
+    actix_web::{dev::ServiceRequest, http::header, web, FromRequest, HttpRequest, HttpResponse},
+    askama::Template,
+};
 
 pub fn e500<T>(e: T) -> actix_web::Error
 where
@@ -7,8 +12,140 @@ where
     actix_web::error::ErrorInternalServerError(e)
 }
 
+pub fn e400<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorBadRequest(e)
+}
+
 pub fn see_other(location: &str) -> HttpResponse {
     HttpResponse::SeeOther()
         .insert_header((header::LOCATION, location))
         .finish()
 }
+
+/// Render a page from its template, see `templates/layout.html` for what they have in common.
+pub fn render_page(page: &impl Template) -> Result<HttpResponse, actix_web::Error> {
+    let body = page.render().map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(header::ContentType::html())
+        .body(body))
+}
+
+/// The client's IP address.
+///
+/// This is the address of whoever connected to us, unless that is one of the `TrustedProxies`.
+/// Then `X-Forwarded-For` is read from the right, past any other trusted proxy, up to the first
+/// address that none of them vouches for. Anything further left was sent by the client and
+/// could be made up.
+pub fn client_ip(request: &HttpRequest) -> String {
+    let peer_ip = match request.peer_addr() {
+        Some(addr) => addr.ip(),
+        None => return "unknown".to_owned(),
+    };
+    let trusted_proxies = match request.app_data::<web::Data<TrustedProxies>>() {
+        Some(trusted_proxies) => trusted_proxies,
+        None => return peer_ip.to_string(),
+    };
+
+    let forwarded_for: Vec<_> = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(str::trim)
+        .collect();
+    let mut client_ip = peer_ip;
+    for hop in forwarded_for.into_iter().rev() {
+        if !trusted_proxies.contains(&client_ip) {
+            break;
+        }
+        match hop.parse() {
+            Ok(ip) => client_ip = ip,
+            Err(_) => break,
+        }
+    }
+
+    client_ip.to_string()
+}
+
+/// Read the whole body of a request in a middleware, putting it back for the handler.
+pub async fn peek_body(req: &mut ServiceRequest) -> Result<web::Bytes, actix_web::Error> {
+    let body = {
+        let (http_request, payload) = req.parts_mut();
+        web::Bytes::from_request(http_request, payload).await?
+    };
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body.clone());
+    req.set_payload(payload.into());
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::client_ip;
+    use crate::startup::TrustedProxies;
+    use actix_web::{test::TestRequest, web, HttpRequest};
+
+    fn request(peer: &str, forwarded_for: Option<&str>, trusted_proxies: &[&str]) -> HttpRequest {
+        let mut request = TestRequest::default().peer_addr(peer.parse().unwrap());
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.insert_header(("X-Forwarded-For", forwarded_for));
+        }
+        let trusted_proxies = trusted_proxies.iter().map(|p| p.parse().unwrap()).collect();
+        request
+            .app_data(web::Data::new(TrustedProxies(trusted_proxies)))
+            .to_http_request()
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_without_trusted_proxies() {
+        let request = request("203.0.113.7:4000", Some("198.51.100.1"), &[]);
+        assert_eq!(client_ip(&request), "203.0.113.7");
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let request = request("203.0.113.7:4000", Some("198.51.100.1"), &["10.0.0.0/8"]);
+        assert_eq!(client_ip(&request), "203.0.113.7");
+    }
+
+    #[test]
+    fn trusted_proxies_report_the_client() {
+        let request = request("10.0.0.2:4000", Some("198.51.100.1"), &["10.0.0.0/8"]);
+        assert_eq!(client_ip(&request), "198.51.100.1");
+    }
+
+    #[test]
+    fn addresses_added_by_the_client_are_ignored() {
+        // The client sent `192.0.2.1` itself, the proxy appended the address it saw
+        let request = request(
+            "10.0.0.2:4000",
+            Some("192.0.2.1, 198.51.100.1"),
+            &["10.0.0.0/8"],
+        );
+        assert_eq!(client_ip(&request), "198.51.100.1");
+    }
+
+    #[test]
+    fn chains_of_trusted_proxies_are_followed() {
+        let request = request(
+            "10.0.0.2:4000",
+            Some("192.0.2.1, 198.51.100.1, 10.0.0.3"),
+            &["10.0.0.0/8"],
+        );
+        assert_eq!(client_ip(&request), "198.51.100.1");
+    }
+
+    #[test]
+    fn malformed_hops_stop_the_search() {
+        let request = request(
+            "10.0.0.2:4000",
+            Some("198.51.100.1, nonsense"),
+            &["10.0.0.0/8"],
+        );
+        assert_eq!(client_ip(&request), "10.0.0.2");
+    }
+}
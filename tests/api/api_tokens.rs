@@ -0,0 +1,329 @@
+use crate::helpers::{assert_is_redirected_to, spawn_app};
+
+use {
+    sha2::{Digest, Sha256},
+    uuid::Uuid,
+};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Release notes",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    })
+}
+
+#[tokio::test]
+async fn tokens_are_shown_once_and_only_their_digest_is_stored() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    assert!(token.starts_with("zp_"));
+
+    let saved = sqlx::query!("SELECT name, token_hash, scopes FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Release notes");
+    assert_eq!(
+        saved.token_hash,
+        hex::encode(Sha256::digest(token.as_bytes()))
+    );
+    assert_eq!(saved.scopes, vec!["newsletters:publish".to_string()]);
+
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("Release notes"));
+    assert!(html_page.contains("newsletters:publish"));
+    assert!(!html_page.contains(&token));
+}
+
+#[tokio::test]
+async fn api_requests_without_a_valid_token_are_rejected() {
+    let app = spawn_app().await;
+    // A browser session is not enough
+    app.login_test_user().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/api/v1/newsletters", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+
+    for token in ["not-a-token", &format!("zp_{}", "a".repeat(40))] {
+        let response = app.get_api_newsletters(token).await;
+        assert_eq!(response.status().as_u16(), 401, "{}", token);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "The bearer token is not valid");
+    }
+}
+
+#[tokio::test]
+async fn newsletters_can_be_published_with_a_token() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let token = app
+        .create_api_token(&["newsletters:read", "newsletters:publish"])
+        .await;
+
+    let response = app
+        .post_api_newsletters(&token, &newsletter_request_body())
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap().to_owned();
+
+    let response = app.get_api_newsletters(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issues = body["issues"].as_array().unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0]["newsletter_issue_id"], issue_id.as_str());
+    assert_eq!(issues[0]["title"], "Release notes");
+}
+
+#[tokio::test]
+async fn api_issues_can_be_written_in_markdown() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+
+    let body = serde_json::json!({
+        "title": "Release notes",
+        "markdown_content": "See [the changelog](https://example.com/changelog).",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response = app.post_api_newsletters(&token, &body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let issue =
+        sqlx::query!("SELECT markdown_content, html_content, text_content FROM newsletter_issues")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        issue.markdown_content.as_deref(),
+        Some("See [the changelog](https://example.com/changelog).")
+    );
+    assert!(issue
+        .html_content
+        .contains("<a href=\"https://example.com/changelog\""));
+    assert_eq!(
+        issue.text_content,
+        "See the changelog [1].\n\n[1] https://example.com/changelog"
+    );
+
+    let body = serde_json::json!({
+        "title": "Release notes",
+        "html_content": "<p>Only HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response = app.post_api_newsletters(&token, &body).await;
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"],
+        "Please write the issue in Markdown, or provide both its HTML and text versions."
+    );
+}
+
+#[tokio::test]
+async fn api_publishing_is_idempotent() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let token = app.create_api_token(&["newsletters:publish"]).await;
+    let body = newsletter_request_body();
+
+    let first: serde_json::Value = app
+        .post_api_newsletters(&token, &body)
+        .await
+        .json()
+        .await
+        .unwrap();
+    let response = app.post_api_newsletters(&token, &body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let second: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(first, second);
+
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 1);
+}
+
+#[tokio::test]
+async fn tokens_can_only_do_what_their_scopes_allow() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let token = app.create_api_token(&["newsletters:read"]).await;
+
+    let response = app
+        .post_api_newsletters(&token, &newsletter_request_body())
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"],
+        "The token does not have the newsletters:publish scope"
+    );
+
+    let response = app.get_api_newsletters(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn token_use_is_recorded() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let token = app.create_api_token(&["newsletters:read"]).await;
+    assert!(app.get_api_tokens_html().await.contains("Never"));
+
+    app.get_api_newsletters(&token).await;
+
+    let last_used_at = sqlx::query!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .last_used_at;
+    assert!(last_used_at.is_some());
+    assert!(!app.get_api_tokens_html().await.contains("Never"));
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let token = app.create_api_token(&["newsletters:read"]).await;
+    let api_token_id = sqlx::query!("SELECT api_token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .api_token_id;
+
+    let response = app
+        .post_api_tokens(&format!("/{}/revoke", api_token_id), &serde_json::json!({}))
+        .await;
+    assert_is_redirected_to(&response, "/admin/api-tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>The token has been revoked.</i></p>"));
+    assert!(!html_page.contains("Release notes"));
+
+    let response = app.get_api_newsletters(&token).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn users_cannot_revoke_tokens_that_are_not_theirs() {
+    let app = spawn_app().await;
+    let editor = app.create_user("editor").await;
+    app.login(&editor).await;
+    let token = app.create_api_token(&["newsletters:read"]).await;
+    let api_token_id = sqlx::query!("SELECT api_token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .api_token_id;
+
+    app.login_test_user().await;
+    app.post_api_tokens(&format!("/{}/revoke", api_token_id), &serde_json::json!({}))
+        .await;
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>That token does not exist.</i></p>"));
+
+    let response = app.get_api_newsletters(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn tokens_lose_their_scopes_along_with_their_owner() {
+    let app = spawn_app().await;
+    let editor = app.create_user("editor").await;
+    app.login(&editor).await;
+    let token = app.create_api_token(&["newsletters:read"]).await;
+
+    sqlx::query!(
+        "UPDATE users SET role = 'viewer' WHERE user_id = $1",
+        editor.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = app.get_api_newsletters(&token).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    sqlx::query!(
+        "UPDATE users SET is_active = false WHERE user_id = $1",
+        editor.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = app.get_api_newsletters(&token).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn tokens_are_checked_against_the_creators_role_and_input() {
+    let app = spawn_app().await;
+    let viewer = app.create_user("viewer").await;
+    app.login(&viewer).await;
+
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("Your role does not allow creating API tokens."));
+
+    let cases = [
+        (
+            serde_json::json!({ "name": "CI", "newsletters:publish": "on" }),
+            "Your role does not allow the newsletters:publish scope.",
+        ),
+        (
+            serde_json::json!({ "name": " ", "newsletters:read": "on" }),
+            "Please give the token a name.",
+        ),
+        (
+            serde_json::json!({ "name": "CI" }),
+            "Please choose at least one scope.",
+        ),
+    ];
+    for (body, message) in cases {
+        let response = app.post_api_tokens("", &body).await;
+        assert_is_redirected_to(&response, "/admin/api-tokens");
+        let html_page = app.get_api_tokens_html().await;
+        assert!(
+            html_page.contains(&format!("<p><i>{}</i></p>", message)),
+            "{}",
+            message
+        );
+    }
+
+    let n_tokens = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM api_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn token_names_are_escaped() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let name = r#"<script>alert("CI")</script>"#;
+    let escaped = "&lt;script&gt;alert(&quot;CI&quot;)&lt;/script&gt;";
+
+    let body = serde_json::json!({ "name": name, "newsletters:read": "on" });
+    let response = app.post_api_tokens("", &body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(escaped));
+    assert!(!html_page.contains("<script>"));
+
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains(escaped));
+    assert!(!html_page.contains("<script>"));
+}
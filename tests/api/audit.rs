@@ -0,0 +1,276 @@
+use crate::helpers::{assert_is_redirected_to, spawn_app, spawn_app_with, TestApp};
+
+use {
+    chrono::{Duration, Utc},
+    uuid::Uuid,
+    wiremock::{
+        matchers::{method, path},
+        Mock, ResponseTemplate,
+    },
+};
+
+struct RecordedEvent {
+    action: String,
+    user_id: Option<Uuid>,
+    details: serde_json::Value,
+}
+
+async fn recorded_events(app: &TestApp) -> Vec<RecordedEvent> {
+    sqlx::query_as!(
+        RecordedEvent,
+        "SELECT action, user_id, details FROM audit_events ORDER BY occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+/// Store `n` events directly, a second apart, the last one being the most recent.
+async fn insert_events(app: &TestApp, action: &str, n: i64) {
+    for i in 0..n {
+        sqlx::query!(
+            r#"
+            INSERT INTO audit_events (
+                audit_event_id, occurred_at, user_id, action, client_ip, details
+            )
+            VALUES ($1, $2, $3, $4, '127.0.0.1', $5)
+            "#,
+            Uuid::new_v4(),
+            Utc::now() - Duration::seconds(n - i),
+            app.test_user.user_id,
+            action,
+            serde_json::json!({ "n": i }),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn logins_and_logouts_are_audited() {
+    let app = spawn_app().await;
+    let wrong_password = Uuid::new_v4().to_string();
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &wrong_password,
+    }))
+    .await;
+    app.login_test_user().await;
+    app.post_logout().await;
+
+    let events = recorded_events(&app).await;
+    let actions: Vec<_> = events.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions, ["login_failed", "login_succeeded", "logout"]);
+    assert_eq!(events[0].user_id, None);
+    assert_eq!(events[0].details["reason"], "invalid_credentials");
+    assert_eq!(events[1].user_id, Some(app.test_user.user_id));
+    assert_eq!(events[2].user_id, Some(app.test_user.user_id));
+
+    // Secrets never make it to the log
+    for event in &events {
+        let details = event.details.to_string();
+        assert!(!details.contains(&wrong_password));
+        assert!(!details.contains(&app.test_user.password));
+    }
+}
+
+/// Fail to log in with a request claiming to be forwarded for 203.0.113.7, returning the
+/// address that was audited.
+async fn audited_address_of_forwarded_login(app: &TestApp) -> String {
+    let body = app
+        .with_csrf_token(
+            "/login",
+            &serde_json::json!({
+                "username": &app.test_user.username,
+                "password": "wrong-password",
+            }),
+        )
+        .await;
+    app.api_client
+        .post(format!("{}/login", &app.address))
+        .header("X-Forwarded-For", "203.0.113.7")
+        .form(&body)
+        .send()
+        .await
+        .expect("Failed to execute login request");
+
+    sqlx::query!("SELECT client_ip FROM audit_events WHERE action = 'login_failed'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .client_ip
+}
+
+#[tokio::test]
+async fn the_audited_address_cannot_be_forged() {
+    let app = spawn_app().await;
+    assert_eq!(audited_address_of_forwarded_login(&app).await, "127.0.0.1");
+}
+
+#[tokio::test]
+async fn the_audited_address_is_taken_from_trusted_proxies() {
+    let app = spawn_app_with(|c| {
+        c.application.trusted_proxies = vec!["127.0.0.1/32".parse().unwrap()];
+    })
+    .await;
+    assert_eq!(
+        audited_address_of_forwarded_login(&app).await,
+        "203.0.113.7"
+    );
+}
+
+#[tokio::test]
+async fn password_changes_and_publishing_are_audited() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let new_password = Uuid::new_v4().to_string();
+    app.post_change_password(&serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    }))
+    .await;
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter text body",
+            "html_content": "<p>Newsletter HTML body</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirected_to(&response, "/admin/newsletters");
+
+    let events = recorded_events(&app).await;
+    let password_changed = events
+        .iter()
+        .find(|e| e.action == "password_changed")
+        .unwrap();
+    assert_eq!(password_changed.user_id, Some(app.test_user.user_id));
+    assert_eq!(password_changed.details["method"], "change");
+    assert!(!password_changed.details.to_string().contains(&new_password));
+
+    let published = events
+        .iter()
+        .find(|e| e.action == "newsletter_published")
+        .unwrap();
+    assert_eq!(published.user_id, Some(app.test_user.user_id));
+    assert_eq!(published.details["title"], "Newsletter title");
+}
+
+#[tokio::test]
+async fn subscriber_changes_are_audited_without_their_address() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let events = recorded_events(&app).await;
+    let actions: Vec<_> = events.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions, ["subscriber_added", "subscriber_confirmed"]);
+    for event in &events {
+        assert_eq!(event.user_id, None);
+        assert_eq!(event.details["subscriber_id"], subscriber_id.to_string());
+        assert!(!event.details.to_string().contains("ursula_le_guin"));
+    }
+}
+
+#[tokio::test]
+async fn the_audit_log_is_paginated_and_filtered() {
+    let app = spawn_app().await;
+    insert_events(&app, "password_changed", 30).await;
+    insert_events(&app, "newsletter_published", 1).await;
+    app.login_test_user().await;
+
+    let html_page = app.get_audit_log_html("?action=password_changed").await;
+    assert_eq!(html_page.matches("<td>password_changed</td>").count(), 25);
+    assert!(!html_page.contains("<td>newsletter_published</td>"));
+    assert!(html_page.contains(r#"href="/admin/audit?page=2&amp;action=password_changed""#));
+    // Newest first
+    assert!(html_page.contains(":29}"));
+    assert!(!html_page.contains(":4}"));
+
+    let html_page = app
+        .get_audit_log_html("?page=2&action=password_changed")
+        .await;
+    assert_eq!(html_page.matches("<td>password_changed</td>").count(), 5);
+    assert!(html_page.contains(r#"href="/admin/audit?page=1&amp;action=password_changed""#));
+    assert!(!html_page.contains("page=3"));
+
+    let html_page = app
+        .get_audit_log_html(&format!("?user_id={}", Uuid::new_v4()))
+        .await;
+    assert!(html_page.contains("No events match."));
+
+    let response = app.get_audit_log("?action=not_an_action").await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app.get_audit_log(&format!("?page={}", i64::MAX / 10)).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_exported_as_json() {
+    let app = spawn_app().await;
+    insert_events(&app, "password_changed", 3).await;
+    app.login_test_user().await;
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/audit/export?action=login_succeeded",
+            &app.address
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let body: serde_json::Value = response.json().await.unwrap();
+    let events = body["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["action"], "login_succeeded");
+    assert_eq!(events[0]["username"], app.test_user.username.as_str());
+    assert_eq!(events[0]["client_ip"], "127.0.0.1");
+}
+
+#[tokio::test]
+async fn only_owners_can_see_the_audit_log() {
+    let app = spawn_app().await;
+    let editor = app.create_user("editor").await;
+    app.login(&editor).await;
+
+    let response = app.get_audit_log("").await;
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app
+        .api_client
+        .get(format!("{}/admin/audit/export", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(!app.get_admin_dashboard_html().await.contains("Audit log"));
+
+    app.login_test_user().await;
+    assert!(app.get_admin_dashboard_html().await.contains("Audit log"));
+}
@@ -74,6 +74,41 @@ async fn current_password_must_be_valid() {
     assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
 }
 
+#[tokio::test]
+async fn wrong_current_passwords_count_towards_the_login_lockout() {
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+    app.login_test_user().await;
+
+    for _ in 0..5 {
+        let body = serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        });
+        let response = app.post_change_password(&body).await;
+        assert_is_redirected_to(&response, "/admin/password");
+    }
+
+    // Even the right password is turned away until the lockout is over
+    let body = serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    });
+    let response = app.post_change_password(&body).await;
+    assert_is_redirected_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page
+        .contains("<p><i>Too many failed login attempts. Please try again in 15 minutes.</i></p>"));
+
+    // The lockout applies to logging in too
+    app.post_logout().await;
+    app.login_test_user().await;
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Too many failed login attempts."));
+}
+
 #[tokio::test]
 async fn new_password_must_be_at_least_12_chars() {
     let app = spawn_app().await;
@@ -184,3 +219,44 @@ async fn change_password_works() {
     let response = app.post_login(&login_body).await;
     assert_is_redirected_to(&response, "/admin/dashboard");
 }
+
+#[tokio::test]
+async fn new_password_must_pass_the_password_policy() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    for (new_password, message) in [
+        (
+            "qwertyuiop123".to_owned(),
+            "This password is too easy to guess. Avoid keyboard patterns like qwerty or asdf.",
+        ),
+        (
+            "correct horse battery staple".to_owned(),
+            "This password has appeared in a data breach, please choose another one.",
+        ),
+        (
+            format!("{}!x", app.test_user.username.to_uppercase()),
+            "The new password must not contain your username.",
+        ),
+    ] {
+        let body = serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        });
+        let response = app.post_change_password(&body).await;
+        assert_is_redirected_to(&response, "/admin/password");
+
+        let html_page = app.get_change_password_html().await;
+        assert!(
+            html_page.contains(&format!("<p><i>{}</i></p>", message)),
+            "{}",
+            new_password
+        );
+    }
+
+    // The password is unchanged
+    app.post_logout().await;
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+}
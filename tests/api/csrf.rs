@@ -0,0 +1,154 @@
+use crate::helpers::{assert_is_redirected_to, spawn_app, TestApp};
+
+use uuid::Uuid;
+
+const REJECTION_MESSAGE: &str =
+    "<p><i>Your form could not be verified, please reload the page and try again.</i></p>";
+
+async fn post_change_password_without_helper(
+    app: &TestApp,
+    csrf_token: Option<&str>,
+) -> reqwest::Response {
+    let new_password = Uuid::new_v4().to_string();
+    let mut body = serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    });
+    if let Some(token) = csrf_token {
+        body["csrf_token"] = token.into();
+    }
+
+    app.api_client
+        .post(format!("{}/admin/password", &app.address))
+        .form(&body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn admin_forms_embed_the_session_csrf_token() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    let token = app.get_csrf_token("/admin/dashboard").await.unwrap();
+    for page in ["/admin/password", "/admin/two-factor", "/admin/users"] {
+        assert_eq!(
+            app.get_csrf_token(page).await.as_deref(),
+            Some(token.as_str())
+        );
+    }
+}
+
+#[tokio::test]
+async fn forged_admin_submissions_are_rejected() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let real_token = app.get_csrf_token("/admin/dashboard").await.unwrap();
+
+    for token in [
+        None,
+        Some(""),
+        Some("not-the-token"),
+        Some(&real_token[1..]),
+    ] {
+        let response = post_change_password_without_helper(&app, token).await;
+        assert_eq!(response.status().as_u16(), 403, "{:?}", token);
+
+        let html_page = app.get_change_password_html().await;
+        assert!(html_page.contains(REJECTION_MESSAGE), "{:?}", token);
+    }
+
+    // The password wasn't changed
+    app.post_logout().await;
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn tokens_from_another_session_are_rejected() {
+    let app = spawn_app().await;
+    // Someone else's token, for example the attacker's own
+    app.login_test_user().await;
+    let other_token = app.get_csrf_token("/admin/dashboard").await.unwrap();
+    app.post_logout().await;
+
+    app.login_test_user().await;
+    let response = post_change_password_without_helper(&app, Some(&other_token)).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn the_csrf_token_can_be_sent_in_a_header() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let token = app.get_csrf_token("/admin/dashboard").await.unwrap();
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .header("X-CSRF-Token", token)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirected_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn forged_logouts_are_rejected() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    // Still logged in
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn forged_logins_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirected_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(REJECTION_MESSAGE));
+}
+
+#[tokio::test]
+async fn the_csrf_token_changes_on_login() {
+    let app = spawn_app().await;
+    let anonymous_token = app.get_csrf_token("/login").await.unwrap();
+
+    app.login_test_user().await;
+    let token = app.get_csrf_token("/admin/dashboard").await.unwrap();
+    assert_ne!(token, anonymous_token);
+}
+
+#[tokio::test]
+async fn anonymous_submissions_are_sent_to_the_login_page() {
+    let app = spawn_app().await;
+
+    let response = post_change_password_without_helper(&app, None).await;
+    assert_is_redirected_to(&response, "/login");
+}
@@ -1,10 +1,16 @@
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    configuration::{get_configuration, DatabaseSettings, Settings},
+    email_client::EmailSender,
     get_connection_pool,
+    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    newsletter_scheduler::send_due_issue,
+    password_reset_worker::try_send_reset_email,
     telemetry::{get_subscriber, init_subscriber},
     Application,
 };
 
+use std::sync::Arc;
+
 use {
     argon2::{password_hash::SaltString, Argon2, PasswordHasher},
     once_cell::sync::Lazy,
@@ -35,12 +41,50 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub test_user: TestUser,
     pub api_client: Client,
+    pub email_client: Arc<dyn EmailSender>,
+    pub password_reset_expiry: chrono::Duration,
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.db_pool, self.email_client.as_ref(), &self.address)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_password_reset_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_send_reset_email(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.address,
+                self.password_reset_expiry,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn send_due_issues(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = send_due_issue(&self.db_pool).await.unwrap() {
+                break;
+            }
+        }
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -48,11 +92,21 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_resend_confirmation(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/confirm/resend", &self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub async fn post_newsletters(&self, body: &serde_json::Value) -> reqwest::Response {
+        let body = self.with_csrf_token("/admin/dashboard", body).await;
         self.api_client
-            .post(&format!("{}/admin/newsletters", &self.address))
+            .post(format!("{}/admin/newsletters", &self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
-            .form(body)
+            .form(&body)
             .send()
             .await
             .expect("Failed to execute request")
@@ -60,7 +114,102 @@ impl TestApp {
 
     pub async fn get_newsletter_page(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/newsletters", &self.address))
+            .get(format!("{}/admin/newsletters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// `path` is relative to `/admin/newsletters/drafts`.
+    pub async fn post_drafts(&self, path: &str, body: &serde_json::Value) -> reqwest::Response {
+        let body = self.with_csrf_token("/admin/dashboard", body).await;
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts{}",
+                &self.address, path
+            ))
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletter_preview(&self, body: &serde_json::Value) -> reqwest::Response {
+        let body = self.with_csrf_token("/admin/dashboard", body).await;
+        self.api_client
+            .post(format!("{}/admin/newsletters/preview", &self.address))
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_draft(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/drafts/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_newsletter_history_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/history", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_scheduled_issues_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/scheduled", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `path` is relative to `/admin/newsletters/scheduled`.
+    pub async fn post_scheduled(&self, path: &str, body: &serde_json::Value) -> reqwest::Response {
+        let body = self.with_csrf_token("/admin/dashboard", body).await;
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/scheduled{}",
+                &self.address, path
+            ))
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_failed_deliveries_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/failed", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_requeue_failed_delivery<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        let body = self.with_csrf_token("/admin/dashboard", body).await;
+        self.api_client
+            .post(format!("{}/admin/newsletters/failed", &self.address))
+            .form(&body)
             .send()
             .await
             .expect("Failed to execute request")
@@ -70,25 +219,62 @@ impl TestApp {
     where
         Body: serde::Serialize,
     {
+        let body = self.with_csrf_token("/login", body).await;
         self.api_client
-            .post(&format!("{}/login", &self.address))
-            .form(body)
+            .post(format!("{}/login", &self.address))
+            .form(&body)
             .send()
             .await
             .expect("Failed to execute login request")
     }
 
     pub async fn login_test_user(&self) -> reqwest::Response {
+        self.login(&self.test_user).await
+    }
+
+    pub async fn login(&self, user: &TestUser) -> reqwest::Response {
         let login_body = serde_json::json!({
-            "username": &self.test_user.username,
-            "password": &self.test_user.password,
+            "username": &user.username,
+            "password": &user.password,
         });
         self.post_login(&login_body).await
     }
 
+    /// Store a new user with the given role, in addition to the test user.
+    pub async fn create_user(&self, role: &str) -> TestUser {
+        let user = TestUser::generate_with_role(role);
+        user.store(&self.db_pool).await;
+        user
+    }
+
+    pub async fn get_users_html(&self) -> String {
+        self.get_users().await.text().await.unwrap()
+    }
+
+    pub async fn get_users(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_users<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        let body = self.with_csrf_token("/admin/dashboard", body).await;
+        self.api_client
+            .post(format!("{}/admin/users{}", &self.address, path))
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -99,7 +285,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -111,7 +297,7 @@ impl TestApp {
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -124,22 +310,266 @@ impl TestApp {
     where
         Body: serde::Serialize,
     {
+        let body = self.with_csrf_token("/admin/dashboard", body).await;
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
-            .form(body)
+            .post(format!("{}/admin/password", &self.address))
+            .form(&body)
             .send()
             .await
             .expect("Failed to execute request")
     }
 
     pub async fn post_logout(&self) -> reqwest::Response {
+        let body = self
+            .with_csrf_token("/admin/dashboard", &serde_json::json!({}))
+            .await;
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
+            .form(&body)
             .send()
             .await
             .expect("Failed to execute logout request")
     }
 
+    pub async fn get_sessions_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_revoke_session(&self, session_id: &str) -> reqwest::Response {
+        let body = self
+            .with_csrf_token("/admin/dashboard", &serde_json::json!({}))
+            .await;
+        self.api_client
+            .post(format!(
+                "{}/admin/sessions/{}/revoke",
+                &self.address, session_id
+            ))
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_sign_out_everywhere(&self) -> reqwest::Response {
+        let body = self
+            .with_csrf_token("/admin/dashboard", &serde_json::json!({}))
+            .await;
+        self.api_client
+            .post(format!("{}/admin/sessions/revoke-all", &self.address))
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// `query` is the query string, e.g. `?action=logout`.
+    pub async fn get_audit_log(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_audit_log_html(&self, query: &str) -> String {
+        self.get_audit_log(query).await.text().await.unwrap()
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/api-tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_api_tokens<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        let body = self.with_csrf_token("/admin/dashboard", body).await;
+        self.api_client
+            .post(format!("{}/admin/api-tokens{}", &self.address, path))
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Create an API token for the logged in user, returning it.
+    pub async fn create_api_token(&self, scopes: &[&str]) -> String {
+        let mut body = serde_json::json!({ "name": "Release notes" });
+        for scope in scopes {
+            body[*scope] = "on".into();
+        }
+        let html = self.post_api_tokens("", &body).await.text().await.unwrap();
+
+        let start = html.find("<code>").expect("No token was created") + "<code>".len();
+        let end = start + html[start..].find("</code>").unwrap();
+        html[start..end].to_owned()
+    }
+
+    pub async fn get_api_newsletters(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/api/v1/newsletters", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_api_newsletters(
+        &self,
+        token: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/api/v1/newsletters", &self.address))
+            .bearer_auth(token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Log `user` in with a client of its own, as if from another device.
+    pub async fn login_from_new_client(&self, user: &TestUser, user_agent: &str) -> Client {
+        let client = Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .user_agent(user_agent)
+            .build()
+            .unwrap();
+
+        let html = client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap();
+        let response = client
+            .post(format!("{}/login", &self.address))
+            .form(&serde_json::json!({
+                "username": &user.username,
+                "password": &user.password,
+                "csrf_token": extract_csrf_token(&html).unwrap(),
+            }))
+            .send()
+            .await
+            .expect("Failed to execute login request");
+        assert_is_redirected_to(&response, "/admin/dashboard");
+
+        client
+    }
+
+    pub async fn post_accept_invitation<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/invitations/accept", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_forgot_password(&self, username: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/forgot", &self.address))
+            .form(&serde_json::json!({ "username": username }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_reset_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login/reset", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_two_factor_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/two-factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_two_factor(&self, code: &str) -> reqwest::Response {
+        let body = self
+            .with_csrf_token("/admin/dashboard", &serde_json::json!({ "code": code }))
+            .await;
+        self.api_client
+            .post(format!("{}/admin/two-factor", &self.address))
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login_second_factor(&self, code: &str) -> reqwest::Response {
+        let body = self
+            .with_csrf_token("/login", &serde_json::json!({ "code": code }))
+            .await;
+        self.api_client
+            .post(format!("{}/login/two-factor", &self.address))
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// The session's CSRF token, as embedded in the forms of `page`. Loading the page clears any
+    /// pending flash messages.
+    pub async fn get_csrf_token(&self, page: &str) -> Option<String> {
+        let html = self
+            .api_client
+            .get(format!("{}{}", &self.address, page))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap();
+
+        extract_csrf_token(&html)
+    }
+
+    /// Add the CSRF token from `page` to a form, like a browser submitting it would.
+    pub async fn with_csrf_token<Body>(&self, page: &str, body: &Body) -> serde_json::Value
+    where
+        Body: serde::Serialize,
+    {
+        let mut body = serde_json::to_value(body).unwrap();
+        if let Some(token) = self.get_csrf_token(page).await {
+            body["csrf_token"] = token.into();
+        }
+        body
+    }
+
     // Extract the confirmation links from the HTML and plaintext emails
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
@@ -165,6 +595,11 @@ impl TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application after `configure` has had a chance to change its settings.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
     std::env::set_var("APP_ENVIRONMENT", "test");
 
@@ -175,6 +610,19 @@ pub async fn spawn_app() -> TestApp {
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        // Keep the progressive delays short, and the limits predictable
+        c.login_throttle.base_delay_millis = 1;
+        c.login_throttle.max_delay_millis = 10;
+        c.login_throttle.max_failures_per_username = 5;
+        c.login_throttle.max_failures_per_ip = 10;
+        // Redis is shared between tests, give each one its own buckets
+        c.subscription_rate_limit.key_prefix = Uuid::new_v4().to_string();
+        c.subscription_rate_limit.per_ip.capacity = 10;
+        c.subscription_rate_limit.per_email.capacity = 3;
+        c.password_reset_rate_limit.key_prefix = Uuid::new_v4().to_string();
+        c.password_reset_rate_limit.per_ip.capacity = 5;
+        c.password_reset_rate_limit.per_account.capacity = 3;
+        configure(&mut c);
         c
     };
 
@@ -184,7 +632,7 @@ pub async fn spawn_app() -> TestApp {
         .expect("Failed to build application");
     let address = format!("http://127.1:{}", application.port());
     let port = application.port();
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
     let api_client = Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -199,6 +647,8 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         test_user: TestUser::generate(),
         api_client,
+        email_client: configuration.email_client.client(),
+        password_reset_expiry: configuration.application.password_reset_expiry(),
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -234,15 +684,24 @@ pub struct ConfirmationLinks {
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
+    pub email: String,
     pub password: String,
+    pub role: String,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::generate_with_role("owner")
+    }
+
+    pub fn generate_with_role(role: &str) -> Self {
+        let username = Uuid::new_v4().to_string();
         Self {
             user_id: Uuid::new_v4(),
-            username: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", username),
+            username,
             password: Uuid::new_v4().to_string(),
+            role: role.to_owned(),
         }
     }
 
@@ -259,12 +718,14 @@ impl TestUser {
 
         sqlx::query!(
             r#"
-            INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)
+            INSERT INTO users (user_id, username, password_hash, role, email)
+            VALUES ($1, $2, $3, $4, $5)
             "#,
             &self.user_id,
             &self.username,
-            password_hash
+            password_hash,
+            &self.role,
+            &self.email,
         )
         .execute(pool)
         .await
@@ -272,6 +733,14 @@ impl TestUser {
     }
 }
 
+/// The CSRF token embedded in a page's forms.
+pub fn extract_csrf_token(html: &str) -> Option<String> {
+    let marker = r#"name="csrf_token" value=""#;
+    let start = html.find(marker)? + marker.len();
+    let end = start + html[start..].find('"')?;
+    Some(html[start..end].to_owned())
+}
+
 pub fn assert_is_redirected_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
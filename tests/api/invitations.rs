@@ -0,0 +1,380 @@
+use crate::helpers::{assert_is_redirected_to, spawn_app, TestApp};
+
+use {
+    reqwest::Url,
+    uuid::Uuid,
+    wiremock::{
+        matchers::{method, path},
+        Mock, ResponseTemplate,
+    },
+};
+
+/// Invite `email` as `role` while logged in as the test user, returning the link from the email.
+async fn invite(app: &TestApp, email: &str, role: &str) -> Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.login_test_user().await;
+    let response = app
+        .post_users("", &serde_json::json!({ "email": email, "role": role }))
+        .await;
+    assert_is_redirected_to(&response, "/admin/users");
+    let html = app.get_users_html().await;
+    assert!(html.contains(&format!(
+        "<p><i>An invitation has been sent to {}.</i></p>",
+        email
+    )));
+    app.post_logout().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).html
+}
+
+fn invitation_token(link: &Url) -> String {
+    link.query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+fn accept_body(link: &Url, username: &str, password: &str) -> serde_json::Value {
+    serde_json::json!({
+        "token": invitation_token(link),
+        "username": username,
+        "password": password,
+        "password_check": password,
+    })
+}
+
+#[tokio::test]
+async fn owners_can_invite_collaborators_by_email() {
+    let app = spawn_app().await;
+
+    // Part 1 - Send the invitation
+    let link = invite(&app, "new-editor@example.com", "editor").await;
+    assert_eq!(link.path(), "/invitations/accept");
+
+    // Part 2 - Follow the link
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("invited to join as editor"));
+
+    // Part 3 - Choose a username and password
+    let password = Uuid::new_v4().to_string();
+    let response = app
+        .post_accept_invitation(&accept_body(&link, "new-editor", &password))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("<p><i>Your account has been created. You can now log in.</i></p>"));
+
+    // Part 4 - The new collaborator can log in with their role
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "new-editor",
+            "password": &password,
+        }))
+        .await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+    let response = app.get_newsletter_page().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn invited_passwords_are_hashed() {
+    let app = spawn_app().await;
+    let link = invite(&app, "new-viewer@example.com", "viewer").await;
+
+    let password = Uuid::new_v4().to_string();
+    app.post_accept_invitation(&accept_body(&link, "new-viewer", &password))
+        .await;
+
+    let stored = sqlx::query!("SELECT password_hash FROM users WHERE username = 'new-viewer'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(stored.password_hash.starts_with("$argon2id$"));
+    assert!(!stored.password_hash.contains(&password));
+}
+
+#[tokio::test]
+async fn invitations_require_a_valid_email_address() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_users(
+            "",
+            &serde_json::json!({ "email": "not-an-email", "role": "viewer" }),
+        )
+        .await;
+    assert_is_redirected_to(&response, "/admin/users");
+    let html = app.get_users_html().await;
+    assert!(html.contains("<p><i>Please enter a valid email address.</i></p>"));
+}
+
+#[tokio::test]
+async fn invitation_links_cannot_be_reused() {
+    let app = spawn_app().await;
+    let link = invite(&app, "new-viewer@example.com", "viewer").await;
+    app.post_accept_invitation(&accept_body(
+        &link,
+        "new-viewer",
+        &Uuid::new_v4().to_string(),
+    ))
+    .await;
+
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("This invitation link has already been used."));
+
+    let response = app
+        .post_accept_invitation(&accept_body(&link, "intruder", &Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("This invitation link has already been used."));
+    let n_users =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM users WHERE username = 'intruder'"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(n_users, 0);
+}
+
+#[tokio::test]
+async fn expired_invitation_links_are_rejected() {
+    let app = spawn_app().await;
+    let link = invite(&app, "new-viewer@example.com", "viewer").await;
+    sqlx::query!("UPDATE user_invitations SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("This invitation link has expired."));
+
+    let response = app
+        .post_accept_invitation(&accept_body(
+            &link,
+            "new-viewer",
+            &Uuid::new_v4().to_string(),
+        ))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("This invitation link has expired."));
+}
+
+#[tokio::test]
+async fn tampered_invitation_links_are_rejected() {
+    let app = spawn_app().await;
+    let link = invite(&app, "new-viewer@example.com", "viewer").await;
+    let token = invitation_token(&link);
+    let (_, signature) = token.split_once('.').unwrap();
+    let tampered = format!("{}.{}", Uuid::new_v4(), signature);
+
+    let response = app
+        .api_client
+        .get(format!("{}/invitations/accept", &app.address))
+        .query(&[("token", &tampered)])
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("This invitation link is not valid."));
+}
+
+#[tokio::test]
+async fn invitees_are_sent_back_to_the_form_when_their_choice_is_rejected() {
+    let app = spawn_app().await;
+    let link = invite(&app, "new-viewer@example.com", "viewer").await;
+    let retry_url = format!(
+        "/invitations/accept?token={}",
+        urlencoding::encode(&invitation_token(&link))
+    );
+
+    // Mismatched passwords
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "token": invitation_token(&link),
+            "username": "new-viewer",
+            "password": Uuid::new_v4().to_string(),
+            "password_check": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirected_to(&response, &retry_url);
+    let html = app
+        .api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("You entered two different passwords"));
+
+    // A password that is too easy to guess
+    let response = app
+        .post_accept_invitation(&accept_body(&link, "new-viewer", "password1234"))
+        .await;
+    assert_is_redirected_to(&response, &retry_url);
+    let html = app
+        .api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("This password is too easy to guess."));
+
+    // A username that is already taken
+    let response = app
+        .post_accept_invitation(&accept_body(
+            &link,
+            &app.test_user.username,
+            &Uuid::new_v4().to_string(),
+        ))
+        .await;
+    assert_is_redirected_to(&response, &retry_url);
+    let html = app
+        .api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("That username is already taken."));
+
+    // The invitation can still be accepted afterwards
+    let response = app
+        .post_accept_invitation(&accept_body(
+            &link,
+            "new-viewer",
+            &Uuid::new_v4().to_string(),
+        ))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn addresses_that_already_have_an_account_cannot_be_invited() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    let response = app
+        .post_users(
+            "",
+            &serde_json::json!({ "email": &app.test_user.email, "role": "viewer" }),
+        )
+        .await;
+    assert_is_redirected_to(&response, "/admin/users");
+    let html = app.get_users_html().await;
+    assert!(html.contains(&format!(
+        "<p><i>{} already has an account.</i></p>",
+        app.test_user.email
+    )));
+}
+
+#[tokio::test]
+async fn only_one_invitation_per_address_can_be_accepted() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // The same address is invited twice
+    app.login_test_user().await;
+    for role in ["viewer", "editor"] {
+        let response = app
+            .post_users(
+                "",
+                &serde_json::json!({ "email": "new-viewer@example.com", "role": role }),
+            )
+            .await;
+        assert_is_redirected_to(&response, "/admin/users");
+    }
+    app.post_logout().await;
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]).html;
+    let second_link = app.get_confirmation_links(&email_requests[1]).html;
+
+    let response = app
+        .post_accept_invitation(&accept_body(
+            &first_link,
+            "new-viewer",
+            &Uuid::new_v4().to_string(),
+        ))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+
+    // The other invitation went with the first one
+    let response = app
+        .post_accept_invitation(&accept_body(
+            &second_link,
+            "new-editor",
+            &Uuid::new_v4().to_string(),
+        ))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("This invitation link has already been used."));
+}
+
+#[tokio::test]
+async fn invitations_to_an_address_that_has_since_got_an_account_are_rejected() {
+    let app = spawn_app().await;
+    let link = invite(&app, "new-viewer@example.com", "viewer").await;
+    sqlx::query!(
+        "UPDATE users SET email = 'new-viewer@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app
+        .post_accept_invitation(&accept_body(
+            &link,
+            "new-viewer",
+            &Uuid::new_v4().to_string(),
+        ))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("An account already exists for this email address."));
+    let n_users =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM users WHERE username = 'new-viewer'"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(n_users, 0);
+}
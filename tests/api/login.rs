@@ -1,4 +1,6 @@
-use crate::helpers::{assert_is_redirected_to, spawn_app};
+use crate::helpers::{assert_is_redirected_to, spawn_app, TestApp};
+
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 
 #[tokio::test]
 async fn error_flash_message_set_on_failure() {
@@ -45,3 +47,206 @@ async fn require_login_to_access_admin() {
 
     assert_is_redirected_to(&response, "/login");
 }
+
+const LOCKOUT_MESSAGE: &str =
+    "<p><i>Too many failed login attempts. Please try again in 15 minutes.</i></p>";
+
+#[tokio::test]
+async fn repeated_failures_lock_the_username_out() {
+    let app = spawn_app().await;
+
+    for _ in 0..5 {
+        let response = app
+            .post_login(&serde_json::json!({
+                "username": &app.test_user.username,
+                "password": "wrong-password",
+            }))
+            .await;
+        assert_is_redirected_to(&response, "/login");
+    }
+
+    // Even the right password is refused during the lockout
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(LOCKOUT_MESSAGE));
+
+    sqlx::query!("UPDATE login_failures SET locked_until = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn repeated_failures_from_one_address_lock_every_username_out() {
+    let app = spawn_app().await;
+
+    for i in 0..10 {
+        app.post_login(&serde_json::json!({
+            "username": format!("user-{}", i),
+            "password": "wrong-password",
+        }))
+        .await;
+    }
+
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(LOCKOUT_MESSAGE));
+}
+
+#[tokio::test]
+async fn the_address_lockout_cannot_be_dodged_with_forwarding_headers() {
+    let app = spawn_app().await;
+
+    // No proxy is trusted, so every attempt comes from the same address
+    for i in 0..10 {
+        let body = app
+            .with_csrf_token(
+                "/login",
+                &serde_json::json!({
+                    "username": format!("user-{}", i),
+                    "password": "wrong-password",
+                }),
+            )
+            .await;
+        app.api_client
+            .post(format!("{}/login", &app.address))
+            .header("X-Forwarded-For", format!("198.51.100.{}", i))
+            .form(&body)
+            .send()
+            .await
+            .expect("Failed to execute login request");
+    }
+
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(LOCKOUT_MESSAGE));
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failure_count() {
+    let app = spawn_app().await;
+    let wrong_login = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password",
+    });
+
+    for _ in 0..4 {
+        app.post_login(&wrong_login).await;
+    }
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    for _ in 0..4 {
+        app.post_login(&wrong_login).await;
+    }
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+}
+
+async fn store_password_hash(app: &TestApp, algorithm: Algorithm, params: Params) -> String {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(algorithm, Version::V0x13, params)
+        .hash_password(app.test_user.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        password_hash,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    password_hash
+}
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+}
+
+#[tokio::test]
+async fn weaker_password_hashes_are_upgraded_on_login() {
+    let app = spawn_app().await;
+    let old_hash = store_password_hash(
+        &app,
+        Algorithm::Argon2id,
+        Params::new(4096, 1, 1, None).unwrap(),
+    )
+    .await;
+
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+
+    let new_hash = stored_password_hash(&app).await;
+    assert_ne!(new_hash, old_hash);
+    assert!(new_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+
+    // The upgraded hash still verifies
+    app.post_logout().await;
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn password_hashes_from_another_algorithm_are_upgraded_on_login() {
+    let app = spawn_app().await;
+    store_password_hash(
+        &app,
+        Algorithm::Argon2i,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .await;
+
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+
+    assert!(stored_password_hash(&app)
+        .await
+        .starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+}
+
+#[tokio::test]
+async fn current_password_hashes_are_left_alone_on_login() {
+    let app = spawn_app().await;
+    let old_hash = stored_password_hash(&app).await;
+
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+
+    assert_eq!(stored_password_hash(&app).await, old_hash);
+}
+
+#[tokio::test]
+async fn weak_password_hashes_are_not_upgraded_after_a_failed_login() {
+    let app = spawn_app().await;
+    let old_hash = store_password_hash(
+        &app,
+        Algorithm::Argon2id,
+        Params::new(4096, 1, 1, None).unwrap(),
+    )
+    .await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "wrong-password",
+        }))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+
+    assert_eq!(stored_password_hash(&app).await, old_hash);
+}
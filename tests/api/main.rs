@@ -1,7 +1,16 @@
+mod api_tokens;
+mod audit;
 mod change_password;
+mod csrf;
 mod health_check;
 mod helpers;
+mod invitations;
 mod login;
 mod newsletter;
+mod password_reset;
+mod sessions;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod two_factor;
+mod users;
@@ -1,8 +1,24 @@
-use crate::helpers::{assert_is_redirected_to, spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    assert_is_redirected_to, spawn_app, spawn_app_with, ConfirmationLinks, TestApp,
+};
+
+use zero2prod::{
+    email_client::{Email, EmailSender, SendEmailError},
+    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+};
 
-use wiremock::{
-    matchers::{any, method, path},
-    Mock, ResponseTemplate,
+use {
+    chrono::{Duration, Utc},
+    fake::{
+        faker::{internet::en::SafeEmail, name::en::Name},
+        Fake,
+    },
+    sqlx::PgPool,
+    uuid::Uuid,
+    wiremock::{
+        matchers::{any, method, path},
+        Mock, Request, Respond, ResponseTemplate,
+    },
 };
 
 #[tokio::test]
@@ -19,7 +35,8 @@ async fn newsletters_not_delivered_to_unconfirmed_subscribers() {
     let newsletter_requst_body = serde_json::json!({
         "title": "Newsletter title",
         "text_content": "Newsletter text body",
-        "html_content": "<p>Newsletter HTML body</p>"
+        "html_content": "<p>Newsletter HTML body</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
     });
     app.login_test_user().await;
     let response = app.post_newsletters(&newsletter_requst_body).await;
@@ -27,7 +44,11 @@ async fn newsletters_not_delivered_to_unconfirmed_subscribers() {
     assert_is_redirected_to(&response, "/admin/newsletters");
     // Follow the redirect
     let html = app.get_newsletter_page().await.text().await.unwrap();
-    assert!(html.contains("<p><i>Newsletter delivered successfully</i></p>"));
+    assert!(html.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -35,9 +56,9 @@ async fn newsletters_delivered_to_confirmed_subscribers() {
     let app = spawn_app().await;
     app.login_test_user().await;
     create_confirmed_subscriber(&app).await;
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(BatchResponder::default())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -46,7 +67,8 @@ async fn newsletters_delivered_to_confirmed_subscribers() {
     let newsletter_requst_body = serde_json::json!({
         "title": "Newsletter title",
         "text_content": "Newsletter text body",
-        "html_content": "<p>Newsletter HTML body</p>"
+        "html_content": "<p>Newsletter HTML body</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
     });
     let response = app.post_newsletters(&newsletter_requst_body).await;
 
@@ -55,7 +77,324 @@ async fn newsletters_delivered_to_confirmed_subscribers() {
 
     // Follow the redirect
     let html = app.get_newsletter_page().await.text().await.unwrap();
-    assert!(html.contains("<p><i>Newsletter delivered successfully</i></p>"));
+    assert!(html.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn delivery_continues_after_a_failed_email() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    // Both deliveries go out in a single batch, Postmark rejects the first one
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder {
+            failing_index: Some(0),
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // The rejected delivery is dead-lettered, the other one is done
+    let remaining = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count queued deliveries");
+    assert_eq!(remaining.count, 0);
+    let dead_letters = sqlx::query!("SELECT n_attempts FROM issue_delivery_dead_letters")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch dead-lettered deliveries");
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].n_attempts, 1);
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_subscriber(&app).await;
+
+    for status in [500, 503, 429] {
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(status))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+    }
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    for n_attempts in 1..=3 {
+        app.dispatch_all_pending_emails().await;
+
+        let task = sqlx::query!("SELECT n_attempts FROM issue_delivery_queue")
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to fetch queued delivery");
+        assert_eq!(task.n_attempts, n_attempts);
+
+        // Skip the backoff
+        make_queued_deliveries_due(&app).await;
+    }
+    app.dispatch_all_pending_emails().await;
+
+    let remaining = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count queued deliveries");
+    assert_eq!(remaining.count, 0);
+}
+
+#[tokio::test]
+async fn transient_failures_are_dead_lettered_after_too_many_attempts() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(10)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    for _ in 0..10 {
+        app.dispatch_all_pending_emails().await;
+        make_queued_deliveries_due(&app).await;
+    }
+
+    let dead_letter = sqlx::query!("SELECT n_attempts FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch dead-lettered delivery");
+    assert_eq!(dead_letter.n_attempts, 10);
+}
+
+#[tokio::test]
+async fn permanent_failures_are_dead_lettered_and_can_be_requeued() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder {
+            failing_index: Some(0),
+        })
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Part 1 - The delivery is moved to the dead-letter table straight away
+    let dead_letter = sqlx::query!(
+        "SELECT newsletter_issue_id, subscriber_email, n_attempts FROM issue_delivery_dead_letters"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch dead-lettered delivery");
+    assert_eq!(dead_letter.n_attempts, 1);
+
+    let html = app.get_failed_deliveries_html().await;
+    assert!(html.contains(&dead_letter.subscriber_email));
+
+    // Part 2 - Requeue the delivery
+    let response = app
+        .post_requeue_failed_delivery(&serde_json::json!({
+            "newsletter_issue_id": dead_letter.newsletter_issue_id,
+            "subscriber_email": dead_letter.subscriber_email,
+        }))
+        .await;
+    assert_is_redirected_to(&response, "/admin/newsletters/failed");
+
+    let html = app.get_failed_deliveries_html().await;
+    assert!(html.contains("<p><i>The delivery has been requeued.</i></p>"));
+    assert!(!html.contains(&dead_letter.subscriber_email));
+
+    // Part 3 - The requeued delivery goes out
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn deliveries_are_claimed_before_they_are_sent() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    publish_newsletter(&app).await;
+
+    let email_client = ClaimCheckingSender {
+        pool: app.db_pool.clone(),
+    };
+    while let ExecutionOutcome::TaskCompleted =
+        try_execute_task(&app.db_pool, &email_client, &app.address)
+            .await
+            .unwrap()
+    {}
+
+    // Every email was sent while its delivery was visibly claimed, and recorded as sent
+    let dead_letters =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_dead_letters"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to count dead-lettered deliveries");
+    assert_eq!(dead_letters.count, 0);
+    let remaining = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count queued deliveries");
+    assert_eq!(remaining.count, 0);
+}
+
+#[tokio::test]
+async fn logged_out_users_cannot_see_failed_deliveries() {
+    let app = spawn_app().await;
+    let response = app
+        .api_client
+        .get(format!("{}/admin/newsletters/failed", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirected_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn newsletters_include_an_unsubscribe_link() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let unsubscribe_link = get_unsubscribe_link(&app).await;
+    let batch_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&batch_request.body).unwrap();
+    assert!(body[0]["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(&unsubscribe_link));
+    assert!(body[0]["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(&unsubscribe_link));
+    assert_eq!(
+        body[0]["Headers"],
+        serde_json::json!([
+            { "Name": "List-Unsubscribe", "Value": format!("<{}>", unsubscribe_link) },
+            { "Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click" },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn opening_the_unsubscribe_link_does_not_unsubscribe() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = app
+        .api_client
+        .get(get_unsubscribe_link(&app).await)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Unsubscribe"));
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Part 1 - Unsubscribe with a one-click request, as mail clients do
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = app
+        .api_client
+        .post(get_unsubscribe_link(&app).await)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "unsubscribed");
+
+    // Part 2 - The next issue is not sent to them
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+    // The mock verifies on drop that only the first issue was sent
 }
 
 #[tokio::test]
@@ -65,24 +404,17 @@ async fn newsletters_returns_400_for_invalid_body() {
     let test_cases = vec![
         (
             serde_json::json!({
-                "text_content": "Newsletter body as plain text",
-                "html_content": "<p>Newsletter body as HTML</p>",
+                "markdown_content": "Newsletter body",
+                "idempotency_key": Uuid::new_v4().to_string(),
             }),
             "missing title",
         ),
         (
             serde_json::json!({
                 "title": "Newsletter!",
-                "html_content": "<p>Newsletter body as HTML</p>"
-            }),
-            "missing text",
-        ),
-        (
-            serde_json::json!({
-                "title": "Newsletter!",
-                "text_content": "Newsletter body as text"
+                "markdown_content": "Newsletter body",
             }),
-            "missing html",
+            "missing idempotency key",
         ),
     ];
 
@@ -97,6 +429,218 @@ async fn newsletters_returns_400_for_invalid_body() {
     }
 }
 
+#[tokio::test]
+async fn newsletters_without_content_are_rejected() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let test_cases = vec![
+        (serde_json::json!({}), "no content"),
+        (
+            serde_json::json!({ "markdown_content": "  ", "html_content": "<p>HTML</p>" }),
+            "blank Markdown and no text version",
+        ),
+        (
+            serde_json::json!({ "text_content": "Text" }),
+            "no Markdown and no HTML version",
+        ),
+    ];
+
+    for (mut body, description) in test_cases {
+        body["title"] = "Newsletter!".into();
+        body["idempotency_key"] = Uuid::new_v4().to_string().into();
+        let response = app.post_newsletters(&body).await;
+        assert_is_redirected_to(&response, "/admin/newsletters");
+        let html_page = app.get_newsletter_page().await.text().await.unwrap();
+        assert!(
+            html_page.contains(
+                "<p><i>Please write the issue in Markdown, or provide both its HTML and text \
+                versions.</i></p>"
+            ),
+            "The form accepted an issue with {}",
+            description
+        );
+    }
+
+    let n_issues = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn markdown_issues_are_sent_as_sanitised_html_and_footnoted_text() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_test_user().await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "markdown_content": MARKDOWN_CONTENT,
+            "html_content": "",
+            "text_content": "",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirected_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let batch_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&batch_request.body).unwrap();
+    let html_body = body[0]["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains("<h1>Hello</h1>"));
+    assert!(html_body.contains("<strong>new</strong>"));
+    assert!(html_body.contains(r#"<a href="https://example.com/post" rel="noopener noreferrer">"#));
+    assert!(!html_body.contains("<script>"));
+    let text_body = body[0]["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with(
+        "Hello\n=====\n\nThere is something new, read the post [1].\n\n[1] https://example.com/post"
+    ));
+}
+
+#[tokio::test]
+async fn unsaved_content_can_be_previewed() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "markdown_content": MARKDOWN_CONTENT,
+        "html_content": "",
+        "text_content": "Handwritten text",
+    });
+    let html_page = app
+        .post_newsletter_preview(&body)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<iframe sandbox"));
+    assert!(html_page.contains("&lt;h1&gt;Hello&lt;/h1&gt;"));
+    assert!(html_page.contains("<pre>Handwritten text</pre>"));
+    assert!(!html_page.contains("<script>"));
+
+    // Nothing is saved
+    let n_issues = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_test_user().await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Submit the newsletter form
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter text body",
+        "html_content": "<p>Newsletter HTML body</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirected_to(&response, "/admin/newsletters");
+
+    let html = app.get_newsletter_page().await.text().await.unwrap();
+    assert!(html.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+
+    // Submit the same form again
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirected_to(&response, "/admin/newsletters");
+
+    let html = app.get_newsletter_page().await.text().await.unwrap();
+    assert!(html.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+
+    app.dispatch_all_pending_emails().await;
+    // The mock verifies on drop that the newsletter was only sent once
+}
+
+#[tokio::test]
+async fn concurrent_form_submission_is_handled_gracefully() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_test_user().await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter text body",
+        "html_content": "<p>Newsletter HTML body</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response1 = app.post_newsletters(&newsletter_request_body);
+    let response2 = app.post_newsletters(&newsletter_request_body);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_idempotency_key() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    let test_cases = vec![("".to_string(), "empty"), ("a".repeat(50), "too long")];
+
+    for (idempotency_key, description) in test_cases {
+        let response = app
+            .post_newsletters(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter text body",
+                "html_content": "<p>Newsletter HTML body</p>",
+                "idempotency_key": idempotency_key,
+            }))
+            .await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the idempotency key was {}",
+            description
+        )
+    }
+}
+
 #[tokio::test]
 async fn logged_out_users_redirected_post() {
     let app = spawn_app().await;
@@ -111,8 +655,440 @@ async fn logged_out_users_redirected_get() {
     assert_is_redirected_to(&response, "/login");
 }
 
+#[tokio::test]
+async fn drafts_can_be_saved_edited_and_previewed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_test_user().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = save_draft(&app).await;
+    let html_page = app.get_draft(&issue_id).await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(html_page.contains(">Draft text body</textarea>"));
+
+    let response = app
+        .post_drafts(
+            &format!("/{}", issue_id),
+            &serde_json::json!({
+                "title": "Better title",
+                "text_content": "Draft text body",
+                "html_content": "<p>Draft <script>alert(1)</script></p>",
+            }),
+        )
+        .await;
+    assert_is_redirected_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", issue_id),
+    );
+
+    let html_page = app
+        .api_client
+        .get(format!(
+            "{}/admin/newsletters/issues/{}/preview",
+            &app.address, issue_id
+        ))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Better title"));
+    assert!(html_page.contains("<iframe sandbox"));
+    assert!(!html_page.contains("<script>"));
+
+    let html_page = app.get_newsletter_history_html().await;
+    assert!(html_page.contains("Better title"));
+    assert!(html_page.contains("<td>draft</td>"));
+    assert!(html_page.contains(&format!("<td>{}</td>", app.test_user.username)));
+    assert!(html_page.contains(&format!(r#"href="/admin/newsletters/drafts/{}""#, issue_id)));
+
+    // Saving a draft sends nothing
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn sent_drafts_are_recorded_in_the_history() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_test_user().await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = save_draft(&app).await;
+    let send_body = serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() });
+    let response = app
+        .post_drafts(&format!("/{}/send", issue_id), &send_body)
+        .await;
+    assert_is_redirected_to(&response, "/admin/newsletters/history");
+    let html_page = app.get_newsletter_history_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+    assert!(html_page.contains("<td>sending</td>"));
+
+    app.dispatch_all_pending_emails().await;
+    let html_page = app.get_newsletter_history_html().await;
+    assert!(html_page.contains("<td>sent</td>"));
+    assert!(html_page.contains("<td>1</td>"));
+    assert!(html_page.contains(&format!("<td>{}</td>", app.test_user.username)));
+    assert!(!html_page.contains(&format!(r#"href="/admin/newsletters/drafts/{}""#, issue_id)));
+
+    // Sent issues can neither be sent again nor edited
+    let send_body = serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() });
+    app.post_drafts(&format!("/{}/send", issue_id), &send_body)
+        .await;
+    let html_page = app.get_newsletter_history_html().await;
+    assert!(html_page.contains("<p><i>Only drafts can be sent.</i></p>"));
+
+    let response = app.get_draft(&issue_id).await;
+    assert_is_redirected_to(&response, "/admin/newsletters/history");
+    let html_page = app.get_newsletter_history_html().await;
+    assert!(html_page.contains("<p><i>Only drafts can be edited.</i></p>"));
+}
+
+#[tokio::test]
+async fn issues_without_recipients_are_sent_straight_away() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    publish_newsletter(&app).await;
+
+    let issue = sqlx::query!("SELECT status, n_recipients, author_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "sent");
+    assert_eq!(issue.n_recipients, Some(0));
+    assert_eq!(issue.author_id, Some(app.test_user.user_id));
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_sent_once_they_are_due() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_test_user().await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = save_draft(&app).await;
+    let response = app
+        .post_drafts(
+            &format!("/{}/schedule", issue_id),
+            &serde_json::json!({ "send_at": send_at_in(Duration::days(1)) }),
+        )
+        .await;
+    assert_is_redirected_to(&response, "/admin/newsletters/scheduled");
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("<p><i>The issue has been scheduled.</i></p>"));
+    assert!(html_page.contains("Draft title"));
+
+    // Nothing goes out before the send time
+    app.send_due_issues().await;
+    let html_page = app.get_newsletter_history_html().await;
+    assert!(html_page.contains("<td>scheduled</td>"));
+
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.send_due_issues().await;
+    app.send_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app.get_newsletter_history_html().await;
+    assert!(html_page.contains("<td>sent</td>"));
+    assert!(html_page.contains("<td>1</td>"));
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("There are no scheduled issues."));
+
+    // Once delivery has started it is too late to cancel
+    let response = app
+        .post_scheduled(&format!("/{}/cancel", issue_id), &serde_json::json!({}))
+        .await;
+    assert_is_redirected_to(&response, "/admin/newsletters/scheduled");
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("<p><i>That issue is no longer scheduled.</i></p>"));
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled_and_cancelled() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_test_user().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = save_draft(&app).await;
+    app.post_drafts(
+        &format!("/{}/schedule", issue_id),
+        &serde_json::json!({ "send_at": send_at_in(Duration::days(1)) }),
+    )
+    .await;
+
+    let new_send_at = send_at_in(Duration::days(7));
+    let response = app
+        .post_scheduled(
+            &format!("/{}/reschedule", issue_id),
+            &serde_json::json!({ "send_at": new_send_at }),
+        )
+        .await;
+    assert_is_redirected_to(&response, "/admin/newsletters/scheduled");
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("<p><i>The issue has been rescheduled.</i></p>"));
+    assert!(html_page.contains(&format!(r#"value="{}""#, new_send_at)));
+
+    let response = app
+        .post_scheduled(&format!("/{}/cancel", issue_id), &serde_json::json!({}))
+        .await;
+    assert_is_redirected_to(&response, "/admin/newsletters/scheduled");
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains(
+        "<p><i>The scheduled send has been cancelled, the issue is a draft again.</i></p>"
+    ));
+    assert!(html_page.contains("There are no scheduled issues."));
+
+    // A cancelled issue is a draft again, and the scheduler leaves it alone
+    let response = app.get_draft(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.send_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    let html_page = app.get_newsletter_history_html().await;
+    assert!(html_page.contains("<td>draft</td>"));
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past_or_at_invalid_times() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let issue_id = save_draft(&app).await;
+
+    let test_cases = vec![
+        (
+            send_at_in(-Duration::hours(1)),
+            "The send time must be in the future.",
+        ),
+        (
+            "tomorrow".to_string(),
+            "Please enter a valid date and time.",
+        ),
+        ("".to_string(), "Please enter a valid date and time."),
+    ];
+    for (send_at, error_message) in test_cases {
+        let response = app
+            .post_drafts(
+                &format!("/{}/schedule", issue_id),
+                &serde_json::json!({ "send_at": send_at }),
+            )
+            .await;
+        assert_is_redirected_to(
+            &response,
+            &format!("/admin/newsletters/drafts/{}", issue_id),
+        );
+        let html_page = app.get_draft(&issue_id).await.text().await.unwrap();
+        assert!(
+            html_page.contains(&format!("<p><i>{}</i></p>", error_message)),
+            "The schedule form did not reject {:?}",
+            send_at
+        );
+    }
+
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("There are no scheduled issues."));
+}
+
+#[tokio::test]
+async fn send_times_are_read_and_shown_in_the_configured_time_zone() {
+    let app = spawn_app_with(|c| c.application.timezone = chrono_tz::Asia::Kolkata).await;
+    app.login_test_user().await;
+    let issue_id = save_draft(&app).await;
+    let html_page = app.get_draft(&issue_id).await.text().await.unwrap();
+    assert!(html_page.contains("Send at (Asia/Kolkata)"));
+
+    // An hour from now in UTC is hours ago in India
+    let response = app
+        .post_drafts(
+            &format!("/{}/schedule", issue_id),
+            &serde_json::json!({ "send_at": send_at_in(Duration::hours(1)) }),
+        )
+        .await;
+    assert_is_redirected_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", issue_id),
+    );
+
+    let due = Utc::now() + Duration::days(1);
+    let send_at = due
+        .with_timezone(&chrono_tz::Asia::Kolkata)
+        .format("%Y-%m-%dT%H:%M")
+        .to_string();
+    app.post_drafts(
+        &format!("/{}/schedule", issue_id),
+        &serde_json::json!({ "send_at": send_at }),
+    )
+    .await;
+    let stored = sqlx::query!("SELECT send_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .send_at
+        .unwrap();
+    assert_eq!(
+        stored.format("%Y-%m-%dT%H:%M").to_string(),
+        due.format("%Y-%m-%dT%H:%M").to_string()
+    );
+
+    let html_page = app.get_scheduled_issues_html().await;
+    assert!(html_page.contains("Send times are in Asia/Kolkata."));
+    assert!(html_page.contains(&format!(r#"value="{}""#, send_at)));
+    assert!(html_page.contains(&format!("{} IST", send_at.replace('T', " "))));
+}
+
+/// A send time `offset` from now, the way a `datetime-local` input submits it.
+fn send_at_in(offset: Duration) -> String {
+    (Utc::now() + offset).format("%Y-%m-%dT%H:%M").to_string()
+}
+
+const MARKDOWN_CONTENT: &str = "# Hello\n\n\
+    There is something **new**, read [the post](https://example.com/post).\n\n\
+    <script>alert(1)</script>";
+
+/// Save a draft from the new issue form, returning its ID.
+async fn save_draft(app: &TestApp) -> String {
+    let response = app
+        .post_drafts(
+            "",
+            &serde_json::json!({
+                "title": "Draft title",
+                "text_content": "Draft text body",
+                "html_content": "<p>Draft HTML body</p>",
+            }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 303);
+    response.headers()["Location"]
+        .to_str()
+        .unwrap()
+        .strip_prefix("/admin/newsletters/drafts/")
+        .unwrap()
+        .to_owned()
+}
+
+/// Answers Postmark batch requests with one result per message, rejecting the message at
+/// `failing_index` if any.
+#[derive(Default)]
+struct BatchResponder {
+    failing_index: Option<usize>,
+}
+
+impl Respond for BatchResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = (0..messages.len())
+            .map(|i| {
+                if Some(i) == self.failing_index {
+                    serde_json::json!({
+                        "ErrorCode": 406,
+                        "Message": "You tried to send to a recipient that has been marked as inactive."
+                    })
+                } else {
+                    serde_json::json!({ "ErrorCode": 0, "Message": "OK" })
+                }
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+}
+
+/// Only accepts emails whose delivery has already been claimed, as seen from a connection other
+/// than the worker's.
+struct ClaimCheckingSender {
+    pool: PgPool,
+}
+
+#[async_trait::async_trait]
+impl EmailSender for ClaimCheckingSender {
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError> {
+        let n_due = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "count!" FROM issue_delivery_queue
+            WHERE subscriber_email = $1 AND next_attempt_at <= now()
+            "#,
+            email.recipient.as_ref(),
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap()
+        .count;
+
+        match n_due {
+            0 => Ok(()),
+            _ => Err(SendEmailError::Permanent(anyhow::anyhow!(
+                "The delivery was sent before it was claimed"
+            ))),
+        }
+    }
+}
+
+async fn publish_newsletter(app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter text body",
+        "html_content": "<p>Newsletter HTML body</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirected_to(&response, "/admin/newsletters");
+}
+
+/// Extract the unsubscribe link from the `List-Unsubscribe` header of the last delivered batch.
+async fn get_unsubscribe_link(app: &TestApp) -> String {
+    let batch_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&batch_request.body).unwrap();
+    let header = body[0]["Headers"][0]["Value"].as_str().unwrap();
+    header
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_owned()
+}
+
+async fn make_queued_deliveries_due(app: &TestApp) {
+    sqlx::query!("UPDATE issue_delivery_queue SET next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to reschedule queued deliveries");
+}
+
 async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = format!(
+        "name={}&email={}",
+        urlencoding::encode(&name),
+        urlencoding::encode(&email)
+    );
 
     let _mock_guard = Mock::given(path("/email"))
         .and(method("POST"))
@@ -122,7 +1098,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .mount_as_scoped(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into())
+    app.post_subscriptions(body)
         .await
         .error_for_status()
         .unwrap();
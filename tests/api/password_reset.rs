@@ -0,0 +1,337 @@
+use crate::helpers::{assert_is_redirected_to, spawn_app, spawn_app_with, TestApp};
+
+use {
+    reqwest::Url,
+    uuid::Uuid,
+    wiremock::{
+        matchers::{method, path},
+        Mock, ResponseTemplate,
+    },
+};
+
+const FORGOT_PASSWORD_MESSAGE: &str =
+    "If that account exists, a link to reset its password has been sent to its email address.";
+
+/// Ask for a reset link for the test user, returning the link from the email.
+async fn request_reset_link(app: &TestApp) -> Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_forgot_password(&app.test_user.username).await;
+    assert_is_redirected_to(&response, "/login");
+    app.dispatch_password_reset_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).html
+}
+
+fn reset_body(link: &Url, new_password: &str) -> serde_json::Value {
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+    serde_json::json!({
+        "token": token,
+        "new_password": new_password,
+        "new_password_check": new_password,
+    })
+}
+
+#[tokio::test]
+async fn forgot_password_responds_the_same_way_for_unknown_users() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let mut pages = Vec::new();
+    for username in [app.test_user.username.as_str(), "nobody"] {
+        let response = app.post_forgot_password(username).await;
+        assert_is_redirected_to(&response, "/login");
+        pages.push(app.get_login_html().await);
+    }
+
+    assert!(pages[0].contains(FORGOT_PASSWORD_MESSAGE));
+    assert_eq!(pages[0], pages[1]);
+
+    // Only the account that exists gets an email
+    app.dispatch_password_reset_emails().await;
+}
+
+#[tokio::test]
+async fn reset_emails_are_sent_after_answering() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // A failing email provider is not the user's problem, and answering differently would give
+    // away that the account exists
+    let response = app.post_forgot_password(&app.test_user.username).await;
+    assert_is_redirected_to(&response, "/login");
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+
+    app.dispatch_password_reset_emails().await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+
+    // The request is kept to be retried later
+    let n_queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM password_reset_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 1);
+}
+
+#[tokio::test]
+async fn reset_requests_from_the_same_ip_are_rate_limited() {
+    let app = spawn_app().await;
+
+    for i in 0..5 {
+        let response = app.post_forgot_password(&format!("nobody{}", i)).await;
+        assert_is_redirected_to(&response, "/login");
+    }
+
+    let response = app.post_forgot_password("one_more").await;
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn reset_requests_for_the_same_account_are_rate_limited() {
+    let app = spawn_app_with(|c| c.password_reset_rate_limit.per_ip.capacity = 100).await;
+
+    for _ in 0..3 {
+        let response = app.post_forgot_password(&app.test_user.username).await;
+        assert_is_redirected_to(&response, "/login");
+    }
+
+    let response = app.post_forgot_password(&app.test_user.username).await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    // Other accounts aren't affected
+    let response = app.post_forgot_password("somebody_else").await;
+    assert_is_redirected_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn reset_links_are_sent_to_the_users_email_address() {
+    let app = spawn_app().await;
+    request_reset_link(&app).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], app.test_user.email);
+}
+
+#[tokio::test]
+async fn a_reset_link_lets_the_user_choose_a_new_password() {
+    let app = spawn_app().await;
+    let link = request_reset_link(&app).await;
+    assert_eq!(link.path(), "/login/reset");
+
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_reset_password(&reset_body(&link, &new_password))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("<p><i>Your password has been reset. You can now log in.</i></p>"));
+
+    // The old password no longer works
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/login");
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn reset_tokens_are_not_stored_in_clear() {
+    let app = spawn_app().await;
+    let link = request_reset_link(&app).await;
+    let token = reset_body(&link, "")["token"].as_str().unwrap().to_owned();
+
+    let stored = sqlx::query!("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+}
+
+#[tokio::test]
+async fn resetting_a_password_logs_out_every_session() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let link = request_reset_link(&app).await;
+
+    app.post_reset_password(&reset_body(&link, &Uuid::new_v4().to_string()))
+        .await;
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("<p><i>Your session has ended, please log in again.</i></p>"));
+}
+
+#[tokio::test]
+async fn reset_links_cannot_be_reused() {
+    let app = spawn_app().await;
+    let link = request_reset_link(&app).await;
+    app.post_reset_password(&reset_body(&link, &Uuid::new_v4().to_string()))
+        .await;
+
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_is_redirected_to(&response, "/login/forgot");
+
+    let response = app
+        .post_reset_password(&reset_body(&link, &Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirected_to(&response, "/login/forgot");
+    let html = app
+        .api_client
+        .get(format!("{}/login/forgot", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<p><i>This password reset link has already been used.</i></p>"));
+}
+
+#[tokio::test]
+async fn a_failed_reset_does_not_use_up_the_link() {
+    let app = spawn_app().await;
+    let link = request_reset_link(&app).await;
+
+    // Sabotage the database so that the new password can't be stored
+    sqlx::query!("ALTER TABLE users ADD CONSTRAINT no_new_passwords CHECK (false) NOT VALID")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app
+        .post_reset_password(&reset_body(&link, &Uuid::new_v4().to_string()))
+        .await;
+    assert_eq!(response.status().as_u16(), 500);
+
+    sqlx::query!("ALTER TABLE users DROP CONSTRAINT no_new_passwords")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app
+        .post_reset_password(&reset_body(&link, &Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("<p><i>Your password has been reset. You can now log in.</i></p>"));
+}
+
+#[tokio::test]
+async fn expired_reset_links_are_rejected() {
+    let app = spawn_app().await;
+    let link = request_reset_link(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_reset_password(&reset_body(&link, &Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirected_to(&response, "/login/forgot");
+    let html = app
+        .api_client
+        .get(format!("{}/login/forgot", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<p><i>This password reset link has expired.</i></p>"));
+
+    // The password was left alone
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn mismatched_new_passwords_are_rejected() {
+    let app = spawn_app().await;
+    let link = request_reset_link(&app).await;
+    let mut body = reset_body(&link, &Uuid::new_v4().to_string());
+    body["new_password_check"] = Uuid::new_v4().to_string().into();
+
+    let response = app.post_reset_password(&body).await;
+    assert_eq!(
+        response.headers().get("Location").unwrap(),
+        &format!("/login/reset?token={}", body["token"].as_str().unwrap())
+    );
+    let html = app
+        .api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("You entered two different new passwords"));
+}
+
+#[tokio::test]
+async fn weak_passwords_are_rejected_without_using_up_the_link() {
+    let app = spawn_app().await;
+    let link = request_reset_link(&app).await;
+
+    let response = app
+        .post_reset_password(&reset_body(&link, "password1234"))
+        .await;
+    assert_is_redirected_to(
+        &response,
+        &format!("{}?{}", link.path(), link.query().unwrap()),
+    );
+    let html = app
+        .api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<p><i>This password is too easy to guess."));
+
+    let response = app
+        .post_reset_password(&reset_body(&link, &Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("<p><i>Your password has been reset. You can now log in.</i></p>"));
+}
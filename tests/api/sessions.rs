@@ -0,0 +1,188 @@
+use crate::helpers::{
+    assert_is_redirected_to, extract_csrf_token, spawn_app, spawn_app_with, TestApp,
+};
+
+use {reqwest::Client, uuid::Uuid};
+
+/// The IDs of the sessions that can be signed out from the sessions page.
+fn revocable_session_ids(html: &str) -> Vec<String> {
+    html.split(r#"action="/admin/sessions/"#)
+        .skip(1)
+        .filter_map(|s| s[..s.find('"')?].strip_suffix("/revoke").map(str::to_owned))
+        .collect()
+}
+
+async fn get_dashboard(app: &TestApp, client: &Client) -> reqwest::Response {
+    client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn the_sessions_page_lists_every_active_session() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    app.login_from_new_client(&app.test_user, "Other device/1.0")
+        .await;
+
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("This session"));
+    assert!(html_page.contains("Other device/1.0"));
+    assert!(html_page.contains("127.0.0.1"));
+    // Only the other session can be signed out from the list
+    assert_eq!(revocable_session_ids(&html_page).len(), 1);
+}
+
+#[tokio::test]
+async fn other_users_sessions_are_not_listed() {
+    let app = spawn_app().await;
+    let editor = app.create_user("editor").await;
+    app.login_from_new_client(&editor, "Editor device/1.0")
+        .await;
+
+    app.login_test_user().await;
+    let html_page = app.get_sessions_html().await;
+    assert!(!html_page.contains("Editor device/1.0"));
+    assert!(revocable_session_ids(&html_page).is_empty());
+}
+
+#[tokio::test]
+async fn revoked_sessions_are_signed_out() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let other_client = app
+        .login_from_new_client(&app.test_user, "Other device/1.0")
+        .await;
+    assert_eq!(get_dashboard(&app, &other_client).await.status(), 200);
+
+    let session_id = revocable_session_ids(&app.get_sessions_html().await).remove(0);
+    let response = app.post_revoke_session(&session_id).await;
+    assert_is_redirected_to(&response, "/admin/sessions");
+
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<p><i>The session has been signed out.</i></p>"));
+    assert!(!html_page.contains("Other device/1.0"));
+
+    // The other device is sent back to the login page...
+    let response = get_dashboard(&app, &other_client).await;
+    assert_is_redirected_to(&response, "/login");
+    let html_page = other_client
+        .get(format!("{}/login", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        html_page.contains("<p><i>This session has been signed out, please log in again.</i></p>")
+    );
+
+    // ...while this one carries on
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn users_cannot_revoke_sessions_that_are_not_theirs() {
+    let app = spawn_app().await;
+    let editor = app.create_user("editor").await;
+    let editor_client = app
+        .login_from_new_client(&editor, "Editor device/1.0")
+        .await;
+    let other_editor_client = app
+        .login_from_new_client(&editor, "Editor laptop/1.0")
+        .await;
+    let editor_sessions_html = editor_client
+        .get(format!("{}/admin/sessions", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let editor_session_id = revocable_session_ids(&editor_sessions_html).remove(0);
+
+    app.login_test_user().await;
+    for session_id in [editor_session_id, Uuid::new_v4().to_string()] {
+        let response = app.post_revoke_session(&session_id).await;
+        assert_is_redirected_to(&response, "/admin/sessions");
+        let html_page = app.get_sessions_html().await;
+        assert!(html_page.contains("<p><i>That session has already ended.</i></p>"));
+    }
+
+    let response = get_dashboard(&app, &other_editor_client).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn signing_out_everywhere_ends_every_session() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let other_client = app
+        .login_from_new_client(&app.test_user, "Other device/1.0")
+        .await;
+
+    let response = app.post_sign_out_everywhere().await;
+    assert_is_redirected_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have been signed out of every session.</i></p>"));
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirected_to(&response, "/login");
+    let response = get_dashboard(&app, &other_client).await;
+    assert_is_redirected_to(&response, "/login");
+
+    // Logging back in starts afresh
+    app.login_test_user().await;
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("This session"));
+    assert!(!html_page.contains("Other device/1.0"));
+}
+
+#[tokio::test]
+async fn logging_out_removes_the_session_from_the_list() {
+    let app = spawn_app().await;
+    let other_client = app
+        .login_from_new_client(&app.test_user, "Other device/1.0")
+        .await;
+    let dashboard_html = get_dashboard(&app, &other_client)
+        .await
+        .text()
+        .await
+        .unwrap();
+    let body = serde_json::json!({ "csrf_token": extract_csrf_token(&dashboard_html) });
+    let response = other_client
+        .post(format!("{}/admin/logout", &app.address))
+        .form(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirected_to(&response, "/login");
+
+    app.login_test_user().await;
+    let html_page = app.get_sessions_html().await;
+    assert!(!html_page.contains("Other device/1.0"));
+}
+
+#[tokio::test]
+async fn idle_sessions_are_logged_out() {
+    let app = spawn_app_with(|c| c.application.session_idle_timeout_minutes = 0).await;
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirected_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(
+        "<p><i>You have been logged out after a period of inactivity, please log in again.</i></p>"
+    ));
+
+    // The session is gone, not just refused
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirected_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("<p><i>"));
+}
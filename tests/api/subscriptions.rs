@@ -1,8 +1,11 @@
 use crate::helpers::spawn_app;
 
-use wiremock::{
-    matchers::{method, path},
-    Mock, ResponseTemplate,
+use {
+    wiremock::{
+        matchers::{method, path},
+        Mock, ResponseTemplate,
+    },
+    zero2prod::subscription_cleanup_worker::delete_unconfirmed_subscriptions,
 };
 
 #[tokio::test]
@@ -56,6 +59,34 @@ async fn subscribe_resends_confirmation_email_on_resub() {
         .mount(&app.email_server)
         .await;
 
+    // Make the first create request, without confirming the subscription
+    app.post_subscriptions(body.into()).await;
+
+    // Make the second create request
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn resubscribing_a_confirmed_email_keeps_it_confirmed() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
     // Make the first create request and confirm the subscription
     app.post_subscriptions(body.into()).await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
@@ -66,17 +97,27 @@ async fn subscribe_resends_confirmation_email_on_resub() {
         .error_for_status()
         .unwrap();
 
-    // Make the second create request
+    // Anyone can submit the same address again
     app.post_subscriptions(body.into())
         .await
         .error_for_status()
         .unwrap();
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+
+    // The subscription outlives the cleanup of unconfirmed ones
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let n_deleted = delete_unconfirmed_subscriptions(&app.db_pool, chrono::Duration::days(7))
+        .await
+        .unwrap();
+    assert_eq!(n_deleted, 0);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription");
-
-    assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(saved.status, "confirmed");
 }
 
 #[tokio::test]
@@ -141,6 +182,42 @@ async fn subscribe_sends_confirmation_email_for_valid_data() {
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
 
+#[tokio::test]
+async fn unconfirmed_subscriptions_are_deleted_after_the_retention_period() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.post_subscriptions("name=tolkien&email=jrr_tolkien%40gmail.com".into())
+        .await;
+
+    // Only the first subscription is older than the retention period
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = now() - interval '8 days' WHERE name = 'le guin'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let n_deleted = delete_unconfirmed_subscriptions(&app.db_pool, chrono::Duration::days(7))
+        .await
+        .unwrap();
+    assert_eq!(n_deleted, 1);
+
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].name, "tolkien");
+}
+
 #[tokio::test]
 async fn subscribe_fails_if_fatal_database_error() {
     let app = spawn_app().await;
@@ -155,3 +232,102 @@ async fn subscribe_fails_if_fatal_database_error() {
     let response = app.post_subscriptions(body.into()).await;
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn repeated_subscriptions_for_the_same_email_are_rate_limited() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    // The email is normalised, changing its case doesn't get around the limit
+    for email in [
+        "ursula%40gmail.com",
+        "Ursula%40gmail.com",
+        "URSULA%40gmail.com",
+    ] {
+        let response = app
+            .post_subscriptions(format!("name=le%20guin&email={}", email))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+}
+
+#[tokio::test]
+async fn subscriptions_from_the_same_ip_are_rate_limited() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for i in 0..10 {
+        let response = app
+            .post_subscriptions(format!("name=reader&email=reader{}%40gmail.com", i))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let response = app
+        .post_subscriptions("name=reader&email=one_more%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn resending_confirmations_is_rate_limited() {
+    let app = spawn_app().await;
+
+    for _ in 0..3 {
+        let response = app.post_resend_confirmation("ursula@gmail.com").await;
+        assert_ne!(response.status().as_u16(), 429);
+    }
+
+    let response = app.post_resend_confirmation("ursula@gmail.com").await;
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn the_ip_limit_cannot_be_dodged_with_forwarding_headers() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // No proxy is trusted, so every request counts against the peer address
+    for i in 0..11 {
+        let response = app
+            .api_client
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", format!("203.0.113.{}", i))
+            .body(format!("name=reader&email=reader{}%40gmail.com", i))
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        let expected = if i < 10 { 200 } else { 429 };
+        assert_eq!(response.status().as_u16(), expected);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, TestApp};
 
 use wiremock::{
     matchers::{method, path},
@@ -68,6 +68,34 @@ async fn clicking_confirmation_link_confirms_subscriber() {
     assert_eq!(saved.status, "confirmed");
 }
 
+#[tokio::test]
+async fn subscription_tokens_are_not_stored_in_clear() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let (_, token) = confirmation_links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT subscription_token_hash FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved token");
+    assert_ne!(saved.subscription_token_hash, token);
+    assert_eq!(saved.subscription_token_hash.len(), 64);
+}
+
 #[tokio::test]
 async fn using_nonexistent_token_returns_401() {
     let app = spawn_app().await;
@@ -112,6 +140,76 @@ async fn using_invalidated_token_returns_401() {
     assert_eq!(saved.status, "pending_confirmation");
 }
 
+#[tokio::test]
+async fn expired_token_returns_410_and_offers_to_resend() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Part 1 - The link no longer works once the token is old enough
+    expire_subscription_tokens(&app).await;
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<form action="/subscriptions/confirm/resend" method="post">"#));
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "pending_confirmation");
+
+    // Part 2 - Ask for a new link
+    let response = app
+        .post_resend_confirmation("ursula_le_guin@gmail.com")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Part 3 - The new link confirms the subscription
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn resending_to_an_unknown_address_sends_nothing() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_resend_confirmation("ursula_le_guin@gmail.com")
+        .await;
+
+    // Same response as for a pending subscriber
+    assert_eq!(response.status().as_u16(), 200);
+}
+
 #[tokio::test]
 async fn malformed_token_returns_400() {
     let app = spawn_app().await;
@@ -137,3 +235,10 @@ async fn malformed_token_returns_400() {
         );
     }
 }
+
+async fn expire_subscription_tokens(app: &TestApp) {
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to backdate subscription tokens");
+}
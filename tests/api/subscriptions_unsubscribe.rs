@@ -0,0 +1,29 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn unsubscribing_without_a_token_is_rejected_with_400() {
+    let app = spawn_app().await;
+    let url = format!("{}/subscriptions/unsubscribe", app.address);
+
+    let response = app.api_client.get(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app.api_client.post(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unsubscribing_with_an_unknown_token_is_rejected_with_401() {
+    let app = spawn_app().await;
+    let url = format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        app.address,
+        "a".repeat(25)
+    );
+
+    let response = app.api_client.get(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.api_client.post(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
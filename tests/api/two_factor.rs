@@ -0,0 +1,226 @@
+use crate::helpers::{assert_is_redirected_to, spawn_app, TestApp};
+
+use {chrono::Utc, zero2prod::authentication::TotpSecret};
+
+/// The text of every `<code>` element in a page.
+fn code_elements(html: &str) -> Vec<String> {
+    html.split("<code>")
+        .skip(1)
+        .map(|s| s.split("</code>").next().unwrap().to_owned())
+        .collect()
+}
+
+/// A valid code that has not been used yet, enrolment uses up the one for the current step.
+fn next_code(secret: &TotpSecret) -> String {
+    secret.code(TotpSecret::step_at(Utc::now().timestamp()) + 1)
+}
+
+fn current_code(secret: &TotpSecret) -> String {
+    secret.code(TotpSecret::step_at(Utc::now().timestamp()))
+}
+
+/// Enrol the test user, returning their secret and recovery codes, and log out.
+async fn enrol(app: &TestApp) -> (TotpSecret, Vec<String>) {
+    app.login_test_user().await;
+    let html = app.get_two_factor_html().await;
+    let secret = TotpSecret::from_base32(&code_elements(&html)[0]).unwrap();
+
+    let response = app.post_two_factor(&current_code(&secret)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let recovery_codes = code_elements(&response.text().await.unwrap());
+    app.post_logout().await;
+
+    (secret, recovery_codes)
+}
+
+#[tokio::test]
+async fn enrolment_shows_an_otpauth_uri_and_requires_a_valid_code() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    let html = app.get_two_factor_html().await;
+    let secret = code_elements(&html)[0].clone();
+    assert!(html.contains(&format!(
+        "otpauth://totp/zero2prod:{}?secret={}&amp;issuer=zero2prod",
+        app.test_user.username, secret
+    )));
+    // Reloading the page keeps the same secret
+    assert!(app.get_two_factor_html().await.contains(&secret));
+
+    let response = app.post_two_factor("000000").await;
+    assert_is_redirected_to(&response, "/admin/two-factor");
+    let html = app.get_two_factor_html().await;
+    assert!(html.contains("That code is not valid."));
+
+    let secret = TotpSecret::from_base32(&secret).unwrap();
+    let response = app.post_two_factor(&current_code(&secret)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let recovery_codes = code_elements(&response.text().await.unwrap());
+    assert_eq!(recovery_codes.len(), 10);
+
+    let html = app.get_two_factor_html().await;
+    assert!(html.contains("Two-factor authentication is enabled."));
+    assert!(html.contains("You have 10 unused recovery codes left."));
+}
+
+#[tokio::test]
+async fn users_without_a_second_factor_log_in_directly() {
+    let app = spawn_app().await;
+
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn login_asks_for_a_code_once_enrolled() {
+    let app = spawn_app().await;
+    let (secret, _) = enrol(&app).await;
+
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/login/two-factor");
+
+    // The password alone doesn't give access to the admin area
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirected_to(&response, "/login");
+
+    let response = app.post_login_second_factor("000000").await;
+    assert_is_redirected_to(&response, "/login/two-factor");
+
+    let response = app.post_login_second_factor(&next_code(&secret)).await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_second_factor_page_requires_a_password_first() {
+    let app = spawn_app().await;
+    enrol(&app).await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/login/two-factor", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirected_to(&response, "/login");
+
+    let response = app.post_login_second_factor("000000").await;
+    assert_is_redirected_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn authenticator_codes_cannot_be_replayed() {
+    let app = spawn_app().await;
+    let (secret, _) = enrol(&app).await;
+    let code = next_code(&secret);
+
+    app.login_test_user().await;
+    let response = app.post_login_second_factor(&code).await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    app.login_test_user().await;
+    let response = app.post_login_second_factor(&code).await;
+    assert_is_redirected_to(&response, "/login/two-factor");
+    let html = app
+        .api_client
+        .get(format!("{}/login/two-factor", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<p><i>That code is not valid.</i></p>"));
+}
+
+#[tokio::test]
+async fn recovery_codes_can_be_used_once() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enrol(&app).await;
+
+    app.login_test_user().await;
+    let response = app
+        .post_login_second_factor(&recovery_codes[3].to_uppercase())
+        .await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+    let html = app.get_two_factor_html().await;
+    assert!(html.contains("You have 9 unused recovery codes left."));
+    app.post_logout().await;
+
+    app.login_test_user().await;
+    let response = app.post_login_second_factor(&recovery_codes[3]).await;
+    assert_is_redirected_to(&response, "/login/two-factor");
+}
+
+#[tokio::test]
+async fn recovery_codes_are_stored_hashed() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enrol(&app).await;
+
+    let stored = sqlx::query!("SELECT code_hash FROM recovery_codes")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.len(), recovery_codes.len());
+    for row in stored {
+        assert!(row.code_hash.starts_with("$argon2id$"));
+        assert!(recovery_codes
+            .iter()
+            .all(|code| !row.code_hash.contains(&code.replace('-', ""))));
+    }
+}
+
+#[tokio::test]
+async fn wrong_codes_count_towards_the_lockout() {
+    let app = spawn_app().await;
+    enrol(&app).await;
+
+    app.login_test_user().await;
+    for _ in 0..5 {
+        let response = app.post_login_second_factor("000000").await;
+        assert_is_redirected_to(&response, "/login/two-factor");
+    }
+
+    let response = app.post_login_second_factor("000000").await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("Too many failed login attempts."));
+}
+
+#[tokio::test]
+async fn entering_the_password_again_does_not_reset_the_lockout() {
+    let app = spawn_app().await;
+    enrol(&app).await;
+
+    app.login_test_user().await;
+    for _ in 0..3 {
+        app.post_login_second_factor("000000").await;
+    }
+    app.login_test_user().await;
+    for _ in 0..2 {
+        let response = app.post_login_second_factor("000000").await;
+        assert_is_redirected_to(&response, "/login/two-factor");
+    }
+
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("Too many failed login attempts."));
+}
+
+#[tokio::test]
+async fn a_pending_second_factor_does_not_keep_an_earlier_login() {
+    let app = spawn_app().await;
+    enrol(&app).await;
+    let other_user = app.create_user("owner").await;
+    let response = app.login(&other_user).await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+
+    let response = app.login_test_user().await;
+    assert_is_redirected_to(&response, "/login/two-factor");
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirected_to(&response, "/login");
+}
@@ -0,0 +1,228 @@
+use crate::helpers::{assert_is_redirected_to, spawn_app};
+
+use {
+    chrono::{Duration, Utc},
+    uuid::Uuid,
+};
+
+#[tokio::test]
+async fn viewers_cannot_publish_newsletters() {
+    let app = spawn_app().await;
+    let viewer = app.create_user("viewer").await;
+    app.login(&viewer).await;
+
+    let response = app.get_newsletter_page().await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter text body",
+            "html_content": "<p>Newsletter HTML body</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    // The dashboard is still available, without the actions they can't perform
+    let html = app.get_admin_dashboard_html().await;
+    assert!(html.contains(&format!("Welcome {}", viewer.username)));
+    assert!(!html.contains(r#"href="/admin/newsletters""#));
+}
+
+#[tokio::test]
+async fn viewers_can_see_issues_but_not_change_them() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let response = app
+        .post_drafts(
+            "",
+            &serde_json::json!({
+                "title": "Draft title",
+                "text_content": "Draft text body",
+                "html_content": "<p>Draft HTML body</p>",
+            }),
+        )
+        .await;
+    let issue_id = response.headers()["Location"]
+        .to_str()
+        .unwrap()
+        .strip_prefix("/admin/newsletters/drafts/")
+        .unwrap()
+        .to_owned();
+    let send_at = (Utc::now() + Duration::days(1)).format("%Y-%m-%dT%H:%M");
+    app.post_drafts(
+        &format!("/{}/schedule", issue_id),
+        &serde_json::json!({ "send_at": send_at.to_string() }),
+    )
+    .await;
+    app.post_logout().await;
+
+    let viewer = app.create_user("viewer").await;
+    app.login(&viewer).await;
+
+    let html = app.get_admin_dashboard_html().await;
+    assert!(html.contains(r#"href="/admin/newsletters/history""#));
+    let html = app.get_newsletter_history_html().await;
+    assert!(html.contains("Draft title"));
+    assert!(!html.contains("Write a new issue"));
+    let html = app.get_scheduled_issues_html().await;
+    assert!(html.contains("Draft title"));
+    assert!(!html.contains("Reschedule"));
+    let html = app.get_failed_deliveries_html().await;
+    assert!(html.contains("There are no failed deliveries."));
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/newsletters/issues/{}/preview",
+            &app.address, issue_id
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_scheduled(&format!("/{}/cancel", issue_id), &serde_json::json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app
+        .post_requeue_failed_delivery(&serde_json::json!({
+            "newsletter_issue_id": &issue_id,
+            "subscriber_email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.get_draft(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn editors_can_publish_but_cannot_manage_collaborators() {
+    let app = spawn_app().await;
+    let editor = app.create_user("editor").await;
+    app.login(&editor).await;
+
+    let response = app.get_newsletter_page().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.get_users().await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app
+        .post_users(
+            "",
+            &serde_json::json!({
+                "email": "intruder@example.com",
+                "role": "owner",
+            }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn owners_can_change_roles() {
+    let app = spawn_app().await;
+    let editor = app.create_user("editor").await;
+    app.login_test_user().await;
+
+    let response = app
+        .post_users(
+            &format!("/{}/role", editor.user_id),
+            &serde_json::json!({ "role": "viewer" }),
+        )
+        .await;
+    assert_is_redirected_to(&response, "/admin/users");
+    let html = app.get_users_html().await;
+    assert!(html.contains("<p><i>The role has been changed.</i></p>"));
+
+    // The change applies to existing sessions straight away
+    app.login(&editor).await;
+    let response = app.get_newsletter_page().await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn deactivated_collaborators_are_logged_out_and_cannot_log_in() {
+    let app = spawn_app().await;
+    let editor = app.create_user("editor").await;
+    app.login_test_user().await;
+
+    let response = app
+        .post_users(
+            &format!("/{}/deactivate", editor.user_id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_is_redirected_to(&response, "/admin/users");
+    let html = app.get_users_html().await;
+    assert!(html.contains("<p><i>The collaborator has been deactivated.</i></p>"));
+
+    let response = app.login(&editor).await;
+    assert_is_redirected_to(&response, "/login");
+
+    // Part 2 - Reactivating them restores access
+    app.login_test_user().await;
+    app.post_users(
+        &format!("/{}/reactivate", editor.user_id),
+        &serde_json::json!({}),
+    )
+    .await;
+    let response = app.login(&editor).await;
+    assert_is_redirected_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn deactivation_ends_existing_sessions() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    sqlx::query!(
+        "UPDATE users SET is_active = false WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirected_to(&response, "/login");
+    let html = app.get_login_html().await;
+    assert!(html.contains("<p><i>Your account has been deactivated.</i></p>"));
+}
+
+#[tokio::test]
+async fn the_last_active_owner_cannot_be_demoted_or_deactivated() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Leave the test user as the only active owner
+    sqlx::query!(
+        "UPDATE users SET is_active = false WHERE user_id != $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app
+        .post_users(
+            &format!("/{}/role", app.test_user.user_id),
+            &serde_json::json!({ "role": "editor" }),
+        )
+        .await;
+    assert_is_redirected_to(&response, "/admin/users");
+    let html = app.get_users_html().await;
+    assert!(html.contains("<p><i>There must always be at least one active owner.</i></p>"));
+
+    let response = app
+        .post_users(
+            &format!("/{}/deactivate", app.test_user.user_id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_is_redirected_to(&response, "/admin/users");
+    let html = app.get_users_html().await;
+    assert!(html.contains("<p><i>There must always be at least one active owner.</i></p>"));
+}